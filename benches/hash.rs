@@ -2,13 +2,14 @@ use blstrs::Scalar as Fr;
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use ff::{Field, PrimeField};
 use generic_array::typenum;
-use neptune::poseidon::{HashMode, PoseidonConstants};
+use generic_array::{sequence::GenericSequence, GenericArray};
+use neptune::poseidon::{HashMode, PoseidonConstants, SimplePoseidonBatchHasher};
 use neptune::*;
 use pasta_curves::{Fp, Fq as Fv};
 use rand::rngs::OsRng;
 use rand::seq::SliceRandom;
 use sha2::{Digest, Sha256, Sha512};
-use typenum::{U11, U2, U4, U8};
+use typenum::{U11, U2, U24, U36, U4, U8};
 
 fn bench_hash_bls<A>(c: &mut Criterion)
 where
@@ -139,7 +140,7 @@ criterion_group! {
     config = Criterion::default();
 
     targets = bench_hash_bls::<U2>, bench_hash_bls::<U4>,
-    bench_hash_bls::<U8>, bench_hash_bls::<U11>
+    bench_hash_bls::<U8>, bench_hash_bls::<U11>, bench_hash_bls::<U24>, bench_hash_bls::<U36>
 }
 
 fn bench_bls_and_pasta_fields_for_arity<A>(c: &mut Criterion)
@@ -179,4 +180,32 @@ criterion_group!(
     bench_bls_and_pasta_fields_for_arity::<U11>,
 );
 
-criterion_main!(hash_bls, bench_all_fields_for_common_arities);
+fn bench_batch_hash<A>(c: &mut Criterion)
+where
+    A: Arity<Fr>,
+{
+    let preimages: Vec<GenericArray<Fr, A>> = (0..1 << 14)
+        .map(|i| GenericArray::generate(|j| Fr::from((i * A::to_usize() + j) as u64)))
+        .collect();
+    let mut batcher = SimplePoseidonBatchHasher::<Fr, A>::new(1 << 12);
+
+    let mut group = c.benchmark_group(format!("batch-hash-{}", A::to_usize()));
+
+    // `hash_iter` always hashes sequentially, whereas `hash` uses multiple threads with the `parallel` feature.
+    group.bench_function("sequential", |b| {
+        b.iter(|| batcher.hash_iter(preimages.iter().cloned()).unwrap())
+    });
+    group.bench_function("hash", |b| b.iter(|| batcher.hash(&preimages).unwrap()));
+
+    group.finish();
+}
+
+criterion_group!(
+    name = batch_hash;
+
+    config = Criterion::default().sample_size(10);
+
+    targets = bench_batch_hash::<U2>, bench_batch_hash::<U8>,
+);
+
+criterion_main!(hash_bls, bench_all_fields_for_common_arities, batch_hash);
@@ -0,0 +1,22 @@
+use blstrs::Scalar as Fr;
+use criterion::{criterion_group, criterion_main, Criterion};
+use neptune::bench::bench_hash_arities;
+use pasta_curves::Fp;
+
+fn bench_bls(c: &mut Criterion) {
+    bench_hash_arities::<Fr>(c, "bls");
+}
+
+fn bench_pallas(c: &mut Criterion) {
+    bench_hash_arities::<Fp>(c, "pallas");
+}
+
+criterion_group!(
+    name = hash_arity;
+
+    config = Criterion::default().sample_size(20);
+
+    targets = bench_bls, bench_pallas,
+);
+
+criterion_main!(hash_arity);
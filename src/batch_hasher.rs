@@ -6,11 +6,13 @@ use crate::error::{ClError, Error};
 use crate::poseidon::SimplePoseidonBatchHasher;
 #[cfg(any(feature = "cuda", feature = "opencl"))]
 use crate::proteus::gpu::ClBatchHasher;
-use crate::{Arity, BatchHasher, NeptuneField, Strength, DEFAULT_STRENGTH};
+use crate::{Arity, BatchHasher, HashTiming, NeptuneField, Strength, DEFAULT_STRENGTH};
 use ec_gpu_gen::rust_gpu_tools::Device;
 use ff::PrimeField;
 use generic_array::GenericArray;
 
+pub use crate::BatcherBackend;
+
 #[allow(clippy::large_enum_variant)]
 pub enum Batcher<F, A>
 where
@@ -40,7 +42,34 @@ where
         ))
     }
 
-    /// Create a new GPU batcher for an arbitrarily picked device.
+    /// Returns the backend this batcher hashes on.
+    pub fn backend(&self) -> BatcherBackend {
+        match self {
+            Batcher::Cpu(_) => BatcherBackend::Cpu,
+            #[cfg(any(feature = "cuda", feature = "opencl"))]
+            Batcher::OpenCl(batcher) => batcher.backend(),
+        }
+    }
+
+    fn strength(&self) -> Strength {
+        match self {
+            Batcher::Cpu(batcher) => batcher.strength(),
+            #[cfg(any(feature = "cuda", feature = "opencl"))]
+            Batcher::OpenCl(batcher) => batcher.strength(),
+        }
+    }
+
+    /// Hashes `sample` both with this batcher and with a freshly constructed CPU batcher of the same strength, and
+    /// returns whether the results agree. This is a cheap sanity check to run before trusting a GPU device.
+    pub fn verify_against_cpu(&mut self, sample: &[GenericArray<F, A>]) -> Result<bool, Error> {
+        let mut cpu =
+            SimplePoseidonBatchHasher::<F, A>::new_with_strength(self.strength(), sample.len());
+        Ok(self.hash(sample)? == cpu.hash(sample)?)
+    }
+
+    /// Create a new GPU batcher for an arbitrarily picked device. If `max_batch_size` is 0, the batch size
+    /// recommended for the device's memory by [`ClBatchHasher::recommended_batch_size`] is used, as it is by every
+    /// GPU constructor.
     #[cfg(any(feature = "cuda", feature = "opencl"))]
     pub fn pick_gpu(max_batch_size: usize) -> Result<Self, Error> {
         let device = *Device::all()
@@ -49,6 +78,26 @@ where
         Self::new(device, max_batch_size)
     }
 
+    /// Create a new GPU batcher for an arbitrarily picked device, or, if no GPU batcher can be initialized, log a
+    /// warning and fall back to a CPU batcher.
+    #[cfg(any(feature = "cuda", feature = "opencl"))]
+    pub fn pick_gpu_or_cpu(max_batch_size: usize) -> Self {
+        Self::pick_gpu(max_batch_size).unwrap_or_else(|err| {
+            log::warn!("failed to initialize GPU batcher, falling back to CPU: {err}");
+            Self::new_cpu(max_batch_size)
+        })
+    }
+
+    /// Create a batcher hashing on all of `devices`, with at most `max_batch_size` preimages per device and dispatch.
+    /// Returns [`ClError::DeviceNotFound`] if `devices` is empty.
+    #[cfg(any(feature = "cuda", feature = "opencl"))]
+    pub fn new_multi(
+        devices: &[&Device],
+        max_batch_size: usize,
+    ) -> Result<MultiBatcher<F, A>, Error> {
+        MultiBatcher::with_strength(devices, DEFAULT_STRENGTH, max_batch_size)
+    }
+
     #[cfg(any(feature = "cuda", feature = "opencl"))]
     /// Create a new GPU batcher for a certain device.
     pub fn new(device: &Device, max_batch_size: usize) -> Result<Self, Error> {
@@ -75,11 +124,56 @@ where
     F: NeptuneField,
     A: Arity<F>,
 {
+    /// Inputs larger than `max_batch_size` are transparently split into sub-batches, which are dispatched to the
+    /// device sequentially.
     fn hash(&mut self, preimages: &[GenericArray<F, A>]) -> Result<Vec<F>, Error> {
         match self {
             Batcher::Cpu(batcher) => batcher.hash(preimages),
             #[cfg(any(feature = "cuda", feature = "opencl"))]
-            Batcher::OpenCl(batcher) => batcher.hash(preimages),
+            Batcher::OpenCl(batcher) => {
+                let max_batch_size = batcher.max_batch_size();
+                if preimages.len() <= max_batch_size {
+                    return batcher.hash(preimages);
+                }
+
+                let mut hashes = Vec::with_capacity(preimages.len());
+                let mut timing = HashTiming::default();
+                for chunk in preimages.chunks(max_batch_size) {
+                    hashes.extend(batcher.hash(chunk)?);
+                    timing += batcher.last_hash_timing().unwrap_or_default();
+                }
+                batcher.last_timing = Some(timing);
+                Ok(hashes)
+            }
+        }
+    }
+
+    fn hash_into(&mut self, preimages: &[GenericArray<F, A>], out: &mut [F]) -> Result<(), Error> {
+        match self {
+            Batcher::Cpu(batcher) => batcher.hash_into(preimages, out),
+            #[cfg(any(feature = "cuda", feature = "opencl"))]
+            Batcher::OpenCl(_) => {
+                if out.len() < preimages.len() {
+                    return Err(Error::IndexOutOfBounds);
+                }
+                out[..preimages.len()].copy_from_slice(&self.hash(preimages)?);
+                Ok(())
+            }
+        }
+    }
+
+    fn hash_iter<I>(&mut self, preimages: I) -> Result<Vec<F>, Error>
+    where
+        I: IntoIterator<Item = GenericArray<F, A>>,
+    {
+        match self {
+            Batcher::Cpu(batcher) => batcher.hash_iter(preimages),
+            #[cfg(any(feature = "cuda", feature = "opencl"))]
+            Batcher::OpenCl(_) => {
+                // Go through `hash`, so that oversized inputs are split into sub-batches.
+                let preimages: Vec<_> = preimages.into_iter().collect();
+                self.hash(&preimages)
+            }
         }
     }
 
@@ -90,4 +184,198 @@ where
             Batcher::OpenCl(batcher) => batcher.max_batch_size(),
         }
     }
+
+    fn last_hash_timing(&self) -> Option<HashTiming> {
+        match self {
+            Batcher::Cpu(batcher) => batcher.last_hash_timing(),
+            #[cfg(any(feature = "cuda", feature = "opencl"))]
+            Batcher::OpenCl(batcher) => batcher.last_hash_timing(),
+        }
+    }
+}
+
+/// A GPU batcher spreading its input across several devices, as created by [`Batcher::new_multi`].
+///
+/// The input is split into sub-batches of at most `max_batch_size` preimages, which are assigned to the devices in
+/// round-robin order; the hashes are returned in the order of the preimages. Dispatches happen on the calling thread,
+/// one sub-batch at a time.
+#[cfg(any(feature = "cuda", feature = "opencl"))]
+pub struct MultiBatcher<F, A>
+where
+    F: NeptuneField,
+    A: Arity<F>,
+{
+    hashers: Vec<ClBatchHasher<F, A>>,
+    max_batch_size: usize,
+    last_timing: Option<HashTiming>,
+}
+
+#[cfg(any(feature = "cuda", feature = "opencl"))]
+impl<F, A> MultiBatcher<F, A>
+where
+    F: NeptuneField,
+    A: Arity<F>,
+{
+    /// Like [`Batcher::new_multi`], with a specified strength.
+    pub fn with_strength(
+        devices: &[&Device],
+        strength: Strength,
+        max_batch_size: usize,
+    ) -> Result<Self, Error> {
+        if devices.is_empty() {
+            return Err(Error::ClError(ClError::DeviceNotFound));
+        }
+        let hashers: Vec<ClBatchHasher<F, A>> = devices
+            .iter()
+            .map(|device| ClBatchHasher::new_with_strength(device, strength, max_batch_size))
+            .collect::<Result<_, _>>()?;
+        // A `max_batch_size` of 0 is resolved by each hasher for its own device; use a size every device can take.
+        let max_batch_size = hashers
+            .iter()
+            .map(BatchHasher::max_batch_size)
+            .min()
+            .unwrap_or(max_batch_size);
+
+        Ok(Self {
+            hashers,
+            max_batch_size,
+            last_timing: None,
+        })
+    }
+
+    /// Returns the backends of the devices, in the order sub-batches are assigned to them.
+    pub fn backends(&self) -> Vec<BatcherBackend> {
+        self.hashers.iter().map(ClBatchHasher::backend).collect()
+    }
+}
+
+#[cfg(any(feature = "cuda", feature = "opencl"))]
+impl<F, A> BatchHasher<F, A> for MultiBatcher<F, A>
+where
+    F: NeptuneField,
+    A: Arity<F>,
+{
+    fn hash(&mut self, preimages: &[GenericArray<F, A>]) -> Result<Vec<F>, Error> {
+        let device_count = self.hashers.len();
+        let mut hashes = Vec::with_capacity(preimages.len());
+        let mut timing = HashTiming::default();
+        for (i, chunk) in preimages.chunks(self.max_batch_size).enumerate() {
+            let hasher = &mut self.hashers[i % device_count];
+            hashes.extend(hasher.hash(chunk)?);
+            timing += hasher.last_hash_timing().unwrap_or_default();
+        }
+        self.last_timing = Some(timing);
+        Ok(hashes)
+    }
+
+    /// The timings of all devices' dispatches are summed, since they happen one after the other.
+    fn last_hash_timing(&self) -> Option<HashTiming> {
+        self.last_timing
+    }
+
+    /// Returns the number of preimages hashed by a single round of dispatches to all devices.
+    fn max_batch_size(&self) -> usize {
+        self.max_batch_size * self.hashers.len()
+    }
+}
+
+#[cfg(test)]
+#[cfg(all(feature = "opencl", not(target_os = "macos")))]
+mod tests {
+    use super::*;
+    use blstrs::Scalar as Fr;
+    use generic_array::{sequence::GenericSequence, typenum::U2};
+
+    #[test]
+    fn test_oversized_batch() {
+        let max_batch_size = 4;
+        let preimages = (0..10u64)
+            .map(|i| GenericArray::<Fr, U2>::generate(|j| Fr::from(i * 2 + j as u64)))
+            .collect::<Vec<_>>();
+
+        let mut gpu_batcher = Batcher::<Fr, U2>::pick_gpu(max_batch_size).unwrap();
+        let mut cpu_batcher = Batcher::<Fr, U2>::new_cpu(max_batch_size);
+
+        assert_eq!(
+            cpu_batcher.hash(&preimages).unwrap(),
+            gpu_batcher.hash(&preimages).unwrap()
+        );
+        assert_eq!(
+            cpu_batcher.hash(&preimages).unwrap(),
+            gpu_batcher.hash_iter(preimages.iter().cloned()).unwrap()
+        );
+        assert_eq!(None, cpu_batcher.last_hash_timing());
+        assert!(gpu_batcher.last_hash_timing().unwrap().total() > Default::default());
+    }
+
+    #[test]
+    fn test_verify_against_cpu() {
+        let sample = (0..10u64)
+            .map(|i| GenericArray::<Fr, U2>::generate(|j| Fr::from(i * 2 + j as u64)))
+            .collect::<Vec<_>>();
+
+        for strength in [Strength::Standard, Strength::Strengthened] {
+            let mut cpu_batcher = Batcher::<Fr, U2>::with_strength_cpu(strength, 4);
+            assert!(cpu_batcher.verify_against_cpu(&sample).unwrap());
+        }
+
+        let mut gpu_batcher = Batcher::<Fr, U2>::pick_gpu(4).unwrap();
+        assert!(gpu_batcher.verify_against_cpu(&sample).unwrap());
+    }
+
+    #[test]
+    fn test_pick_gpu_or_cpu() {
+        let backend = Batcher::<Fr, U2>::pick_gpu_or_cpu(4).backend();
+        assert!(matches!(
+            backend,
+            BatcherBackend::Cuda(_) | BatcherBackend::OpenCl(_)
+        ));
+    }
+
+    #[test]
+    fn test_multi_batcher() {
+        let preimages = (0..10u64)
+            .map(|i| GenericArray::<Fr, U2>::generate(|j| Fr::from(i * 2 + j as u64)))
+            .collect::<Vec<_>>();
+        let mut cpu_batcher = Batcher::<Fr, U2>::new_cpu(preimages.len());
+
+        // Assigning the same device twice exercises the round-robin split without requiring several GPUs.
+        let device = *Device::all().first().unwrap();
+        let mut multi = Batcher::<Fr, U2>::new_multi(&[device, device], 3).unwrap();
+        assert_eq!(6, multi.max_batch_size());
+        assert_eq!(2, multi.backends().len());
+        assert_eq!(
+            cpu_batcher.hash(&preimages).unwrap(),
+            multi.hash(&preimages).unwrap()
+        );
+
+        assert!(matches!(
+            Batcher::<Fr, U2>::new_multi(&[], 3),
+            Err(Error::ClError(ClError::DeviceNotFound))
+        ));
+
+        // A size of 0 resolves to the device's recommended size, as for a single-device batcher.
+        let mut resolved = Batcher::<Fr, U2>::new_multi(&[device], 0).unwrap();
+        assert_eq!(
+            Batcher::<Fr, U2>::new(device, 0).unwrap().max_batch_size(),
+            resolved.max_batch_size()
+        );
+        assert_ne!(0, resolved.max_batch_size());
+        assert_eq!(
+            cpu_batcher.hash(&preimages).unwrap(),
+            resolved.hash(&preimages).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_backend() {
+        assert_eq!(BatcherBackend::Cpu, Batcher::<Fr, U2>::new_cpu(1).backend());
+
+        let device = *Device::all().first().unwrap();
+        let backend = Batcher::<Fr, U2>::new(device, 1).unwrap().backend();
+        assert!(matches!(
+            backend,
+            BatcherBackend::Cuda(name) | BatcherBackend::OpenCl(name) if name == device.name()
+        ));
+    }
 }
@@ -0,0 +1,57 @@
+//! Criterion benchmarks of the single-hash and batch-hash paths, generic over the field, so that they can be run
+//! against fields defined outside this crate. The `hash_arity` bench of this crate runs them for BLS12-381 and Pallas.
+use crate::poseidon::{Poseidon, PoseidonConstants, SimplePoseidonBatchHasher};
+use crate::{Arity, BatchHasher, Strength};
+use criterion::{BenchmarkId, Criterion, Throughput};
+use ff::PrimeField;
+use generic_array::typenum::{U16, U2, U4, U8};
+use generic_array::{sequence::GenericSequence, GenericArray};
+
+/// The number of preimages hashed per iteration of the batch benchmarks.
+pub const BATCH_SIZE: usize = 1 << 10;
+
+/// Benchmark [`Poseidon::hash`] and [`SimplePoseidonBatchHasher`] for arity `A`, under both standard and strengthened
+/// constants. Results are reported in the group `hash-arity-{field_name}`, as `{path}-{strength}/{arity}`, so that
+/// Criterion plots the arities of a path side by side.
+pub fn bench_hash_arity<F, A>(c: &mut Criterion, field_name: &str)
+where
+    F: PrimeField,
+    A: Arity<F>,
+{
+    let arity = A::to_usize();
+    let mut group = c.benchmark_group(format!("hash-arity-{}", field_name));
+
+    for (strength, strength_name) in [
+        (Strength::Standard, "standard"),
+        (Strength::Strengthened, "strengthened"),
+    ] {
+        let constants = PoseidonConstants::<F, A>::new_with_strength(strength);
+        let preimage: Vec<F> = (0..arity as u64).map(F::from).collect();
+        group.throughput(Throughput::Elements(1));
+        group.bench_function(
+            BenchmarkId::new(format!("hash-{}", strength_name), arity),
+            |b| b.iter(|| Poseidon::new_with_preimage(&preimage, &constants).hash()),
+        );
+
+        let preimages: Vec<GenericArray<F, A>> = (0..BATCH_SIZE)
+            .map(|i| GenericArray::generate(|j| F::from((i * arity + j) as u64)))
+            .collect();
+        let mut batcher =
+            SimplePoseidonBatchHasher::<F, A>::new_with_strength(strength, BATCH_SIZE);
+        group.throughput(Throughput::Elements(BATCH_SIZE as u64));
+        group.bench_function(
+            BenchmarkId::new(format!("batch-{}", strength_name), arity),
+            |b| b.iter(|| batcher.hash(&preimages).unwrap()),
+        );
+    }
+
+    group.finish();
+}
+
+/// Run [`bench_hash_arity`] for arities 2, 4, 8 and 16.
+pub fn bench_hash_arities<F: PrimeField>(c: &mut Criterion, field_name: &str) {
+    bench_hash_arity::<F, U2>(c, field_name);
+    bench_hash_arity::<F, U4>(c, field_name);
+    bench_hash_arity::<F, U8>(c, field_name);
+    bench_hash_arity::<F, U16>(c, field_name);
+}
@@ -148,6 +148,10 @@ where
 {
     /// Create a new Poseidon hasher for `preimage`.
     fn new(elements: Vec<Elt<Scalar>>, constants: &'a PoseidonConstants<Scalar, A>) -> Self {
+        assert_eq!(
+            5, constants.alpha,
+            "the legacy circuit only supports the quintic S-box"
+        );
         let width = constants.width();
 
         PoseidonCircuit {
@@ -359,6 +363,10 @@ where
 }
 
 /// Create legacy circuit for Poseidon hash. If possible, prefer the equivalent 'optimal' alternatives.
+///
+/// # Panics
+///
+/// Panics unless `constants` use the quintic S-box.
 pub fn poseidon_hash<CS, Scalar, A>(
     cs: CS,
     preimage: Vec<AllocatedNum<Scalar>>,
@@ -9,7 +9,7 @@ use crate::poseidon::{Arity, PoseidonConstants};
 use bellpepper_core::boolean::Boolean;
 use bellpepper_core::num::{self, AllocatedNum};
 use bellpepper_core::test_cs::TestConstraintSystem;
-use bellpepper_core::{ConstraintSystem, LinearCombination, SynthesisError};
+use bellpepper_core::{ConstraintSystem, LinearCombination, SynthesisError, Variable};
 use ff::{Field, PrimeField};
 use std::marker::PhantomData;
 
@@ -27,6 +27,34 @@ impl<Scalar: PrimeField> From<AllocatedNum<Scalar>> for Elt<Scalar> {
     }
 }
 
+impl<Scalar: PrimeField> From<num::Num<Scalar>> for Elt<Scalar> {
+    fn from(num: num::Num<Scalar>) -> Self {
+        Self::Num(num)
+    }
+}
+
+impl<Scalar: PrimeField> From<Elt<Scalar>> for num::Num<Scalar> {
+    fn from(elt: Elt<Scalar>) -> Self {
+        match elt {
+            Elt::Num(num) => num,
+            Elt::Allocated(a) => a.into(),
+        }
+    }
+}
+
+/// Succeeds only for `Elt::Allocated`, since allocating an `Elt::Num` needs a constraint system: otherwise the `Elt`
+/// is handed back, to be allocated with [`Elt::ensure_allocated`].
+impl<Scalar: PrimeField> TryFrom<Elt<Scalar>> for AllocatedNum<Scalar> {
+    type Error = Elt<Scalar>;
+
+    fn try_from(elt: Elt<Scalar>) -> Result<Self, Self::Error> {
+        match elt {
+            Elt::Allocated(a) => Ok(a),
+            elt => Err(elt),
+        }
+    }
+}
+
 impl<Scalar: PrimeField> Elt<Scalar> {
     pub const fn is_allocated(&self) -> bool {
         matches!(self, Self::Allocated(_))
@@ -36,6 +64,15 @@ impl<Scalar: PrimeField> Elt<Scalar> {
         matches!(self, Self::Num(_))
     }
 
+    /// Returns the variable of an `Elt::Allocated`, or `None` for an `Elt::Num`, whose value is a linear combination
+    /// available through `lc`. Use `ensure_allocated` to obtain a variable for the latter, at the cost of one constraint.
+    pub fn variable(&self) -> Option<Variable> {
+        match self {
+            Self::Allocated(v) => Some(v.get_variable()),
+            Self::Num(_) => None,
+        }
+    }
+
     pub fn num_from_fr<CS: ConstraintSystem<Scalar>>(fr: Scalar) -> Self {
         let num = num::Num::<Scalar>::zero();
         Self::Num(num.add_bool_with_coeff(CS::one(), &Boolean::Constant(true), fr))
@@ -160,9 +197,20 @@ where
     Scalar: PrimeField,
     A: Arity<Scalar>,
 {
-    /// Create a new Poseidon hasher for `preimage`.
+    /// Create a new Poseidon hasher for `elements`: the domain tag followed by the (padded) preimage.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless there are exactly `constants.width()` elements.
     pub fn new(elements: Vec<Elt<Scalar>>, constants: &'a PoseidonConstants<Scalar, A>) -> Self {
         let width = constants.width();
+        assert_eq!(
+            width,
+            elements.len(),
+            "Invalid state size: expected the domain tag and {} preimage elements, got {} elements in total",
+            constants.arity(),
+            elements.len()
+        );
 
         PoseidonCircuit2 {
             constants_offset: 0,
@@ -175,10 +223,22 @@ where
         }
     }
 
+    /// Returns the number of constraints synthesized by a single permutation (`hash`) with `constants`.
+    pub fn constraints_per_permutation(constants: &PoseidonConstants<Scalar, A>) -> usize {
+        permutation_constraints(
+            constants.width(),
+            constants.full_rounds,
+            constants.partial_rounds,
+            constants.alpha,
+        )
+    }
+
+    /// Create a new Poseidon hasher with no preimage. The capacity element is initialized to the domain tag of the
+    /// [`HashType`] in `constants`, matching [`crate::poseidon::Poseidon::new`].
     pub fn new_empty<CS: ConstraintSystem<Scalar>>(
         constants: &'a PoseidonConstants<Scalar, A>,
     ) -> Self {
-        let elements = Self::initial_elements::<CS>();
+        let elements = Self::initial_elements::<CS>(constants);
         Self::new(elements, constants)
     }
 
@@ -277,7 +337,8 @@ where
         };
         constants_offset += post_round_keys.len();
 
-        // Apply the quintic S-Box to all elements
+        // Apply the S-Box to all elements
+        let alpha = self.constants.alpha;
         for i in 0..self.elements.len() {
             let pre_round_key = if first_round {
                 let rk = pre_round_keys[i];
@@ -295,17 +356,20 @@ where
 
             if first_round {
                 {
-                    self.elements[i] = quintic_s_box_pre_add(
+                    self.elements[i] = s_box(
                         cs.namespace(|| format!("quintic s-box {i}")),
                         &self.elements[i],
+                        alpha,
                         pre_round_key,
                         post_round_key,
                     )?;
                 }
             } else {
-                self.elements[i] = quintic_s_box(
+                self.elements[i] = s_box(
                     cs.namespace(|| format!("quintic s-box {i}")),
                     &self.elements[i],
+                    alpha,
+                    None,
                     post_round_key,
                 )?;
             }
@@ -323,10 +387,12 @@ where
     ) -> Result<(), SynthesisError> {
         let round_key = self.constants.compressed_round_constants[self.constants_offset];
         self.constants_offset += 1;
-        // Apply the quintic S-Box to the first element.
-        self.elements[0] = quintic_s_box(
+        // Apply the S-Box to the first element.
+        self.elements[0] = s_box(
             cs.namespace(|| "solitary quintic s-box"),
             &self.elements[0],
+            self.constants.alpha,
+            None,
             Some(round_key),
         )?;
 
@@ -413,14 +479,16 @@ where
         Ok(())
     }
 
-    fn initial_elements<CS: ConstraintSystem<Scalar>>() -> Vec<Elt<Scalar>> {
-        std::iter::repeat(Elt::num_from_fr::<CS>(Scalar::ZERO))
-            .take(A::to_usize() + 1)
+    fn initial_elements<CS: ConstraintSystem<Scalar>>(
+        constants: &PoseidonConstants<Scalar, A>,
+    ) -> Vec<Elt<Scalar>> {
+        std::iter::once(Elt::num_from_fr::<CS>(constants.domain_tag))
+            .chain(std::iter::repeat(Elt::num_from_fr::<CS>(Scalar::ZERO)).take(A::to_usize()))
             .collect()
     }
     pub fn reset<CS: ConstraintSystem<Scalar>>(&mut self) {
         self.reset_offsets();
-        self.elements = Self::initial_elements::<CS>();
+        self.elements = Self::initial_elements::<CS>(self.constants);
     }
 
     pub fn reset_offsets(&mut self) {
@@ -435,7 +503,27 @@ where
     }
 }
 
+/// Panics unless `len` is the preimage length expected by `constants`: the constant length of a
+/// [`HashType::ConstantLength`] hash, or the arity otherwise. This matches [`crate::poseidon::Poseidon::new_with_preimage`].
+fn assert_preimage_len<Scalar: PrimeField, A: Arity<Scalar>>(
+    constants: &PoseidonConstants<Scalar, A>,
+    len: usize,
+) {
+    let expected = match constants.hash_type {
+        HashType::ConstantLength(length) => length,
+        _ => constants.arity(),
+    };
+    assert_eq!(
+        expected, len,
+        "Invalid preimage size: expected {expected} elements, got {len}"
+    );
+}
+
 /// Create circuit for Poseidon hash, returning an allocated `Num` at the cost of one constraint.
+///
+/// # Panics
+///
+/// Panics if the preimage length does not match `constants`, as for [`crate::poseidon::Poseidon::new_with_preimage`].
 pub fn poseidon_hash_allocated<CS, Scalar, A>(
     cs: CS,
     preimage: Vec<AllocatedNum<Scalar>>,
@@ -446,6 +534,7 @@ where
     Scalar: PrimeField,
     A: Arity<Scalar>,
 {
+    assert_preimage_len(constants, preimage.len());
     if cs.is_witness_generator() {
         let mut cs = cs;
         poseidon_hash_allocated_witness(&mut cs, &preimage, constants)
@@ -471,6 +560,10 @@ where
 }
 
 /// Create circuit for Poseidon hash, minimizing constraints by returning an unallocated `Num`.
+///
+/// # Panics
+///
+/// Panics if the preimage length does not match `constants`, as for [`poseidon_hash_allocated`].
 pub fn poseidon_hash_num<CS, Scalar, A>(
     cs: CS,
     preimage: Vec<AllocatedNum<Scalar>>,
@@ -481,6 +574,7 @@ where
     Scalar: PrimeField,
     A: Arity<Scalar>,
 {
+    assert_preimage_len(constants, preimage.len());
     let arity = A::to_usize();
     let tag_element = Elt::num_from_fr::<CS>(constants.domain_tag);
     let mut elements = Vec::with_capacity(arity + 1);
@@ -501,6 +595,59 @@ where
     p.hash_to_num(cs)
 }
 
+/// Compute (l + pre_round_key)^alpha + post_round_key by square-and-multiply, and enforce constraints, as counted by
+/// [`s_box_constraints`]. The quintic S-box uses [`quintic_s_box`] and [`quintic_s_box_pre_add`].
+fn s_box<CS: ConstraintSystem<Scalar>, Scalar: PrimeField>(
+    mut cs: CS,
+    l: &Elt<Scalar>,
+    alpha: u64,
+    pre_round_key: Option<Scalar>,
+    post_round_key: Option<Scalar>,
+) -> Result<Elt<Scalar>, SynthesisError> {
+    if alpha == 5 {
+        return match pre_round_key {
+            Some(_) => quintic_s_box_pre_add(cs, l, pre_round_key, post_round_key),
+            None => quintic_s_box(cs, l, post_round_key),
+        };
+    }
+    assert!(alpha >= 3 && alpha % 2 == 1, "S-box exponent must be odd");
+
+    // Scan the exponent's bits after the leading one. Since it is odd, the last step is a multiplication by the base,
+    // which also adds the post round key.
+    let mut acc: Option<AllocatedNum<Scalar>> = None;
+    for i in (0..63 - alpha.leading_zeros()).rev() {
+        let squared = match (&acc, pre_round_key) {
+            (Some(acc), _) => acc.square(cs.namespace(|| format!("square {i}")))?,
+            (None, Some(pre_round_key)) => square_sum(
+                cs.namespace(|| format!("square {i}")),
+                pre_round_key,
+                l,
+                true,
+            )?,
+            (None, None) => l.square(cs.namespace(|| format!("square {i}")))?,
+        };
+        acc = Some(if (alpha >> i) & 1 == 1 {
+            mul_sum(
+                cs.namespace(|| format!("multiply {i}")),
+                &squared,
+                l,
+                pre_round_key,
+                if i == 0 { post_round_key } else { None },
+                true,
+            )?
+        } else {
+            squared
+        });
+    }
+
+    Ok(Elt::Allocated(acc.expect("exponent is at least 3")))
+}
+
+/// Returns the number of constraints of an [`s_box`] with exponent `alpha`: one per squaring and per multiplication.
+pub(crate) fn s_box_constraints(alpha: u64) -> usize {
+    (63 - alpha.leading_zeros() + alpha.count_ones() - 1) as usize
+}
+
 /// Compute l^5 and enforce constraint. If round_key is supplied, add it to result.
 fn quintic_s_box<CS: ConstraintSystem<Scalar>, Scalar: PrimeField>(
     mut cs: CS,
@@ -658,6 +805,19 @@ fn scalar_product<Scalar: PrimeField, CS: ConstraintSystem<Scalar>>(
         })
 }
 
+/// Returns the number of constraints synthesized by a single permutation of a state of size `width`, with the given
+/// number of rounds.
+pub(crate) fn permutation_constraints(
+    width: usize,
+    full_rounds: usize,
+    partial_rounds: usize,
+    alpha: u64,
+) -> usize {
+    let s_box_cost = s_box_constraints(alpha);
+
+    (width * s_box_cost * full_rounds) + (s_box_cost * partial_rounds)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -691,6 +851,36 @@ mod tests {
         test_poseidon_hash_aux::<typenum::U15>(Strength::Standard, 561, true);
     }
 
+    #[test]
+    #[should_panic(expected = "Invalid preimage size: expected 4 elements, got 3")]
+    fn test_poseidon_hash_wrong_preimage_len() {
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let constants = PoseidonConstants::<Fr, typenum::U4>::new();
+        let preimage = (0..3)
+            .map(|i| AllocatedNum::alloc_infallible(cs.namespace(|| format!("x{i}")), || Fr::ONE))
+            .collect();
+        let _ = poseidon_hash_allocated(&mut cs, preimage, &constants);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid preimage size: expected 2 elements, got 3")]
+    fn test_poseidon_hash_wrong_constant_length() {
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let constants = PoseidonConstants::<Fr, typenum::U4>::new_constant_length(2);
+        let preimage = (0..3)
+            .map(|i| AllocatedNum::alloc_infallible(cs.namespace(|| format!("x{i}")), || Fr::ONE))
+            .collect();
+        let _ = poseidon_hash_num(&mut cs, preimage, &constants);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid state size")]
+    fn test_poseidon_circuit_wrong_width() {
+        let constants = PoseidonConstants::<Fr, typenum::U4>::new();
+        let elements = vec![Elt::num_from_fr::<TestConstraintSystem<Fr>>(Fr::ONE); 4];
+        PoseidonCircuit2::new(elements, &constants);
+    }
+
     fn test_poseidon_hash_aux<A>(
         strength: Strength,
         expected_constraints: usize,
@@ -817,6 +1007,80 @@ mod tests {
         Elt::num_from_fr::<TestConstraintSystem<Fr>>(fr(n))
     }
 
+    #[test]
+    fn test_poseidon_hash_alpha() {
+        use crate::test_field::Fp as F3;
+
+        // The test field defaults to x^3, which costs 2 constraints per S-box, and x^7 costs 4.
+        test_poseidon_hash_alpha_aux(&PoseidonConstants::<F3, typenum::U2>::new(), 2);
+        test_poseidon_hash_alpha_aux(
+            &PoseidonConstants::<Fr, typenum::U4>::new_with_alpha(
+                Strength::Standard,
+                HashType::MerkleTree,
+                Some(7),
+            ),
+            4,
+        );
+    }
+
+    fn test_poseidon_hash_alpha_aux<F: PrimeField, A: Arity<F>>(
+        constants: &PoseidonConstants<F, A>,
+        s_box_cost: usize,
+    ) {
+        let mut cs = TestConstraintSystem::<F>::new();
+        let preimage: Vec<F> = (0..A::to_usize() as u64).map(F::from).collect();
+        let data: Vec<_> = preimage
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                AllocatedNum::alloc_infallible(cs.namespace(|| format!("data {i}")), || *x)
+            })
+            .collect();
+
+        let out = poseidon_hash_allocated(&mut cs, data, constants).unwrap();
+        let expected =
+            Poseidon::new_with_preimage(&preimage, constants).hash_in_mode(HashMode::Correct);
+
+        assert!(cs.is_satisfied());
+        assert_eq!(expected, out.get_value().unwrap());
+        assert_eq!(s_box_cost, s_box_constraints(constants.alpha));
+        assert_eq!(
+            constants.width() * s_box_cost * constants.full_rounds
+                + s_box_cost * constants.partial_rounds
+                + 1,
+            cs.num_constraints()
+        );
+    }
+
+    #[test]
+    fn test_elt_conversions() {
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let three = AllocatedNum::alloc_infallible(cs.namespace(|| "three"), || Fr::from(3));
+
+        let allocated = Elt::from(three.clone());
+        assert!(allocated.is_allocated());
+        assert_eq!(Some(three.get_variable()), allocated.variable());
+        let num = num::Num::from(allocated.clone());
+        assert_eq!(Some(Fr::from(3)), num.get_value());
+        assert_eq!(
+            three.get_variable(),
+            AllocatedNum::try_from(allocated)
+                .ok()
+                .expect("allocated")
+                .get_variable()
+        );
+
+        let num = Elt::from(num::Num::from(three).scale(Fr::from(2)));
+        assert!(num.is_num());
+        assert!(num.variable().is_none());
+        let Err(num) = AllocatedNum::try_from(num) else {
+            panic!("a bare Num cannot be converted without allocating");
+        };
+        let six = num.ensure_allocated(&mut cs, true).unwrap();
+        assert_eq!(Some(Fr::from(6)), six.get_value());
+        assert!(cs.is_satisfied());
+    }
+
     #[test]
     fn test_square_sum() {
         let mut cs = TestConstraintSystem::<Fr>::new();
@@ -1,7 +1,7 @@
 /// The `circuit2_witness` module implements witness-generation for the optimal Poseidon hash circuit.
 use std::ops::{AddAssign, MulAssign};
 
-use crate::circuit2::Elt;
+use crate::circuit2::{Elt, PoseidonCircuit2};
 use crate::hash_type::HashType;
 use crate::matrix::Matrix;
 use crate::mds::SparseMatrix;
@@ -91,10 +91,7 @@ where
     A: Arity<Scalar>,
 {
     fn num_constraints(&self) -> usize {
-        let s_box_cost = 3;
-        let width = A::ConstantsSize::to_usize();
-        (width * s_box_cost * self.constants.full_rounds)
-            + (s_box_cost * self.constants.partial_rounds)
+        PoseidonCircuit2::constraints_per_permutation(self.constants)
     }
 
     fn num_inputs(&self) -> usize {
@@ -114,14 +111,9 @@ where
 
         let c = &constants.compressed_round_constants;
 
+        let alpha = constants.alpha;
         let mut offset = 0;
         let mut aux_index = 0;
-        macro_rules! push_aux {
-            ($val:expr) => {
-                aux[aux_index] = $val;
-                aux_index += 1;
-            };
-        }
 
         assert_eq!(width, elements.len());
 
@@ -131,19 +123,7 @@ where
             for elt in elements.iter_mut() {
                 let x = c[offset];
                 let y = c[offset + width];
-                let mut tmp = *elt;
-
-                tmp.add_assign(x);
-                tmp = tmp.square();
-                push_aux!(tmp); // l2
-
-                tmp = tmp.square();
-                push_aux!(tmp); // l4
-
-                tmp = tmp * (*elt + x) + y;
-                push_aux!(tmp); // l5
-
-                *elt = tmp;
+                *elt = s_box_witness(alpha, *elt, Some(x), Some(y), aux, &mut aux_index);
                 offset += 1;
             }
             offset += width; // post-round keys
@@ -177,18 +157,7 @@ where
                     // s-box
                     for elt in elements.iter_mut() {
                         let y = c[offset];
-                        let mut tmp = *elt;
-
-                        tmp = tmp.square();
-                        push_aux!(tmp); // l2
-
-                        tmp = tmp.square();
-                        push_aux!(tmp); // l4
-
-                        tmp = tmp * *elt + y;
-                        push_aux!(tmp); // l5
-
-                        *elt = tmp;
+                        *elt = s_box_witness(alpha, *elt, None, Some(y), aux, &mut aux_index);
                         offset += 1;
                     }
                 }
@@ -216,18 +185,7 @@ where
                 // FIXME: a little silly to use a loop here.
                 for elt in elements[0..1].iter_mut() {
                     let y = c[offset];
-                    let mut tmp = *elt;
-
-                    tmp = tmp.square();
-                    push_aux!(tmp); // l2
-
-                    tmp = tmp.square();
-                    push_aux!(tmp); // l4
-
-                    tmp = tmp * *elt + y;
-                    push_aux!(tmp); // l5
-
-                    *elt = tmp;
+                    *elt = s_box_witness(alpha, *elt, None, Some(y), aux, &mut aux_index);
                     offset += 1;
                 }
                 let m = &constants.sparse_matrixes[i];
@@ -255,18 +213,7 @@ where
                     // s-box
                     for elt in elements.iter_mut() {
                         let y = c[offset];
-                        let mut tmp = *elt;
-
-                        tmp = tmp.square();
-                        push_aux!(tmp); // l2
-
-                        tmp = tmp.square();
-                        push_aux!(tmp); // l4
-
-                        tmp = tmp * *elt + y;
-                        push_aux!(tmp); // l5
-
-                        *elt = tmp;
+                        *elt = s_box_witness(alpha, *elt, None, Some(y), aux, &mut aux_index);
                         offset += 1;
                     }
                 }
@@ -289,18 +236,7 @@ where
             {
                 // s-box
                 for elt in elements.iter_mut() {
-                    let mut tmp = *elt;
-
-                    tmp = tmp.square();
-                    push_aux!(tmp); // l2
-
-                    tmp = tmp.square();
-                    push_aux!(tmp); // l4
-
-                    tmp *= *elt;
-                    push_aux!(tmp); // l5
-
-                    *elt = tmp;
+                    *elt = s_box_witness(alpha, *elt, None, None, aux, &mut aux_index);
                 }
 
                 // mds
@@ -318,6 +254,40 @@ where
     }
 }
 
+/// Returns `(elt + pre_add)^alpha + post_add`, writing the values allocated by the S-box circuit to `aux`: the result
+/// of each squaring and multiplication, in order.
+fn s_box_witness<Scalar: PrimeField>(
+    alpha: u64,
+    elt: Scalar,
+    pre_add: Option<Scalar>,
+    post_add: Option<Scalar>,
+    aux: &mut [Scalar],
+    aux_index: &mut usize,
+) -> Scalar {
+    let mut push_aux = |val| {
+        aux[*aux_index] = val;
+        *aux_index += 1;
+    };
+
+    let base = pre_add.map_or(elt, |x| elt + x);
+    let mut acc = base;
+    for i in (0..63 - alpha.leading_zeros()).rev() {
+        acc = acc.square();
+        push_aux(acc);
+
+        if (alpha >> i) & 1 == 1 {
+            acc *= base;
+            if i == 0 {
+                if let Some(y) = post_add {
+                    acc += y;
+                }
+            }
+            push_aux(acc);
+        }
+    }
+    acc
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -352,6 +322,45 @@ mod test {
         test_poseidon_hash_aux::<typenum::U15>(Strength::Standard, 561, true);
     }
 
+    #[test]
+    fn test_poseidon_hash_witness_alpha() {
+        use crate::test_field::Fp as F3;
+
+        test_poseidon_hash_witness_alpha_aux(&PoseidonConstants::<F3, typenum::U2>::new());
+        test_poseidon_hash_witness_alpha_aux(
+            &PoseidonConstants::<Fr, typenum::U4>::new_with_alpha(
+                Strength::Standard,
+                HashType::MerkleTree,
+                Some(7),
+            ),
+        );
+    }
+
+    fn test_poseidon_hash_witness_alpha_aux<F: PrimeField, A: Arity<F>>(
+        constants: &PoseidonConstants<F, A>,
+    ) {
+        let mut cs = TestConstraintSystem::<F>::new();
+        let mut wcs = WitnessCS::<F>::new();
+        let preimage: Vec<F> = (0..A::to_usize() as u64).map(F::from).collect();
+        let data: Vec<_> = preimage
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                AllocatedNum::alloc_infallible(cs.namespace(|| format!("data {i}")), || *x)
+            })
+            .collect();
+        wcs.extend_aux(&preimage);
+
+        circuit2::poseidon_hash_allocated(&mut cs, data.clone(), constants).unwrap();
+        let out = poseidon_hash_allocated_witness(&mut wcs, &data, constants).unwrap();
+
+        assert_eq!(None, mismatch(&cs.scalar_aux(), wcs.aux_assignment()));
+        assert_eq!(
+            Poseidon::new_with_preimage(&preimage, constants).hash_in_mode(HashMode::Correct),
+            out.get_value().unwrap()
+        );
+    }
+
     // Returns index of first mismatch, along with the mismatched elements if they exist.
     #[allow(clippy::type_complexity)]
     fn mismatch<T: PartialEq + Copy>(a: &[T], b: &[T]) -> Option<(usize, (Option<T>, Option<T>))> {
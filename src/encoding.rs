@@ -0,0 +1,146 @@
+//! Canonical fixed-width byte encodings of field elements, in either byte order.
+//!
+//! `PrimeField::to_repr` leaves its byte order to the field implementation, so two parties agreeing on a field can
+//! still disagree on its bytes. The functions here are explicit about it: an element encodes as the binary
+//! representation of its canonical integer value, in [`encoded_len`] bytes, least significant byte first (`le`) or
+//! most significant byte first (`be`). Decoding rejects encodings of the wrong length and encodings of integers which
+//! are not less than the modulus, so that every element has exactly one accepted encoding.
+use crate::Error;
+use alloc::{vec, vec::Vec};
+use ff::PrimeFieldBits;
+
+/// Returns the number of bytes in the encoding of an element of `F`: its number of bits, rounded up to whole bytes.
+pub fn encoded_len<F: PrimeFieldBits>() -> usize {
+    (F::NUM_BITS as usize + 7) / 8
+}
+
+/// Encode `x` least significant byte first.
+pub fn field_to_bytes_le<F: PrimeFieldBits>(x: &F) -> Vec<u8> {
+    let mut bytes = vec![0; encoded_len::<F>()];
+    for (i, bit) in x
+        .to_le_bits()
+        .into_iter()
+        .take(F::NUM_BITS as usize)
+        .enumerate()
+    {
+        bytes[i / 8] |= u8::from(bit) << (i % 8);
+    }
+    bytes
+}
+
+/// Encode `x` most significant byte first.
+pub fn field_to_bytes_be<F: PrimeFieldBits>(x: &F) -> Vec<u8> {
+    let mut bytes = field_to_bytes_le(x);
+    bytes.reverse();
+    bytes
+}
+
+/// Decode an element from its least significant byte first encoding.
+pub fn field_from_bytes_le<F: PrimeFieldBits>(bytes: &[u8]) -> Result<F, Error> {
+    let expected = encoded_len::<F>();
+    if bytes.len() != expected {
+        return Err(Error::InvalidEncodingLength {
+            expected,
+            got: bytes.len(),
+        });
+    }
+
+    // Compare with the modulus from the most significant bit down: the first differing bit decides.
+    let modulus = F::char_le_bits();
+    let bit = |i: usize| bytes[i / 8] >> (i % 8) & 1 == 1;
+    let below_modulus = (0..8 * expected)
+        .rev()
+        .map(|i| (bit(i), modulus.get(i).map_or(false, |m| *m)))
+        .find(|(x, m)| x != m)
+        .map_or(false, |(x, _)| !x);
+    if !below_modulus {
+        return Err(Error::NonCanonicalEncoding);
+    }
+
+    let radix = F::from(256);
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(F::ZERO, |acc, byte| acc * radix + F::from(u64::from(*byte))))
+}
+
+/// Decode an element from its most significant byte first encoding.
+pub fn field_from_bytes_be<F: PrimeFieldBits>(bytes: &[u8]) -> Result<F, Error> {
+    let mut le = bytes.to_vec();
+    le.reverse();
+    field_from_bytes_le(&le)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blstrs::Scalar as Fr;
+    use ff::{Field, PrimeField};
+    use pasta_curves::Fp;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn test_roundtrip() {
+        fn roundtrip<F: PrimeFieldBits>() {
+            let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+            for x in [F::ZERO, F::ONE, -F::ONE]
+                .into_iter()
+                .chain((0..20).map(|_| F::random(&mut rng)))
+            {
+                let le = field_to_bytes_le(&x);
+                let be = field_to_bytes_be(&x);
+                assert_eq!(encoded_len::<F>(), le.len());
+                assert_eq!(
+                    le.iter().rev().collect::<Vec<_>>(),
+                    be.iter().collect::<Vec<_>>()
+                );
+                assert_eq!(x, field_from_bytes_le(&le).unwrap());
+                assert_eq!(x, field_from_bytes_be(&be).unwrap());
+            }
+        }
+
+        roundtrip::<Fr>();
+        roundtrip::<Fp>();
+    }
+
+    #[test]
+    fn test_byte_order() {
+        let x = Fr::from(0x0102);
+        let le = field_to_bytes_le(&x);
+        assert_eq!(32, le.len());
+        assert_eq!([0x02, 0x01, 0x00], le[..3]);
+        assert_eq!([0x01, 0x02], field_to_bytes_be(&x)[30..]);
+
+        // BLS12-381 and the Pasta fields use little-endian representations.
+        assert_eq!(x.to_repr().as_ref(), le.as_slice());
+    }
+
+    #[test]
+    fn test_reject_non_canonical() {
+        // -1 is p - 1, so adding one to its least significant byte encodes p itself.
+        let mut p = field_to_bytes_le(&-Fr::ONE);
+        p[0] += 1;
+        assert!(matches!(
+            field_from_bytes_le::<Fr>(&p),
+            Err(Error::NonCanonicalEncoding)
+        ));
+        assert!(matches!(
+            field_from_bytes_le::<Fr>(&[0xff; 32]),
+            Err(Error::NonCanonicalEncoding)
+        ));
+        p.reverse();
+        assert!(matches!(
+            field_from_bytes_be::<Fr>(&p),
+            Err(Error::NonCanonicalEncoding)
+        ));
+
+        assert!(matches!(
+            field_from_bytes_le::<Fr>(&[0; 31]),
+            Err(Error::InvalidEncodingLength {
+                expected: 32,
+                got: 31
+            })
+        ));
+    }
+}
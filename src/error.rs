@@ -1,4 +1,7 @@
-use std::{error, fmt};
+use alloc::string::String;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error;
 
 #[derive(Debug, Clone)]
 #[cfg(any(feature = "cuda", feature = "opencl"))]
@@ -12,6 +15,11 @@ pub enum ClError {
     CannotCreateContext,
     CannotCreateQueue,
     GetDeviceError,
+    /// A device buffer could not be allocated, e.g. because the device is out of memory. Holds the underlying error,
+    /// including its OpenCL or CUDA error code.
+    BufferAllocationFailed(String),
+    /// A kernel could not be created or launched. Holds the underlying error, including its OpenCL or CUDA error code.
+    KernelLaunchFailed(String),
 }
 
 #[cfg(any(feature = "cuda", feature = "opencl"))]
@@ -36,17 +44,37 @@ impl fmt::Display for ClError {
             ClError::CannotCreateContext => write!(f, "Cannot create cl_context."),
             ClError::CannotCreateQueue => write!(f, "Cannot create cl_command_queue."),
             ClError::GetDeviceError => write!(f, "Cannot get Device"),
+            ClError::BufferAllocationFailed(e) => write!(f, "Cannot allocate buffer: {e}"),
+            ClError::KernelLaunchFailed(e) => write!(f, "Cannot launch kernel: {e}"),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 /// Possible error states for the hashing.
+#[non_exhaustive]
 pub enum Error {
     /// The allowed number of leaves cannot be greater than the arity of the tree.
     FullBuffer,
     /// Attempt to reference an index element that is out of bounds
     IndexOutOfBounds,
+    /// A preimage does not have the number of elements required by the arity.
+    ArityMismatch {
+        expected: usize,
+        got: usize,
+    },
+    /// A sponge state does not have the width of the constants it is used with.
+    WidthMismatch {
+        expected: usize,
+        got: usize,
+    },
+    /// A byte encoding of a field element does not have the fixed length required by the field.
+    InvalidEncodingLength {
+        expected: usize,
+        got: usize,
+    },
+    /// A byte encoding of a field element encodes an integer which is not less than the modulus.
+    NonCanonicalEncoding,
     GpuError(String),
     #[cfg(any(feature = "cuda", feature = "opencl"))]
     ClError(ClError),
@@ -67,6 +95,7 @@ impl From<ec_gpu_gen::EcError> for Error {
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for Error {}
 
 impl fmt::Display for Error {
@@ -76,7 +105,22 @@ impl fmt::Display for Error {
                 f,
                 "The size of the buffer cannot be greater than the hash arity."
             ),
-            Error::IndexOutOfBounds => write!(f, "The referenced index is outs of bounds."),
+            Error::IndexOutOfBounds => write!(f, "The referenced index is out of bounds."),
+            Error::ArityMismatch { expected, got } => {
+                write!(f, "Expected a preimage of {expected} elements, got {got}.")
+            }
+            Error::WidthMismatch { expected, got } => {
+                write!(f, "Expected a state of {expected} elements, got {got}.")
+            }
+            Error::InvalidEncodingLength { expected, got } => {
+                write!(
+                    f,
+                    "Expected a field element encoding of {expected} bytes, got {got}."
+                )
+            }
+            Error::NonCanonicalEncoding => {
+                write!(f, "The encoded integer is not less than the field modulus.")
+            }
             Error::GpuError(s) => write!(f, "GPU Error: {s}"),
             #[cfg(any(feature = "cuda", feature = "opencl"))]
             Error::ClError(e) => write!(f, "OpenCL Error: {e}"),
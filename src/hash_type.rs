@@ -30,6 +30,11 @@ pub enum HashType<F: PrimeField, A: Arity<F>> {
     Encryption,
     Custom(CType<F, A>),
     Sponge,
+    /// Like `MerkleTree`, for the hashes of a Merkle tree whose preimages are leaves. Tagging these apart from
+    /// `MerkleNode` hashes keeps an internal node from being presented as a leaf.
+    MerkleLeaf,
+    /// Like `MerkleTree`, for the hashes of a Merkle tree whose preimages are internal nodes.
+    MerkleNode,
 }
 
 impl<F: PrimeField, A: Arity<F>> HashType<F, A> {
@@ -55,6 +60,11 @@ impl<F: PrimeField, A: Arity<F>> HashType<F, A> {
             // we make identifier a multiple of 2^40 rather than 2^32.
             HashType::Custom(ref ctype) => ctype.domain_tag(),
             HashType::Sponge => F::ZERO,
+            // (2^arity - 1) + 2^56
+            // Above every `Custom` tag and below every `ConstantLength` tag.
+            HashType::MerkleLeaf => A::tag() + pow2::<F>(56),
+            // (2^arity - 1) + 2^57
+            HashType::MerkleNode => A::tag() + pow2::<F>(57),
         }
     }
 
@@ -68,7 +78,9 @@ impl<F: PrimeField, A: Arity<F>> HashType<F, A> {
             | HashType::ConstantLength(_)
             | HashType::Encryption
             | HashType::Custom(_)
-            | HashType::Sponge => true,
+            | HashType::Sponge
+            | HashType::MerkleLeaf
+            | HashType::MerkleNode => true,
         }
     }
 }
@@ -192,7 +204,17 @@ mod tests {
             assert_eq!(expected_standard_custom, standard_custom);
         }
 
-        all_tags.extend(&[expected_merkle_standard, expected_encryption_standard]);
+        let merkle_leaf = HashType::MerkleLeaf::<Fr, U8>.domain_tag();
+        let merkle_node = HashType::MerkleNode::<Fr, U8>.domain_tag();
+        assert_eq!(scalar_from_u64s([0x01000000000000ff, 0, 0, 0]), merkle_leaf);
+        assert_eq!(scalar_from_u64s([0x02000000000000ff, 0, 0, 0]), merkle_node);
+
+        all_tags.extend(&[
+            expected_merkle_standard,
+            expected_encryption_standard,
+            merkle_leaf,
+            merkle_node,
+        ]);
 
         let standard_sponge = HashType::Sponge::<Fr, U8>.domain_tag();
         let expected_standard_sponge = scalar_from_u64s([0, 0, 0, 0]);
@@ -1,18 +1,20 @@
+#![cfg_attr(not(feature = "std"), no_std)]
 #![allow(dead_code)]
 #![allow(unused_imports)]
 
+extern crate alloc;
+
 pub use crate::poseidon::{Arity, Poseidon};
 use crate::round_constants::generate_constants;
 use crate::round_numbers::{round_numbers_base, round_numbers_strengthened};
-#[cfg(feature = "abomonation")]
-use abomonation_derive::Abomonation;
+use alloc::vec::Vec;
 #[cfg(test)]
 use blstrs::Scalar as Fr;
+use core::fmt;
 pub use error::Error;
 use ff::PrimeField;
 use generic_array::GenericArray;
 use serde::{Deserialize, Serialize};
-use std::fmt;
 use trait_set::trait_set;
 
 // See https://www.lurklurk.org/effective-rust/re-export.html
@@ -44,13 +46,28 @@ compile_error!("The `strengthened` feature needs the `cuda` and/or `opencl` feat
 ))]
 compile_error!("The `cuda` and `opencl` features need the `bls` and/or `pasta` feature to be set");
 
+/// Criterion benchmarks, generic over the field
+#[cfg(feature = "bench-utils")]
+pub mod bench;
 /// Poseidon circuit
+#[cfg(feature = "std")]
 pub mod circuit;
+#[cfg(feature = "std")]
 pub mod circuit2;
+#[cfg(feature = "std")]
 pub mod circuit2_witness;
+/// Canonical byte encodings of field elements
+pub mod encoding;
 pub mod error;
-mod matrix;
-mod mds;
+/// Matrices over a prime field
+pub mod matrix;
+/// MDS matrices and their sparse factorization
+pub mod mds;
+/// Merkle trees built with a `BatchHasher`
+pub mod merkle;
+/// Merkle path verification circuit
+#[cfg(feature = "std")]
+pub mod merkle_circuit;
 
 /// Poseidon hash
 pub mod poseidon;
@@ -80,6 +97,10 @@ pub mod batch_hasher;
 #[cfg(any(feature = "cuda", feature = "opencl"))]
 pub mod proteus;
 
+/// Checks of the hashing backends against each other
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+
 #[cfg(not(any(feature = "cuda", feature = "opencl")))]
 trait_set! {
    /// Use a trait alias, so that we can have different traits depending on the features.
@@ -98,29 +119,112 @@ trait_set! {
 }
 
 mod serde_impl;
+#[cfg(test)]
+mod test_bn254;
+#[cfg(test)]
+mod test_field;
+#[cfg(test)]
+mod test_goldilocks;
 
 pub(crate) const TEST_SEED: [u8; 16] = [
     0x59, 0x62, 0xbe, 0x5d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06, 0xbc, 0xe5,
 ];
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-#[cfg_attr(feature = "abomonation", derive(Abomonation))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Strength {
     Standard,
     Strengthened,
+    /// Explicit round counts, e.g. for research into round-count/security tradeoffs. Use [`Strength::custom`] to
+    /// construct a valid instance. Custom strengths are not supported on the GPU.
+    Custom {
+        full_rounds: usize,
+        partial_rounds: usize,
+    },
+}
+
+impl Strength {
+    /// Creates a [`Strength::Custom`] with the given round counts. Returns an error unless there is at least one
+    /// partial round and a non-zero, even number of full rounds (half of which are performed on either side of the
+    /// partial rounds).
+    pub fn custom(full_rounds: usize, partial_rounds: usize) -> Result<Self, Error> {
+        if full_rounds == 0 || full_rounds % 2 != 0 {
+            return Err(Error::Other(alloc::format!(
+                "number of full rounds must be even and non-zero, got {full_rounds}"
+            )));
+        }
+        if partial_rounds == 0 {
+            return Err(Error::Other(
+                "number of partial rounds must be non-zero".into(),
+            ));
+        }
+        Ok(Self::Custom {
+            full_rounds,
+            partial_rounds,
+        })
+    }
 }
 
+// `Strength` owns no heap data, so the default (no-op) methods suffice.
+#[cfg(feature = "abomonation")]
+impl abomonation::Abomonation for Strength {}
+
 impl fmt::Display for Strength {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Standard => write!(f, "standard"),
             Self::Strengthened => write!(f, "strengthened"),
+            Self::Custom {
+                full_rounds,
+                partial_rounds,
+            } => write!(f, "custom_{full_rounds}_{partial_rounds}"),
         }
     }
 }
 
 pub(crate) const DEFAULT_STRENGTH: Strength = Strength::Standard;
 
+/// Host-side wall-clock timings of a [`BatchHasher::hash`] call on a GPU, as returned by
+/// [`BatchHasher::last_hash_timing`].
+///
+/// Kernel launches may return before the kernel has completed (OpenCL enqueues them), in which case its execution is
+/// counted in `download`, which waits for the results. `total` is therefore the reliable measure of throughput, while
+/// `upload` isolates the cost of moving preimages to the device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HashTiming {
+    /// Time spent copying the preimages to the device.
+    pub upload: core::time::Duration,
+    /// Time spent creating and launching the kernel.
+    pub kernel: core::time::Duration,
+    /// Time spent waiting for and copying the hashes back from the device.
+    pub download: core::time::Duration,
+}
+
+impl HashTiming {
+    /// Returns the total time of the call.
+    pub fn total(&self) -> core::time::Duration {
+        self.upload + self.kernel + self.download
+    }
+}
+
+impl core::ops::AddAssign for HashTiming {
+    fn add_assign(&mut self, other: Self) {
+        self.upload += other.upload;
+        self.kernel += other.kernel;
+        self.download += other.download;
+    }
+}
+
+/// The backend a batch hasher hashes on, along with the name of the device where applicable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BatcherBackend {
+    Cpu,
+    Cuda(alloc::string::String),
+    OpenCl(alloc::string::String),
+}
+
+/// Hashes batches of preimages of `A` elements each. Every state has width `A + 1`, i.e. a single capacity element, as
+/// required of [`Arity::ConstantsSize`]. The CPU and GPU batch hashers check this at compile time when constructed,
+/// so an arity with a mismatched width cannot silently produce wrong hashes.
 pub trait BatchHasher<F, A>
 where
     F: PrimeField,
@@ -130,6 +234,17 @@ where
 
     fn hash(&mut self, preimages: &[GenericArray<F, A>]) -> Result<Vec<F>, Error>;
 
+    /// Like `hash`, but takes preimages from an iterator. The default implementation collects all preimages before
+    /// hashing them; implementers which do not need a contiguous batch should override it to avoid doing so.
+    fn hash_iter<I>(&mut self, preimages: I) -> Result<Vec<F>, Error>
+    where
+        I: IntoIterator<Item = GenericArray<F, A>>,
+        Self: Sized,
+    {
+        let preimages: Vec<_> = preimages.into_iter().collect();
+        self.hash(&preimages)
+    }
+
     fn hash_into_slice(
         &mut self,
         target_slice: &mut [F],
@@ -142,6 +257,22 @@ where
         Ok(())
     }
 
+    /// Hash `preimages` into the first `preimages.len()` elements of `out`. Returns [`Error::IndexOutOfBounds`] if
+    /// `out` is too small to hold all the hashes.
+    fn hash_into(&mut self, preimages: &[GenericArray<F, A>], out: &mut [F]) -> Result<(), Error> {
+        if out.len() < preimages.len() {
+            return Err(Error::IndexOutOfBounds);
+        }
+        out[..preimages.len()].copy_from_slice(&self.hash(preimages)?);
+        Ok(())
+    }
+
+    /// Returns the timings of the last call to `hash`, if this hasher measures them. GPU hashers do; the default
+    /// implementation returns `None`. For a call split into several dispatches, the timings are summed.
+    fn last_hash_timing(&self) -> Option<HashTiming> {
+        None
+    }
+
     /// `max_batch_size` is advisory. Implenters of `BatchHasher` should ensure that up to the returned max hashes can
     /// be safely performed on the target GPU (currently 2080Ti). The max returned should represent a safe batch size
     /// optimized for performance.
@@ -151,10 +282,28 @@ where
     }
 }
 
+/// Returns the numbers of full and partial rounds for `arity` and `strength`, with the x^5 S-box.
 pub fn round_numbers(arity: usize, strength: &Strength) -> (usize, usize) {
+    round_numbers_with_alpha(arity, strength, 5)
+}
+
+/// Returns the numbers of full and partial rounds `(full_rounds, partial_rounds)` of the constants for field `F`,
+/// arity `A` and `strength`, with the default S-box exponent of `F`, without generating the constants. This agrees
+/// with [`PoseidonConstants::round_numbers`](crate::poseidon::PoseidonConstants::round_numbers).
+pub fn round_numbers_for<F: PrimeField, A: Arity<F>>(strength: &Strength) -> (usize, usize) {
+    round_numbers_with_alpha(A::to_usize(), strength, default_alpha::<F>())
+}
+
+/// Like [`round_numbers`], but for the S-box x^`alpha`. Lower exponents need more rounds. Custom strengths are returned
+/// as is, whatever the exponent.
+pub fn round_numbers_with_alpha(arity: usize, strength: &Strength, alpha: u64) -> (usize, usize) {
     match strength {
-        Strength::Standard => round_numbers_base(arity),
-        Strength::Strengthened => round_numbers_strengthened(arity),
+        Strength::Standard => round_numbers_base(arity, alpha),
+        Strength::Strengthened => round_numbers_strengthened(arity, alpha),
+        Strength::Custom {
+            full_rounds,
+            partial_rounds,
+        } => (*full_rounds, *partial_rounds),
     }
 }
 
@@ -173,17 +322,17 @@ pub(crate) fn scalar_from_u64s(parts: [u64; 4]) -> Fr {
 const SBOX: u8 = 1; // x^5
 const FIELD: u8 = 1; // Gf(p)
 
-fn round_constants<F: PrimeField>(arity: usize, strength: &Strength) -> Vec<F> {
+fn round_constants<F: PrimeField>(arity: usize, strength: &Strength, alpha: u64) -> Vec<F> {
     let t = arity + 1;
 
-    let (full_rounds, partial_rounds) = round_numbers(arity, strength);
+    let (full_rounds, partial_rounds) = round_numbers_with_alpha(arity, strength, alpha);
 
     let r_f = full_rounds as u16;
     let r_p = partial_rounds as u16;
 
     let fr_num_bits = F::NUM_BITS;
     let field_size = {
-        assert!(fr_num_bits <= u32::from(std::u16::MAX));
+        assert!(fr_num_bits <= u32::from(u16::MAX));
         // It's safe to convert to u16 for compatibility with other types.
         fr_num_bits as u16
     };
@@ -191,6 +340,64 @@ fn round_constants<F: PrimeField>(arity: usize, strength: &Strength) -> Vec<F> {
     generate_constants::<F>(FIELD, SBOX, field_size, t as u16, r_f, r_p)
 }
 
+/// Returns the default S-box exponent of `F`: the smallest `alpha` which is valid according to [`is_valid_alpha`].
+///
+/// This is 5 for the BLS12-381 scalar field and both Pasta fields, 3 for fields whose modulus `p` satisfies
+/// `p = 2 (mod 3)`, and 7 for e.g. the Goldilocks field.
+pub fn default_alpha<F: PrimeField>() -> u64 {
+    (3..)
+        .find(|alpha| is_valid_alpha::<F>(*alpha))
+        .expect("some small exponent is coprime to p - 1")
+}
+
+/// Returns whether x^`alpha` is a valid Poseidon S-box for `F`, that is whether `alpha` is at least 3 and coprime to
+/// `p - 1` for the modulus `p`, which makes the S-box a permutation of `F`.
+pub fn is_valid_alpha<F: PrimeField>(alpha: u64) -> bool {
+    alpha >= 3 && gcd(alpha, modulus_minus_one_mod::<F>(alpha)) == 1
+}
+
+/// Returns `(p - 1) mod m` for the modulus `p` of `F`.
+fn modulus_minus_one_mod<F: PrimeField>(m: u64) -> u64 {
+    // The byte order of a representation is implementation-specific, but it is the same for every element.
+    let p_minus_one = (-F::ONE).to_repr();
+    let little_endian = F::ONE.to_repr().as_ref()[0] == 1;
+    let fold = |acc: u128, byte: &u8| (acc * 256 + u128::from(*byte)) % u128::from(m);
+
+    let bytes = p_minus_one.as_ref();
+    let rem = if little_endian {
+        bytes.iter().rev().fold(0, fold)
+    } else {
+        bytes.iter().fold(0, fold)
+    };
+    rem as u64
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Apply the S-Box (s^alpha) to a given item, using [`quintic_s_box`] for `alpha = 5`.
+pub(crate) fn s_box<F: PrimeField>(
+    alpha: u64,
+    l: &mut F,
+    pre_add: Option<&F>,
+    post_add: Option<&F>,
+) {
+    if alpha == 5 {
+        return quintic_s_box(l, pre_add, post_add);
+    }
+    if let Some(x) = pre_add {
+        l.add_assign(x);
+    }
+    *l = l.pow_vartime([alpha]);
+    if let Some(x) = post_add {
+        l.add_assign(x);
+    }
+}
+
 /// Apply the quintic S-Box (s^5) to a given item
 pub(crate) fn quintic_s_box<F: PrimeField>(l: &mut F, pre_add: Option<&F>, post_add: Option<&F>) {
     if let Some(x) = pre_add {
@@ -209,6 +416,69 @@ pub(crate) fn quintic_s_box<F: PrimeField>(l: &mut F, pre_add: Option<&F>, post_
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_custom_strength() {
+        let strength = Strength::custom(6, 40).unwrap();
+        assert_eq!((6, 40), round_numbers(4, &strength));
+        assert_eq!("custom_6_40", strength.to_string());
+
+        assert!(Strength::custom(0, 40).is_err());
+        assert!(Strength::custom(7, 40).is_err());
+        assert!(Strength::custom(8, 0).is_err());
+    }
+
+    #[test]
+    fn test_round_numbers_for() {
+        use crate::poseidon::PoseidonConstants;
+        use generic_array::typenum::{U2, U8};
+
+        fn check<F: PrimeField, A: Arity<F>>() {
+            for strength in [Strength::Standard, Strength::Strengthened] {
+                let constants = PoseidonConstants::<F, A>::new_with_strength(strength);
+                assert_eq!(
+                    constants.round_numbers(),
+                    round_numbers_for::<F, A>(&strength)
+                );
+            }
+        }
+
+        check::<Fr, U2>();
+        check::<Fr, U8>();
+        check::<test_field::Fp, U2>();
+        check::<test_bn254::Fr, U8>();
+        assert_eq!((8, 55), round_numbers_for::<Fr, U2>(&Strength::Standard));
+    }
+
+    #[test]
+    fn test_default_alpha() {
+        use pasta_curves::{Fp, Fq};
+
+        assert_eq!(5, default_alpha::<Fr>());
+        assert_eq!(5, default_alpha::<Fp>());
+        assert_eq!(5, default_alpha::<Fq>());
+        assert_eq!(5, default_alpha::<test_bn254::Fr>());
+        assert_eq!(3, default_alpha::<test_field::Fp>());
+
+        // 3 divides p - 1 for the BLS12-381 scalar field, 7 does not.
+        assert!(!is_valid_alpha::<Fr>(1));
+        assert!(!is_valid_alpha::<Fr>(3));
+        assert!(is_valid_alpha::<Fr>(7));
+        assert!(is_valid_alpha::<test_field::Fp>(3));
+        assert!(!is_valid_alpha::<test_field::Fp>(4));
+    }
+
+    #[test]
+    fn test_s_box() {
+        use ff::Field;
+
+        let x = Fr::from(3);
+        for alpha in [3, 5, 7] {
+            let mut l = x;
+            s_box(alpha, &mut l, Some(&Fr::from(1)), Some(&Fr::from(2)));
+            assert_eq!(Fr::from(4).pow_vartime([alpha]) + Fr::from(2), l);
+        }
+    }
+
     #[test]
     fn test_strengthened_round_numbers() {
         let cases = [
@@ -230,7 +500,7 @@ mod tests {
         ];
 
         cases.iter().for_each(|(arity, expected_rounds)| {
-            let (full_rounds, actual_rounds) = round_numbers_strengthened(*arity);
+            let (full_rounds, actual_rounds) = round_numbers_strengthened(*arity, 5);
             assert_eq!(8, full_rounds);
             assert_eq!(
                 *expected_rounds, actual_rounds,
@@ -1,20 +1,21 @@
+//! Matrix functions over a prime field, as used to precompute the MDS matrices and their sparse factorization. They
+//! are not optimized for speed, and panic when given a [`Matrix`] whose rows are not all of the same length.
+
 // Allow `&Matrix` in function signatures.
 #![allow(clippy::ptr_arg)]
 
+use alloc::{vec, vec::Vec};
 use ff::PrimeField;
 
-/// Matrix functions here are, at least for now, quick and dirty — intended only to support precomputation of poseidon optimization.
-
 /// Matrix represented as a Vec of rows, so that m[i][j] represents the jth column of the ith row in Matrix, m.
-pub(crate) type Matrix<T> = Vec<Vec<T>>;
+pub type Matrix<T> = Vec<Vec<T>>;
 
-pub(crate) fn rows<T>(matrix: &Matrix<T>) -> usize {
+pub fn rows<T>(matrix: &Matrix<T>) -> usize {
     matrix.len()
 }
 
 /// Panics if `matrix` is not actually a matrix. So only use any of these functions on well-formed data.
-/// Only use during constant calculation on matrices known to have been constructed correctly.
-fn columns<T>(matrix: &Matrix<T>) -> usize {
+pub fn columns<T>(matrix: &Matrix<T>) -> usize {
     if matrix.is_empty() {
         0
     } else {
@@ -28,7 +29,7 @@ fn columns<T>(matrix: &Matrix<T>) -> usize {
 
 // This wastefully discards the actual inverse, if it exists, so in general callers should
 // just call `invert` if that result will be needed.
-pub(crate) fn is_invertible<F: PrimeField>(matrix: &Matrix<F>) -> bool {
+pub fn is_invertible<F: PrimeField>(matrix: &Matrix<F>) -> bool {
     is_square(matrix) && invert(matrix).is_some()
 }
 
@@ -57,8 +58,9 @@ fn scalar_vec_mul<F: PrimeField>(scalar: F, vec: &[F]) -> Vec<F> {
         .collect::<Vec<_>>()
 }
 
-pub(crate) fn mat_mul<F: PrimeField>(a: &Matrix<F>, b: &Matrix<F>) -> Option<Matrix<F>> {
-    if rows(a) != columns(b) {
+/// Returns the product `ab`, or `None` if the number of columns of `a` differs from the number of rows of `b`.
+pub fn mat_mul<F: PrimeField>(a: &Matrix<F>, b: &Matrix<F>) -> Option<Matrix<F>> {
+    if columns(a) != rows(b) {
         return None;
     };
 
@@ -108,7 +110,7 @@ pub(crate) fn vec_sub<F: PrimeField>(a: &[F], b: &[F]) -> Vec<F> {
 }
 
 /// Left-multiply a vector by a square matrix of same size: MV where V is considered a column vector.
-pub(crate) fn left_apply_matrix<F: PrimeField>(m: &Matrix<F>, v: &[F]) -> Vec<F> {
+pub fn left_apply_matrix<F: PrimeField>(m: &Matrix<F>, v: &[F]) -> Vec<F> {
     assert!(is_square(m), "Only square matrix can be applied to vector.");
     assert_eq!(
         rows(m),
@@ -129,7 +131,7 @@ pub(crate) fn left_apply_matrix<F: PrimeField>(m: &Matrix<F>, v: &[F]) -> Vec<F>
 }
 
 /// Right-multiply a vector by a square matrix  of same size: VM where V is considered a row vector.
-pub(crate) fn apply_matrix<F: PrimeField>(m: &Matrix<F>, v: &[F]) -> Vec<F> {
+pub fn apply_matrix<F: PrimeField>(m: &Matrix<F>, v: &[F]) -> Vec<F> {
     assert!(is_square(m), "Only square matrix can be applied to vector.");
     assert_eq!(
         rows(m),
@@ -149,22 +151,14 @@ pub(crate) fn apply_matrix<F: PrimeField>(m: &Matrix<F>, v: &[F]) -> Vec<F> {
     result
 }
 
-#[allow(clippy::needless_range_loop)]
-pub(crate) fn transpose<F: PrimeField>(matrix: &Matrix<F>) -> Matrix<F> {
-    let size = rows(matrix);
-    let mut new = Vec::with_capacity(size);
-    for j in 0..size {
-        let mut row = Vec::with_capacity(size);
-        for i in 0..size {
-            row.push(matrix[i][j])
-        }
-        new.push(row);
-    }
-    new
+pub fn transpose<F: PrimeField>(matrix: &Matrix<F>) -> Matrix<F> {
+    (0..columns(matrix))
+        .map(|j| matrix.iter().map(|row| row[j]).collect())
+        .collect()
 }
 
 #[allow(clippy::needless_range_loop)]
-pub(crate) fn make_identity<F: PrimeField>(size: usize) -> Matrix<F> {
+pub fn make_identity<F: PrimeField>(size: usize) -> Matrix<F> {
     let mut result = vec![vec![F::ZERO; size]; size];
     for i in 0..size {
         result[i][i] = F::ONE;
@@ -180,7 +174,7 @@ pub(crate) fn kronecker_delta<F: PrimeField>(i: usize, j: usize) -> F {
     }
 }
 
-pub(crate) fn is_identity<F: PrimeField>(matrix: &Matrix<F>) -> bool {
+pub fn is_identity<F: PrimeField>(matrix: &Matrix<F>) -> bool {
     for i in 0..rows(matrix) {
         for j in 0..columns(matrix) {
             if matrix[i][j] != kronecker_delta(i, j) {
@@ -191,7 +185,7 @@ pub(crate) fn is_identity<F: PrimeField>(matrix: &Matrix<F>) -> bool {
     true
 }
 
-pub(crate) fn is_square<T>(matrix: &Matrix<T>) -> bool {
+pub fn is_square<T>(matrix: &Matrix<T>) -> bool {
     rows(matrix) == columns(matrix)
 }
 
@@ -345,8 +339,11 @@ fn reduce_to_identity<F: PrimeField>(
     Some(result)
 }
 
-//
-pub(crate) fn invert<F: PrimeField>(matrix: &Matrix<F>) -> Option<Matrix<F>> {
+/// Returns the inverse of `matrix`, or `None` if it is not square or not invertible.
+pub fn invert<F: PrimeField>(matrix: &Matrix<F>) -> Option<Matrix<F>> {
+    if !is_square(matrix) {
+        return None;
+    }
     let mut shadow = make_identity(columns(matrix));
     let ut = upper_triangular(matrix, &mut shadow);
 
@@ -357,8 +354,12 @@ pub(crate) fn invert<F: PrimeField>(matrix: &Matrix<F>) -> Option<Matrix<F>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::poseidon::{Poseidon, PoseidonConstants};
     use blstrs::Scalar as Fr;
     use ff::Field;
+    use generic_array::typenum::U4;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
 
     #[test]
     fn test_minor() {
@@ -619,4 +620,67 @@ mod tests {
 
         assert!(is_identity(&prod));
     }
+
+    #[test]
+    fn test_random_inverse() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+
+        for size in 1..6 {
+            let m: Matrix<Fr> = (0..size)
+                .map(|_| (0..size).map(|_| Fr::random(&mut rng)).collect())
+                .collect();
+            // A random matrix is invertible with overwhelming probability.
+            assert!(is_invertible(&m));
+
+            let m_inv = invert(&m).unwrap();
+            assert!(is_identity(&mat_mul(&m, &m_inv).unwrap()));
+            assert!(is_identity(&mat_mul(&m_inv, &m).unwrap()));
+        }
+
+        let singular = vec![
+            vec![Fr::from(1), Fr::from(2)],
+            vec![Fr::from(2), Fr::from(4)],
+        ];
+        assert!(!is_invertible(&singular));
+        assert!(invert(&singular).is_none());
+    }
+
+    #[test]
+    fn test_rectangular() {
+        let a: Matrix<Fr> = vec![
+            vec![Fr::from(1), Fr::from(2), Fr::from(3)],
+            vec![Fr::from(4), Fr::from(5), Fr::from(6)],
+        ];
+        let a_t = transpose(&a);
+        assert_eq!(3, rows(&a_t));
+        assert_eq!(2, columns(&a_t));
+        assert_eq!(a, transpose(&a_t));
+
+        let expected = vec![
+            vec![Fr::from(14), Fr::from(32)],
+            vec![Fr::from(32), Fr::from(77)],
+        ];
+        assert_eq!(Some(expected), mat_mul(&a, &a_t));
+        assert!(mat_mul(&a, &a).is_none());
+        assert!(invert(&a).is_none());
+    }
+
+    #[test]
+    fn test_apply_mds() {
+        let constants = PoseidonConstants::<Fr, U4>::new();
+        let preimage: Vec<Fr> = (0..4).map(Fr::from).collect();
+        let mut p = Poseidon::new_with_preimage(&preimage, &constants);
+        let elements = p.elements.to_vec();
+
+        p.product_mds();
+        assert_eq!(
+            left_apply_matrix(&constants.mds_matrices.m, &elements),
+            p.elements.to_vec()
+        );
+        // The MDS matrix is symmetric, so applying it from either side agrees.
+        assert_eq!(
+            apply_matrix(&constants.mds_matrices.m, &elements),
+            p.elements.to_vec()
+        );
+    }
 }
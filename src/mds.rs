@@ -5,14 +5,16 @@
 use abomonation::Abomonation;
 #[cfg(feature = "abomonation")]
 use abomonation_derive::Abomonation;
+use alloc::{format, vec, vec::Vec};
 use ff::PrimeField;
 use serde::{Deserialize, Serialize};
 
 use crate::matrix;
 use crate::matrix::{
-    apply_matrix, invert, is_identity, is_invertible, is_square, left_apply_matrix, mat_mul, minor,
-    transpose, Matrix,
+    apply_matrix, invert, is_identity, is_invertible, is_square, kronecker_delta,
+    left_apply_matrix, mat_mul, minor, transpose, Matrix,
 };
+use crate::Error;
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[cfg_attr(feature = "abomonation", derive(Abomonation))]
@@ -54,9 +56,11 @@ pub(crate) fn derive_mds_matrices<F: PrimeField>(m: Matrix<F>) -> MdsMatrices<F>
     }
 }
 
-/// A `SparseMatrix` is specifically one of the form of M''.
-/// This means its first row and column are each dense, and the interior matrix
-/// (minor to the element in both the row and column) is the identity.
+/// A `SparseMatrix` is specifically one of the form of M''. Its first row and column are each dense, and the interior
+/// matrix (minor to the element in both the row and column) is the identity, so its only non-zero elements are its
+/// first row, first column and diagonal. Such matrices are produced by [`factor_to_sparse_matrixes`], and are used to
+/// speed up the partial rounds.
+///
 /// We will pluralize this compact structure `sparse_matrixes` to distinguish from `sparse_matrices` from which they are created.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[cfg_attr(feature = "abomonation", derive(Abomonation))]
@@ -89,6 +93,35 @@ impl<F: PrimeField> SparseMatrix<F> {
         self.w_hat.len()
     }
 
+    /// Multiply `state`, considered a row vector, by this sparse matrix in place. This is equivalent to, but much
+    /// cheaper than, `apply_matrix(&self.to_matrix(), state)`: it takes `2 * (size - 1) + 1` multiplications
+    /// rather than `size * size`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of `state` is not equal to the size of the matrix.
+    pub fn apply(&self, state: &mut [F]) {
+        assert_eq!(
+            self.size(),
+            state.len(),
+            "state must match sparse matrix size"
+        );
+
+        // First column is dense.
+        let first = self
+            .w_hat
+            .iter()
+            .zip(state.iter())
+            .fold(F::ZERO, |acc, (w, x)| acc + *w * x);
+
+        // First row is dense, and except for first row/column, diagonals are one.
+        let (head, rest) = state.split_first_mut().unwrap();
+        for (x, v) in rest.iter_mut().zip(self.v_rest.iter()) {
+            *x += *v * *head;
+        }
+        *head = first;
+    }
+
     pub fn to_matrix(&self) -> Matrix<F> {
         let mut m = matrix::make_identity(self.size());
         for (j, elt) in self.w_hat.iter().enumerate() {
@@ -107,7 +140,11 @@ impl<F: PrimeField> SparseMatrix<F> {
 //   - M'' is sparse and replaces M for the round.
 //   - The previous layer's M is then replaced by M x M' = M*.
 //   - M* is likewise factored into M*' and M*'', and the process continues.
-pub(crate) fn factor_to_sparse_matrixes<F: PrimeField>(
+/// Factor `n` successive applications of `base_matrix` into a single dense matrix, to be applied first, followed
+/// by `n` [`SparseMatrix`]es, to be applied in order. As in the partial rounds of Poseidon, a (non-linear)
+/// function of the first element only may be interleaved between successive applications without changing the
+/// result.
+pub fn factor_to_sparse_matrixes<F: PrimeField>(
     base_matrix: &Matrix<F>,
     n: usize,
 ) -> (Matrix<F>, Vec<SparseMatrix<F>>) {
@@ -169,6 +206,184 @@ pub(crate) fn generate_mds<F: PrimeField>(t: usize) -> Matrix<F> {
     matrix
 }
 
+/// Returns the Cauchy matrix `1 / (x_i - y_j)` of the `xs` and `ys`, which is MDS whenever it is defined: every square
+/// submatrix of it is a Cauchy matrix too, whose determinant is non-zero if the `xs` are distinct, the `ys` are
+/// distinct, and no `x` equals a `y`. This is an alternative to the default matrix (which is itself the Cauchy matrix of
+/// `0..t` and `-(t..2t)`) for fields or widths where a different choice of points is preferred. The matrix need not be
+/// symmetric.
+///
+/// Returns an error if the lengths of `xs` and `ys` differ, or if the two sets are not disjoint sets of distinct
+/// elements.
+pub fn cauchy_mds<F: PrimeField>(xs: &[F], ys: &[F]) -> Result<Matrix<F>, Error> {
+    if xs.len() != ys.len() {
+        return Err(Error::Other(format!(
+            "Cauchy matrix needs as many x values as y values, got {} and {}",
+            xs.len(),
+            ys.len()
+        )));
+    }
+    let has_duplicates = |values: &[F]| {
+        values
+            .iter()
+            .enumerate()
+            .any(|(i, a)| values[i + 1..].contains(a))
+    };
+    if has_duplicates(xs) || has_duplicates(ys) {
+        return Err(Error::Other(
+            "Cauchy matrix x values and y values must each be distinct".into(),
+        ));
+    }
+
+    xs.iter()
+        .map(|x| {
+            ys.iter()
+                .map(|y| {
+                    Option::from((*x - y).invert()).ok_or_else(|| {
+                        Error::Other("Cauchy matrix x values and y values must be disjoint".into())
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The widest non-Cauchy matrix for which [`check_mds`] checks every square submatrix, of which there are
+/// `binomial(2 * width, width) - 1`.
+const MAX_EXHAUSTIVE_MDS_WIDTH: usize = 8;
+
+/// How thoroughly [`check_mds`] established that a matrix is MDS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MdsCheck {
+    /// Every square submatrix is invertible.
+    Verified,
+    /// The matrix is too wide for its square submatrices to be checked exhaustively. Only its elements, the matrix
+    /// itself and the minor `M^` used by the sparse factorization were found to be invertible, which is all that
+    /// constructing constants from it requires.
+    Unverified,
+}
+
+/// Returns an error identifying a singular square submatrix of `m`, unless `m` is MDS (all its square submatrices are
+/// invertible) or, for wide non-Cauchy matrices, no singular submatrix is found among those checked.
+///
+/// Every square submatrix of a Cauchy matrix `1 / (x_i - y_j)` is itself a Cauchy matrix, which is invertible exactly
+/// when the `x_i` are distinct and the `y_j` are distinct. So for a Cauchy matrix, such as [`generate_mds`] produces,
+/// checking that suffices. Other matrices are checked exhaustively, which is only feasible for small widths: wider ones
+/// are checked only for the invertibility of the submatrices the construction relies on, and reported as
+/// [`MdsCheck::Unverified`].
+pub(crate) fn check_mds<F: PrimeField>(m: &Matrix<F>) -> Result<MdsCheck, Error> {
+    if !is_square(m) {
+        return Err(Error::Other("MDS matrix is not square".into()));
+    }
+    let singular = |rows: &[usize], columns: &[usize]| {
+        Error::Other(format!(
+            "MDS matrix has a singular submatrix with rows {rows:?} and columns {columns:?}"
+        ))
+    };
+
+    let size = matrix::rows(m);
+    let mut inverses = Vec::with_capacity(size);
+    for (i, row) in m.iter().enumerate() {
+        let mut row_inverses = Vec::with_capacity(size);
+        for (j, elt) in row.iter().enumerate() {
+            match Option::<F>::from(elt.invert()) {
+                Some(inverse) => row_inverses.push(inverse),
+                None => return Err(singular(&[i], &[j])),
+            }
+        }
+        inverses.push(row_inverses);
+    }
+
+    // Recover `x` and `y`, which are only determined up to adding the same constant to both.
+    let xs: Vec<F> = (0..size).map(|i| inverses[i][0]).collect();
+    let ys: Vec<F> = (0..size).map(|j| xs[0] - inverses[0][j]).collect();
+    let is_cauchy = (0..size).all(|i| (0..size).all(|j| inverses[i][j] == xs[i] - ys[j]));
+
+    if is_cauchy {
+        for i in 0..size {
+            for k in i + 1..size {
+                // Equal `x` (`y`) values make for equal rows (columns).
+                if xs[i] == xs[k] {
+                    return Err(singular(&[i, k], &[0, 1]));
+                }
+                if ys[i] == ys[k] {
+                    return Err(singular(&[0, 1], &[i, k]));
+                }
+            }
+        }
+        return Ok(MdsCheck::Verified);
+    }
+
+    if size > MAX_EXHAUSTIVE_MDS_WIDTH {
+        let all: Vec<usize> = (0..size).collect();
+        if !is_invertible(m) {
+            return Err(singular(&all, &all));
+        }
+        if !is_invertible(&minor(m, 0, 0)) {
+            return Err(singular(&all[1..], &all[1..]));
+        }
+        return Ok(MdsCheck::Unverified);
+    }
+    let select =
+        |mask: usize| -> Vec<usize> { (0..size).filter(|i| mask & (1 << i) != 0).collect() };
+    for row_mask in 1..(1usize << size) {
+        let rows = select(row_mask);
+        for column_mask in (1..(1usize << size)).filter(|c| c.count_ones() == row_mask.count_ones())
+        {
+            let columns = select(column_mask);
+            let submatrix: Matrix<F> = rows
+                .iter()
+                .map(|i| columns.iter().map(|j| m[*i][*j]).collect())
+                .collect();
+            if !is_invertible(&submatrix) {
+                return Err(singular(&rows, &columns));
+            }
+        }
+    }
+    Ok(MdsCheck::Verified)
+}
+
+/// Returns an error unless applying `pre_sparse` and then `sparse`, in order, to a row vector equals applying `m`
+/// `sparse.len() + 1` times, when any function of the first element only is interleaved between the matrices, as in
+/// the partial rounds.
+///
+/// This holds if `T_0 = pre_sparse` and, for each `i`, `C_i = m^-1 * T_{i-1}` leaves the first element alone (its first
+/// row and column are those of the identity) and `T_i = C_i * sparse[i]`, with `T_n = m` at the end: then by induction
+/// `x * T_{i-1} = f(x * m) * C_i` for any such `f`.
+pub(crate) fn check_sparse_factorization<F: PrimeField>(
+    m: &Matrix<F>,
+    pre_sparse: &Matrix<F>,
+    sparse: &[SparseMatrix<F>],
+) -> Result<(), Error> {
+    let m_inv = invert(m).ok_or_else(|| Error::Other("MDS matrix is not invertible".into()))?;
+    let size = matrix::rows(m);
+
+    let mut t = pre_sparse.clone();
+    for (i, sparse_matrix) in sparse.iter().enumerate() {
+        let mismatch = || {
+            Error::Other(format!(
+                "sparse factorization does not reproduce the MDS matrix at partial round {i}"
+            ))
+        };
+        if sparse_matrix.size() != size {
+            return Err(mismatch());
+        }
+        let c = mat_mul(&m_inv, &t).ok_or_else(mismatch)?;
+        let leaves_first_alone =
+            (0..size).all(|j| c[0][j] == kronecker_delta(0, j) && c[j][0] == kronecker_delta(j, 0));
+        if !leaves_first_alone {
+            return Err(mismatch());
+        }
+        t = mat_mul(&c, &sparse_matrix.to_matrix()).ok_or_else(mismatch)?;
+    }
+
+    if t != *m {
+        return Err(Error::Other(
+            "sparse factorization does not reproduce the MDS matrix".into(),
+        ));
+    }
+    Ok(())
+}
+
 fn make_prime<F: PrimeField>(m: &Matrix<F>) -> Matrix<F> {
     m.iter()
         .enumerate()
@@ -231,6 +446,10 @@ mod tests {
         for i in 2..5 {
             test_mds_matrices_creation_aux(i);
         }
+        // Widths of the less common arities 11, 16 and 36.
+        for i in [12, 17, 37] {
+            test_mds_matrices_creation_aux(i);
+        }
     }
 
     fn test_mds_matrices_creation_aux(width: usize) {
@@ -256,6 +475,87 @@ mod tests {
         assert_eq!(m, mat_mul(&m_prime, &m_double_prime).unwrap());
     }
 
+    #[test]
+    fn test_check_mds() {
+        for width in [2, 3, 5, 9, 12, 17, 37] {
+            check_mds(&generate_mds::<Fr>(width)).unwrap();
+        }
+
+        // Small non-Cauchy matrices are checked exhaustively.
+        let mut rng = XorShiftRng::from_seed(TEST_SEED);
+        let random: Matrix<Fr> = (0..4)
+            .map(|_| (0..4).map(|_| Fr::random(&mut rng)).collect())
+            .collect();
+        assert_eq!(MdsCheck::Verified, check_mds(&random).unwrap());
+
+        // Wider ones are only checked for the invertibility of the whole matrix and of `M^`.
+        let wide: Matrix<Fr> = (0..9)
+            .map(|_| (0..9).map(|_| Fr::random(&mut rng)).collect())
+            .collect();
+        assert_eq!(MdsCheck::Unverified, check_mds(&wide).unwrap());
+        let mut singular = wide.clone();
+        singular[8] = singular[7].clone();
+        let err = check_mds(&singular).unwrap_err().to_string();
+        assert!(err.contains("rows [0, 1, 2, 3, 4, 5, 6, 7, 8]"), "{err}");
+
+        // Invertible, but with a singular submatrix.
+        let m = [[1, 2, 3], [2, 4, 5], [3, 7, 1]]
+            .iter()
+            .map(|row| row.iter().map(|x| Fr::from(*x)).collect())
+            .collect();
+        assert!(is_invertible(&m));
+        let err = check_mds(&m).unwrap_err().to_string();
+        assert!(err.contains("rows [0, 1] and columns [0, 1]"), "{err}");
+
+        // A Cauchy matrix whose `x` values are not distinct.
+        let xs = [1, 2, 1].map(Fr::from);
+        let ys = [10, 20, 30].map(Fr::from);
+        let m = xs
+            .iter()
+            .map(|x| ys.iter().map(|y| (*x - y).invert().unwrap()).collect())
+            .collect();
+        let err = check_mds(&m).unwrap_err().to_string();
+        assert!(err.contains("rows [0, 2]"), "{err}");
+
+        let mut m = generate_mds::<Fr>(3);
+        m[1][2] = Fr::ZERO;
+        let err = check_mds(&m).unwrap_err().to_string();
+        assert!(err.contains("rows [1] and columns [2]"), "{err}");
+    }
+
+    #[test]
+    fn test_cauchy_mds() {
+        // The default matrix is a Cauchy matrix.
+        for width in [2, 3, 5, 12] {
+            let xs: Vec<Fr> = (0..width as u64).map(Fr::from).collect();
+            let ys: Vec<Fr> = (width as u64..2 * width as u64)
+                .map(|y| -Fr::from(y))
+                .collect();
+            assert_eq!(generate_mds::<Fr>(width), cauchy_mds(&xs, &ys).unwrap());
+        }
+
+        let xs = [1, 2, 3, 4].map(Fr::from);
+        let ys = [5, 7, 11, 13].map(Fr::from);
+        let m = cauchy_mds(&xs, &ys).unwrap();
+        check_mds(&m).unwrap();
+        assert_ne!(m, transpose(&m));
+        derive_mds_matrices(m);
+
+        assert!(cauchy_mds(&xs, &ys[..3]).is_err());
+        assert!(cauchy_mds(&[1, 2, 1].map(Fr::from), &ys[..3]).is_err());
+        assert!(cauchy_mds(&xs, &[5, 7, 4, 13].map(Fr::from)).is_err());
+    }
+
+    #[test]
+    fn test_check_sparse_factorization() {
+        let m = transpose(&generate_mds::<Fr>(5));
+        let (pre_sparse, mut sparse) = factor_to_sparse_matrixes(&m, 4);
+        check_sparse_factorization(&m, &pre_sparse, &sparse).unwrap();
+
+        sparse.swap(0, 1);
+        assert!(check_sparse_factorization(&m, &pre_sparse, &sparse).is_err());
+    }
+
     #[test]
     fn test_swapping() {
         test_swapping_aux(3);
@@ -351,11 +651,43 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_sparse_matrix_apply() {
+        let mut rng = XorShiftRng::from_seed(TEST_SEED);
+
+        for width in 3..9 {
+            let m = generate_mds::<Fr>(width);
+            let (pre_sparse, sparse_matrixes) = factor_to_sparse_matrixes(&m, 3);
+
+            let initial: Vec<Fr> = (0..width).map(|_| Fr::random(&mut rng)).collect();
+
+            for sparse in &sparse_matrixes {
+                let mut actual = initial.clone();
+                sparse.apply(&mut actual);
+                assert_eq!(apply_matrix(&sparse.to_matrix(), &initial), actual);
+            }
+
+            // Applying the factorization is equivalent to applying the dense matrix repeatedly.
+            let expected = (0..4).fold(initial.clone(), |acc, _| apply_matrix(&m, &acc));
+            let actual = sparse_matrixes.iter().fold(
+                apply_matrix(&pre_sparse, &initial),
+                |mut acc, sparse| {
+                    sparse.apply(&mut acc);
+                    acc
+                },
+            );
+            assert_eq!(expected, actual);
+        }
+    }
+
     #[test]
     fn test_factor_to_sparse_matrixes() {
         for width in 3..9 {
             test_factor_to_sparse_matrixes_aux(width, 3);
         }
+        for width in [12, 17, 37] {
+            test_factor_to_sparse_matrixes_aux(width, 3);
+        }
     }
 
     fn test_factor_to_sparse_matrixes_aux(width: usize, n: usize) {
@@ -0,0 +1,351 @@
+use crate::error::Error;
+use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
+use crate::BatchHasher;
+use alloc::{format, vec, vec::Vec};
+use core::marker::PhantomData;
+use ff::PrimeField;
+use generic_array::GenericArray;
+
+/// Builds full Merkle trees over field elements, hashing one row at a time with any [`BatchHasher`].
+///
+/// Leaves are padded with `F::ZERO` up to the smallest power of the arity which is at least the number of leaves (and
+/// at least the arity itself, so the root is always a hash). Each row is hashed into the next in batches of at most
+/// [`BatchHasher::max_batch_size`] preimages.
+///
+/// A tree built with [`MerkleTreeBuilder::new_domain_separated`] hashes the leaves with one batcher and the internal
+/// nodes with another, e.g. using [`crate::hash_type::HashType::MerkleLeaf`] and
+/// [`crate::hash_type::HashType::MerkleNode`], so that an internal node cannot be passed off as a leaf.
+pub struct MerkleTreeBuilder<F, A, B>
+where
+    F: PrimeField,
+    A: Arity<F>,
+    B: BatchHasher<F, A>,
+{
+    batcher: B,
+    leaf_batcher: Option<B>,
+    _f: PhantomData<(F, A)>,
+}
+
+impl<F, A, B> MerkleTreeBuilder<F, A, B>
+where
+    F: PrimeField,
+    A: Arity<F>,
+    B: BatchHasher<F, A>,
+{
+    pub fn new(batcher: B) -> Self {
+        Self {
+            batcher,
+            leaf_batcher: None,
+            _f: PhantomData,
+        }
+    }
+
+    /// Create a builder hashing the leaves with `leaf_batcher`, and every row above them with `node_batcher`.
+    pub fn new_domain_separated(leaf_batcher: B, node_batcher: B) -> Self {
+        Self {
+            batcher: node_batcher,
+            leaf_batcher: Some(leaf_batcher),
+            _f: PhantomData,
+        }
+    }
+
+    /// Build the tree over `leaves`, keeping every row.
+    pub fn build(&mut self, leaves: &[F]) -> Result<MerkleTree<F, A>, Error> {
+        let mut rows = vec![Self::pad(leaves)?];
+        while rows[rows.len() - 1].len() > 1 {
+            let next = self.hash_row(&rows[rows.len() - 1], rows.len() == 1)?;
+            rows.push(next);
+        }
+
+        Ok(MerkleTree {
+            rows,
+            _a: PhantomData,
+        })
+    }
+
+    /// Compute the root of the tree over `leaves`, keeping only the row being hashed.
+    pub fn root(&mut self, leaves: &[F]) -> Result<F, Error> {
+        let mut row = Self::pad(leaves)?;
+        let mut is_leaves = true;
+        while row.len() > 1 {
+            row = self.hash_row(&row, is_leaves)?;
+            is_leaves = false;
+        }
+        Ok(row[0])
+    }
+
+    /// Returns the number of leaves of the full tree over `leaf_count` leaves, as described by the padding rule.
+    pub fn padded_leaf_count(leaf_count: usize) -> usize {
+        let arity = A::to_usize();
+        let mut padded = arity;
+        while padded < leaf_count {
+            padded *= arity;
+        }
+        padded
+    }
+
+    fn pad(leaves: &[F]) -> Result<Vec<F>, Error> {
+        if leaves.is_empty() {
+            return Err(Error::Other(
+                "cannot build a Merkle tree without leaves".into(),
+            ));
+        }
+
+        let mut padded = vec![F::ZERO; Self::padded_leaf_count(leaves.len())];
+        padded[..leaves.len()].copy_from_slice(leaves);
+        Ok(padded)
+    }
+
+    fn hash_row(&mut self, row: &[F], is_leaves: bool) -> Result<Vec<F>, Error> {
+        let arity = A::to_usize();
+        let batcher = match (is_leaves, self.leaf_batcher.as_mut()) {
+            (true, Some(leaf_batcher)) => leaf_batcher,
+            _ => &mut self.batcher,
+        };
+        let max_batch_size = batcher.max_batch_size().max(1);
+
+        let mut next = Vec::with_capacity(row.len() / arity);
+        for batch in row.chunks(max_batch_size * arity) {
+            let preimages: Vec<GenericArray<F, A>> = batch
+                .chunks_exact(arity)
+                .map(|chunk| GenericArray::from_slice(chunk).clone())
+                .collect();
+            next.extend(batcher.hash(&preimages)?);
+        }
+
+        if next.len() != row.len() / arity {
+            return Err(Error::Other(format!(
+                "batch hasher returned {} hashes for {} preimages",
+                next.len(),
+                row.len() / arity
+            )));
+        }
+        Ok(next)
+    }
+}
+
+/// A full Merkle tree, as built by [`MerkleTreeBuilder::build`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleTree<F, A>
+where
+    F: PrimeField,
+    A: Arity<F>,
+{
+    rows: Vec<Vec<F>>,
+    _a: PhantomData<A>,
+}
+
+impl<F, A> MerkleTree<F, A>
+where
+    F: PrimeField,
+    A: Arity<F>,
+{
+    pub fn root(&self) -> F {
+        self.rows[self.rows.len() - 1][0]
+    }
+
+    /// Returns all rows of the tree, from the (padded) leaves to the root.
+    pub fn rows(&self) -> &[Vec<F>] {
+        &self.rows
+    }
+
+    /// Returns the (padded) leaves.
+    pub fn leaves(&self) -> &[F] {
+        &self.rows[0]
+    }
+
+    /// Returns the number of hashes from a leaf to the root.
+    pub fn height(&self) -> usize {
+        self.rows.len() - 1
+    }
+
+    /// Returns the Merkle path of the leaf at `index`, or [`Error::IndexOutOfBounds`] if there is no such leaf.
+    pub fn path(&self, index: usize) -> Result<MerklePath<F>, Error> {
+        if index >= self.leaves().len() {
+            return Err(Error::IndexOutOfBounds);
+        }
+
+        let arity = A::to_usize();
+        let mut siblings = Vec::with_capacity(self.height());
+        let mut node_index = index;
+        for row in &self.rows[..self.height()] {
+            let first = node_index - node_index % arity;
+            let mut level: Vec<F> = row[first..first + arity].to_vec();
+            level.remove(node_index % arity);
+            siblings.push(level);
+            node_index /= arity;
+        }
+
+        Ok(MerklePath { index, siblings })
+    }
+}
+
+/// The path from a leaf to the root of a [`MerkleTree`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerklePath<F: PrimeField> {
+    /// The index of the leaf.
+    pub index: usize,
+    /// For each hash from the leaf to the root, the other `arity - 1` elements of its preimage, in order.
+    pub siblings: Vec<Vec<F>>,
+}
+
+impl<F: PrimeField> MerklePath<F> {
+    /// Recompute the root from `leaf` along this path, hashing with `constants`. The result equals the tree's root if
+    /// and only if `leaf` is the leaf at `index` (barring hash collisions).
+    pub fn compute_root<A: Arity<F>>(&self, leaf: F, constants: &PoseidonConstants<F, A>) -> F {
+        self.compute_root_domain_separated(leaf, constants, constants)
+    }
+
+    /// Like `compute_root`, for a tree built by [`MerkleTreeBuilder::new_domain_separated`]: the first hash uses
+    /// `leaf_constants` and the others `node_constants`.
+    pub fn compute_root_domain_separated<A: Arity<F>>(
+        &self,
+        leaf: F,
+        leaf_constants: &PoseidonConstants<F, A>,
+        node_constants: &PoseidonConstants<F, A>,
+    ) -> F {
+        let arity = A::to_usize();
+        let mut node = leaf;
+        let mut node_index = self.index;
+        let mut preimage = Vec::with_capacity(arity);
+        for (level, siblings) in self.siblings.iter().enumerate() {
+            assert_eq!(arity - 1, siblings.len(), "malformed Merkle path");
+
+            let position = node_index % arity;
+            preimage.clear();
+            preimage.extend_from_slice(&siblings[..position]);
+            preimage.push(node);
+            preimage.extend_from_slice(&siblings[position..]);
+
+            let constants = if level == 0 {
+                leaf_constants
+            } else {
+                node_constants
+            };
+            node = Poseidon::new_with_preimage(&preimage, constants).hash();
+            node_index /= arity;
+        }
+        node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_type::HashType;
+    use crate::poseidon::SimplePoseidonBatchHasher;
+    use crate::Strength;
+    use blstrs::Scalar as Fr;
+    use generic_array::typenum::{U2, U4};
+
+    #[test]
+    fn test_build() {
+        let constants = PoseidonConstants::<Fr, U2>::new();
+        let leaves: Vec<Fr> = (1..=5).map(Fr::from).collect();
+        let mut builder = MerkleTreeBuilder::new(SimplePoseidonBatchHasher::<Fr, U2>::new(2));
+        let tree = builder.build(&leaves).unwrap();
+
+        let hash = |a, b| Poseidon::new_with_preimage(&[a, b], &constants).hash();
+        let zero = Fr::from(0);
+        let expected = hash(
+            hash(hash(leaves[0], leaves[1]), hash(leaves[2], leaves[3])),
+            hash(hash(leaves[4], zero), hash(zero, zero)),
+        );
+
+        assert_eq!(
+            [8, 4, 2, 1],
+            tree.rows().iter().map(Vec::len).collect::<Vec<_>>()[..]
+        );
+        assert_eq!(3, tree.height());
+        assert_eq!(expected, tree.root());
+        assert_eq!(expected, builder.root(&leaves).unwrap());
+
+        // A single leaf still yields a single hash.
+        assert_eq!(hash(leaves[0], zero), builder.root(&leaves[..1]).unwrap());
+        assert!(builder.root(&[]).is_err());
+    }
+
+    #[test]
+    fn test_batch_size_independence() {
+        let leaves: Vec<Fr> = (0..17).map(Fr::from).collect();
+        let roots: Vec<Fr> = [0, 1, 3, 1000]
+            .into_iter()
+            .map(|max_batch_size| {
+                MerkleTreeBuilder::new(SimplePoseidonBatchHasher::<Fr, U4>::new(max_batch_size))
+                    .root(&leaves)
+                    .unwrap()
+            })
+            .collect();
+
+        assert!(roots.iter().all(|root| *root == roots[0]));
+        assert_eq!(
+            64,
+            MerkleTreeBuilder::<Fr, U4, SimplePoseidonBatchHasher<Fr, U4>>::padded_leaf_count(17)
+        );
+    }
+
+    #[test]
+    fn test_path() {
+        let constants = PoseidonConstants::<Fr, U4>::new();
+        let leaves: Vec<Fr> = (0..20).map(Fr::from).collect();
+        let tree = MerkleTreeBuilder::new(SimplePoseidonBatchHasher::<Fr, U4>::new(4))
+            .build(&leaves)
+            .unwrap();
+
+        for (index, leaf) in tree.leaves().iter().enumerate() {
+            let path = tree.path(index).unwrap();
+            assert_eq!(tree.height(), path.siblings.len());
+            assert_eq!(tree.root(), path.compute_root(*leaf, &constants));
+            assert_ne!(
+                tree.root(),
+                path.compute_root(*leaf + Fr::from(1), &constants)
+            );
+        }
+
+        assert!(matches!(tree.path(64), Err(Error::IndexOutOfBounds)));
+    }
+
+    #[test]
+    fn test_domain_separated() {
+        let leaf_constants =
+            PoseidonConstants::<Fr, U2>::new().with_hash_type(HashType::MerkleLeaf);
+        let node_constants =
+            PoseidonConstants::<Fr, U2>::new().with_hash_type(HashType::MerkleNode);
+        let batcher = |hash_type| {
+            SimplePoseidonBatchHasher::<Fr, U2>::new_with_strength_and_type(
+                Strength::Standard,
+                hash_type,
+                2,
+            )
+        };
+        let leaves: Vec<Fr> = (1..=4).map(Fr::from).collect();
+        let mut builder = MerkleTreeBuilder::new_domain_separated(
+            batcher(HashType::MerkleLeaf),
+            batcher(HashType::MerkleNode),
+        );
+        let tree = builder.build(&leaves).unwrap();
+
+        let hash = |constants, a, b| Poseidon::new_with_preimage(&[a, b], constants).hash();
+        let expected = hash(
+            &node_constants,
+            hash(&leaf_constants, leaves[0], leaves[1]),
+            hash(&leaf_constants, leaves[2], leaves[3]),
+        );
+        assert_eq!(expected, tree.root());
+        assert_eq!(expected, builder.root(&leaves).unwrap());
+
+        let plain = MerkleTreeBuilder::new(SimplePoseidonBatchHasher::<Fr, U2>::new(2))
+            .root(&leaves)
+            .unwrap();
+        assert_ne!(plain, tree.root());
+
+        for (index, leaf) in tree.leaves().iter().enumerate() {
+            let path = tree.path(index).unwrap();
+            assert_eq!(
+                tree.root(),
+                path.compute_root_domain_separated(*leaf, &leaf_constants, &node_constants)
+            );
+            assert_ne!(tree.root(), path.compute_root(*leaf, &node_constants));
+        }
+    }
+}
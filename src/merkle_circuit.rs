@@ -0,0 +1,344 @@
+//! Circuits verifying paths through Merkle trees hashed with Poseidon, such as those built by
+//! [`crate::merkle::MerkleTreeBuilder`].
+use crate::circuit2::poseidon_hash_allocated;
+use crate::poseidon::{Arity, PoseidonConstants};
+use bellpepper_core::boolean::Boolean;
+use bellpepper_core::num::AllocatedNum;
+use bellpepper_core::{ConstraintSystem, LinearCombination, SynthesisError};
+use ff::PrimeField;
+
+/// Compute the root of a Merkle tree from an allocated `leaf` and its path.
+///
+/// `siblings` holds, for each hash from the leaf to the root, the other `arity - 1` elements of its preimage in order,
+/// as in [`crate::merkle::MerklePath`]. `index_bits` is the little-endian bit decomposition of the leaf index,
+/// `log2(arity)` bits per level: each level's bits give the position of the current node among its siblings.
+///
+/// # Panics
+///
+/// Panics if the arity is not a power of two, or if `siblings` or `index_bits` do not match the number of levels.
+pub fn compute_root<F, A, CS>(
+    cs: CS,
+    constants: &PoseidonConstants<F, A>,
+    leaf: &AllocatedNum<F>,
+    siblings: &[Vec<AllocatedNum<F>>],
+    index_bits: &[Boolean],
+) -> Result<AllocatedNum<F>, SynthesisError>
+where
+    F: PrimeField,
+    A: Arity<F>,
+    CS: ConstraintSystem<F>,
+{
+    compute_root_domain_separated(cs, constants, constants, leaf, siblings, index_bits)
+}
+
+/// Like [`compute_root`], for a tree built by [`crate::merkle::MerkleTreeBuilder::new_domain_separated`]: the first
+/// hash uses `leaf_constants` and the others `node_constants`.
+pub fn compute_root_domain_separated<F, A, CS>(
+    mut cs: CS,
+    leaf_constants: &PoseidonConstants<F, A>,
+    node_constants: &PoseidonConstants<F, A>,
+    leaf: &AllocatedNum<F>,
+    siblings: &[Vec<AllocatedNum<F>>],
+    index_bits: &[Boolean],
+) -> Result<AllocatedNum<F>, SynthesisError>
+where
+    F: PrimeField,
+    A: Arity<F>,
+    CS: ConstraintSystem<F>,
+{
+    let arity = A::to_usize();
+    assert!(arity.is_power_of_two(), "arity must be a power of two");
+    let bits_per_level = arity.trailing_zeros() as usize;
+    assert_eq!(
+        siblings.len() * bits_per_level,
+        index_bits.len(),
+        "index bits do not match path length"
+    );
+
+    let mut node = leaf.clone();
+    for (level, (level_siblings, position_bits)) in siblings
+        .iter()
+        .zip(index_bits.chunks(bits_per_level.max(1)))
+        .enumerate()
+    {
+        assert_eq!(
+            arity - 1,
+            level_siblings.len(),
+            "malformed Merkle path at level {level}"
+        );
+        let mut cs = cs.namespace(|| format!("level {level}"));
+
+        let preimage = insert_at_position(
+            cs.namespace(|| "preimage"),
+            &node,
+            level_siblings,
+            position_bits,
+        )?;
+        let constants = if level == 0 {
+            leaf_constants
+        } else {
+            node_constants
+        };
+        node = poseidon_hash_allocated(cs.namespace(|| "hash"), preimage, constants)?;
+    }
+
+    Ok(node)
+}
+
+/// Constrain the root computed from `leaf` and its path, as described by [`compute_root`], to equal `root`.
+pub fn verify_path<F, A, CS>(
+    cs: CS,
+    constants: &PoseidonConstants<F, A>,
+    leaf: &AllocatedNum<F>,
+    siblings: &[Vec<AllocatedNum<F>>],
+    index_bits: &[Boolean],
+    root: &AllocatedNum<F>,
+) -> Result<(), SynthesisError>
+where
+    F: PrimeField,
+    A: Arity<F>,
+    CS: ConstraintSystem<F>,
+{
+    verify_path_domain_separated(cs, constants, constants, leaf, siblings, index_bits, root)
+}
+
+/// Like [`verify_path`], for a tree built by [`crate::merkle::MerkleTreeBuilder::new_domain_separated`], as described
+/// by [`compute_root_domain_separated`].
+pub fn verify_path_domain_separated<F, A, CS>(
+    mut cs: CS,
+    leaf_constants: &PoseidonConstants<F, A>,
+    node_constants: &PoseidonConstants<F, A>,
+    leaf: &AllocatedNum<F>,
+    siblings: &[Vec<AllocatedNum<F>>],
+    index_bits: &[Boolean],
+    root: &AllocatedNum<F>,
+) -> Result<(), SynthesisError>
+where
+    F: PrimeField,
+    A: Arity<F>,
+    CS: ConstraintSystem<F>,
+{
+    let computed = compute_root_domain_separated(
+        cs.namespace(|| "compute root"),
+        leaf_constants,
+        node_constants,
+        leaf,
+        siblings,
+        index_bits,
+    )?;
+
+    cs.enforce(
+        || "computed root equals root",
+        |lc| lc + computed.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + root.get_variable(),
+    );
+    Ok(())
+}
+
+/// Returns `siblings` with `node` inserted at the position given by the little-endian `position_bits`.
+///
+/// Each slot `k` of the result holds `node` if the position is `k`, the sibling `k - 1` if the position is less than
+/// `k` and the sibling `k` otherwise. With `eq_k` the (mutually exclusive) position selectors, and `lt_k` the sum of
+/// `eq_j` for `j < k`, this is `s_k + eq_k * (node - s_k) + lt_k * (s_{k-1} - s_k)`, which costs two constraints for
+/// every slot but the first and last, where one of the terms vanishes.
+fn insert_at_position<F, CS>(
+    mut cs: CS,
+    node: &AllocatedNum<F>,
+    siblings: &[AllocatedNum<F>],
+    position_bits: &[Boolean],
+) -> Result<Vec<AllocatedNum<F>>, SynthesisError>
+where
+    F: PrimeField,
+    CS: ConstraintSystem<F>,
+{
+    let arity = siblings.len() + 1;
+
+    let mut selectors = Vec::with_capacity(arity);
+    for k in 0..arity {
+        let mut eq = Boolean::constant(true);
+        for (i, bit) in position_bits.iter().enumerate() {
+            let bit = if (k >> i) & 1 == 1 {
+                bit.clone()
+            } else {
+                bit.not()
+            };
+            eq = Boolean::and(cs.namespace(|| format!("position {k} bit {i}")), &eq, &bit)?;
+        }
+        selectors.push(eq);
+    }
+
+    let position = position_bits
+        .iter()
+        .rev()
+        .try_fold(0, |acc, bit| bit.get_value().map(|b| 2 * acc + b as usize));
+    let value = |k: usize| -> Result<F, SynthesisError> {
+        let position = position.ok_or(SynthesisError::AssignmentMissing)?;
+        let elt = match position.cmp(&k) {
+            core::cmp::Ordering::Equal => node,
+            core::cmp::Ordering::Less => &siblings[k - 1],
+            core::cmp::Ordering::Greater => &siblings[k],
+        };
+        elt.get_value().ok_or(SynthesisError::AssignmentMissing)
+    };
+
+    let one = CS::one();
+    let mut lt = LinearCombination::zero();
+    let mut preimage = Vec::with_capacity(arity);
+    for (k, eq) in selectors.iter().enumerate() {
+        let mut cs = cs.namespace(|| format!("slot {k}"));
+        let slot = AllocatedNum::alloc(cs.namespace(|| "value"), || value(k))?;
+
+        // The last slot is never greater than the position, so its base is the preceding sibling.
+        let (base, previous) = if k == arity - 1 {
+            (&siblings[k - 1], None)
+        } else {
+            (&siblings[k], k.checked_sub(1).map(|j| &siblings[j]))
+        };
+
+        match previous {
+            None => {
+                // slot - base = eq * (node - base)
+                cs.enforce(
+                    || "select",
+                    |_| eq.lc(one, F::ONE),
+                    |lc| lc + node.get_variable() - base.get_variable(),
+                    |lc| lc + slot.get_variable() - base.get_variable(),
+                );
+            }
+            Some(previous) => {
+                let t = AllocatedNum::alloc(cs.namespace(|| "t"), || {
+                    let selected = eq.get_value().ok_or(SynthesisError::AssignmentMissing)?;
+                    let node = node.get_value().ok_or(SynthesisError::AssignmentMissing)?;
+                    let base = base.get_value().ok_or(SynthesisError::AssignmentMissing)?;
+                    Ok(if selected { node - base } else { F::ZERO })
+                })?;
+                // t = eq * (node - base)
+                cs.enforce(
+                    || "node term",
+                    |_| eq.lc(one, F::ONE),
+                    |lc| lc + node.get_variable() - base.get_variable(),
+                    |lc| lc + t.get_variable(),
+                );
+                // slot - base - t = lt * (previous - base)
+                cs.enforce(
+                    || "select",
+                    |_| lt.clone(),
+                    |lc| lc + previous.get_variable() - base.get_variable(),
+                    |lc| lc + slot.get_variable() - base.get_variable() - t.get_variable(),
+                );
+            }
+        }
+
+        lt = lt + &eq.lc(one, F::ONE);
+        preimage.push(slot);
+    }
+
+    Ok(preimage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_type::HashType;
+    use crate::merkle::MerkleTreeBuilder;
+    use crate::poseidon::SimplePoseidonBatchHasher;
+    use crate::Strength;
+    use bellpepper_core::boolean::AllocatedBit;
+    use bellpepper_core::test_cs::TestConstraintSystem;
+    use blstrs::Scalar as Fr;
+    use generic_array::typenum::{U2, U4, U8};
+
+    #[test]
+    fn test_verify_path() {
+        // Full trees, so that all leaves are distinct.
+        test_verify_path_aux::<U2>(8, false);
+        test_verify_path_aux::<U4>(16, false);
+        test_verify_path_aux::<U8>(64, false);
+    }
+
+    #[test]
+    fn test_verify_path_domain_separated() {
+        test_verify_path_aux::<U2>(8, true);
+        test_verify_path_aux::<U4>(16, true);
+    }
+
+    fn test_verify_path_aux<A: Arity<Fr>>(leaf_count: u64, domain_separated: bool) {
+        let (leaf_type, node_type) = if domain_separated {
+            (HashType::MerkleLeaf, HashType::MerkleNode)
+        } else {
+            (HashType::MerkleTree, HashType::MerkleTree)
+        };
+        let leaf_constants = PoseidonConstants::<Fr, A>::new().with_hash_type(leaf_type.clone());
+        let node_constants = PoseidonConstants::<Fr, A>::new().with_hash_type(node_type.clone());
+        let batcher = |hash_type| {
+            SimplePoseidonBatchHasher::<Fr, A>::new_with_strength_and_type(
+                Strength::Standard,
+                hash_type,
+                16,
+            )
+        };
+        let leaves: Vec<Fr> = (0..leaf_count).map(|i| Fr::from(i + 1)).collect();
+        let tree = MerkleTreeBuilder::new_domain_separated(batcher(leaf_type), batcher(node_type))
+            .build(&leaves)
+            .unwrap();
+        let bits_per_level = A::to_usize().trailing_zeros() as usize;
+
+        for index in 0..tree.leaves().len() {
+            let path = tree.path(index).unwrap();
+
+            let synthesize = |index: usize, root: Fr| {
+                let mut cs = TestConstraintSystem::<Fr>::new();
+                let leaf =
+                    AllocatedNum::alloc(cs.namespace(|| "leaf"), || Ok(tree.leaves()[path.index]))
+                        .unwrap();
+                let siblings: Vec<Vec<_>> = path
+                    .siblings
+                    .iter()
+                    .enumerate()
+                    .map(|(level, level_siblings)| {
+                        level_siblings
+                            .iter()
+                            .enumerate()
+                            .map(|(i, x)| {
+                                AllocatedNum::alloc(
+                                    cs.namespace(|| format!("sibling {level} {i}")),
+                                    || Ok(*x),
+                                )
+                                .unwrap()
+                            })
+                            .collect()
+                    })
+                    .collect();
+                let index_bits: Vec<_> = (0..bits_per_level * tree.height())
+                    .map(|i| {
+                        Boolean::from(
+                            AllocatedBit::alloc(
+                                cs.namespace(|| format!("index bit {i}")),
+                                Some((index >> i) & 1 == 1),
+                            )
+                            .unwrap(),
+                        )
+                    })
+                    .collect();
+                let root = AllocatedNum::alloc(cs.namespace(|| "root"), || Ok(root)).unwrap();
+
+                verify_path_domain_separated(
+                    cs.namespace(|| "verify"),
+                    &leaf_constants,
+                    &node_constants,
+                    &leaf,
+                    &siblings,
+                    &index_bits,
+                    &root,
+                )
+                .unwrap();
+                cs.is_satisfied()
+            };
+
+            assert!(synthesize(index, tree.root()), "leaf {index}");
+            assert!(!synthesize(index, tree.root() + Fr::from(1)));
+            assert!(!synthesize(index ^ 1, tree.root()));
+        }
+    }
+}
@@ -1,27 +1,47 @@
 use crate::hash_type::HashType;
 use crate::matrix::{apply_matrix, left_apply_matrix, transpose, Matrix};
 use crate::mds::{
-    create_mds_matrices, derive_mds_matrices, factor_to_sparse_matrixes, generate_mds, MdsMatrices,
-    SparseMatrix,
+    cauchy_mds, check_mds, check_sparse_factorization, create_mds_matrices, derive_mds_matrices,
+    factor_to_sparse_matrixes, generate_mds, MdsCheck, MdsMatrices, SparseMatrix,
 };
-use crate::poseidon_alt::{hash_correct, hash_optimized_dynamic};
+use crate::poseidon_alt::{hash_correct, hash_correct_trace, hash_optimized_dynamic};
 use crate::preprocessing::compress_round_constants;
-use crate::{matrix, quintic_s_box, BatchHasher, Strength, DEFAULT_STRENGTH};
-use crate::{round_constants, round_numbers, Error};
+use crate::sponge::{
+    api::{IOPattern, SpongeOp},
+    vanilla::{Mode, Sponge, SpongeTrait},
+};
+use crate::{
+    default_alpha, is_valid_alpha, matrix, s_box, BatchHasher, Strength, DEFAULT_STRENGTH,
+};
+use crate::{round_constants, round_numbers_with_alpha, Error};
 #[cfg(feature = "abomonation")]
 use abomonation::Abomonation;
 #[cfg(feature = "abomonation")]
 use abomonation_derive::Abomonation;
+use alloc::{vec, vec::Vec};
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
 use ff::PrimeField;
 use generic_array::{sequence::GenericSequence, typenum, ArrayLength, GenericArray};
 use serde::{Deserialize, Serialize};
-use std::marker::PhantomData;
+#[cfg(feature = "std")]
+use std::{
+    any::{Any, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    sync::{Arc, OnceLock, RwLock},
+};
 use typenum::marker_traits::Unsigned;
 use typenum::*;
 
 /// Available arities for the Poseidon hasher.
-pub trait Arity<T>: ArrayLength {
-    /// Must be Arity + 1.
+///
+/// `Arity` is implemented for `U1` through `U36`, so any of those can be used on the CPU and in circuits. The GPU
+/// kernels are only compiled for the arities enabled by the `arity2`, `arity4`, `arity8`, `arity11`, `arity16`,
+/// `arity24` and `arity36` features.
+pub trait Arity<T>: ArrayLength + Send + Sync {
+    /// Must be Arity + 1: the state holds one capacity element besides the rate elements. The batch hashers check
+    /// this at compile time.
     type ConstantsSize: ArrayLength;
 
     fn tag() -> T;
@@ -55,6 +75,18 @@ impl_arity!(
     U22, U23, U24, U25, U26, U27, U28, U29, U30, U31, U32, U33, U34, U35, U36
 );
 
+/// Evaluating [`AssertWidth::OK`] fails to compile unless the width `A::ConstantsSize` of arity `A` is `A + 1`, which
+/// the batch hashers assume when laying out states. Since the check is evaluated on monomorphization, an `Arity`
+/// implementation with a wrong width is rejected as soon as a batch hasher is constructed for it.
+pub(crate) struct AssertWidth<F, A>(PhantomData<(F, A)>);
+
+impl<F: PrimeField, A: Arity<F>> AssertWidth<F, A> {
+    pub(crate) const OK: () = assert!(
+        <A::ConstantsSize as Unsigned>::USIZE == A::USIZE + 1,
+        "the width of an arity must be the arity plus one"
+    );
+}
+
 /// Holds preimage, some utility offsets and counters along with the reference
 /// to [`PoseidonConstants`] required for hashing. [`Poseidon`] is parameterized
 /// by [`ff::PrimeField`] and [`Arity`], which should be similar to [`PoseidonConstants`].
@@ -106,9 +138,39 @@ pub struct PoseidonConstants<F: PrimeField, A: Arity<F>> {
     pub half_full_rounds: usize,
     pub partial_rounds: usize,
     pub hash_type: HashType<F, A>,
+    /// The S-box exponent: the S-box maps `x` to `x^alpha`. See [`PoseidonConstants::new_with_alpha`].
+    pub alpha: u64,
     pub(crate) _a: PhantomData<A>,
 }
 
+/// Hashes the parameters and constants which determine the permutation, each field element by its canonical
+/// representation. The MDS-derived and sparse matrices are omitted, since they are computed from the MDS matrix, so
+/// this agrees with `Eq` while being cheaper.
+impl<F: PrimeField, A: Arity<F>> Hash for PoseidonConstants<F, A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        fn hash_elements<'f, F: PrimeField, H: Hasher>(
+            elements: impl IntoIterator<Item = &'f F>,
+            state: &mut H,
+        ) {
+            for element in elements {
+                element.to_repr().as_ref().hash(state);
+            }
+        }
+
+        A::to_usize().hash(state);
+        self.strength.hash(state);
+        self.full_rounds.hash(state);
+        self.half_full_rounds.hash(state);
+        self.partial_rounds.hash(state);
+        self.alpha.hash(state);
+        hash_elements([&self.domain_tag, &self.hash_type.domain_tag()], state);
+        hash_elements(self.mds_matrices.m.iter().flatten(), state);
+        self.round_constants.is_some().hash(state);
+        hash_elements(self.round_constants.iter().flatten(), state);
+        hash_elements(&self.compressed_round_constants, state);
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum HashMode {
     // The initial and correct version of the algorithm. We should preserve the ability to hash this way for reference
@@ -160,6 +222,11 @@ where
     /// Instantiated [`PoseidonConstants`] still calculates internal constants based on [`Arity`], but calculation of
     /// [`HashType::domain_tag`] is based on input `length`.
     ///
+    /// For a single permutation, `length` must be at most [`Arity`], which [`Poseidon::new_with_preimage`] checks
+    /// against the preimage; constants for the same [`Arity`] but different lengths produce different digests, even for
+    /// the same zero-padded state. Longer lengths are only meaningful for simplex sponges hashing `length` elements, see
+    /// [`SpongeTrait::simplex_constants`], so they are not rejected here.
+    ///
     /// # Example
     ///
     /// ```
@@ -200,7 +267,7 @@ where
     /// assert_eq!(constants.hash_type, HashType::<Fp, U8>::ConstantLength(preimage_length - 2));
     /// ```
     pub fn with_length(&self, length: usize) -> Self {
-        let arity = A::to_usize();
+        let arity = self.arity();
         assert!(length <= arity);
 
         let hash_type = match self.hash_type {
@@ -217,6 +284,50 @@ where
         }
     }
 
+    /// Creates new instance of [`PoseidonConstants`] from already defined one with a different domain separation
+    /// ([`HashType`]). Round constants and matrices do not depend on the [`HashType`], so they are reused as is.
+    ///
+    /// Which [`HashType`] variants are valid depends on how the constants will be used:
+    ///
+    /// - [`HashType::MerkleTree`], [`HashType::MerkleLeaf`] and [`HashType::MerkleNode`] require a preimage of exactly
+    ///   [`Arity`] elements.
+    /// - [`HashType::ConstantLength`] requires a length of at most [`Arity`], which the preimage must match.
+    /// - [`HashType::Encryption`], [`HashType::Custom`] and [`HashType::Sponge`] are valid for any [`Arity`].
+    /// - [`HashType::MerkleTreeSparse`] and [`HashType::VariableLength`] are not yet supported.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hash_type` is not supported, or is a [`HashType::ConstantLength`] longer than [`Arity`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use neptune::poseidon::PoseidonConstants;
+    /// use neptune::hash_type::HashType;
+    /// use pasta_curves::Fp;
+    /// use generic_array::typenum::U4;
+    ///
+    /// let constants: PoseidonConstants<Fp, U4> = PoseidonConstants::new();
+    /// let constants = constants.with_hash_type(HashType::ConstantLength(3));
+    ///
+    /// assert_eq!(constants.hash_type, HashType::<Fp, U4>::ConstantLength(3));
+    /// assert_eq!(constants.domain_tag, constants.hash_type.domain_tag());
+    /// ```
+    pub fn with_hash_type(&self, hash_type: HashType<F, A>) -> Self {
+        assert!(hash_type.is_supported());
+        if let HashType::ConstantLength(length) = hash_type {
+            assert!(length <= self.arity());
+        }
+
+        let domain_tag = hash_type.domain_tag();
+
+        Self {
+            hash_type,
+            domain_tag,
+            ..self.clone()
+        }
+    }
+
     /// Generates new instance of [`PoseidonConstants`] suitable for both optimized / non-optimized hashing
     /// with Merkle Tree (where all leafs are presented) domain separation ([`HashType`]) custom security level ([`Strength`]).
     ///
@@ -259,27 +370,219 @@ where
     /// assert_eq!(constants.hash_type, HashType::Encryption);
     /// ```
     pub fn new_with_strength_and_type(strength: Strength, hash_type: HashType<F, A>) -> Self {
+        Self::new_with_alpha(strength, hash_type, None)
+    }
+
+    /// Generates new instance of [`PoseidonConstants`] like [`PoseidonConstants::new_with_strength_and_type`], with
+    /// the S-box x^`alpha`.
+    ///
+    /// If `alpha` is `None`, the smallest valid exponent for the field is used, as returned by
+    /// [`crate::default_alpha`]: an exponent is valid if it is at least 3 and coprime to `p - 1`, for the field modulus
+    /// `p`. This is 5 for the BLS12-381 scalar field and the Pasta fields, so they use the quintic S-box by default.
+    /// Round numbers are computed for the chosen exponent, unless `strength` is [`Strength::Custom`].
+    ///
+    /// All other constructors use the default exponent. The GPU only supports the quintic S-box.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `alpha` is not a valid exponent for the field.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use neptune::poseidon::PoseidonConstants;
+    /// use neptune::Strength;
+    /// use neptune::hash_type::HashType;
+    /// use pasta_curves::Fp;
+    /// use generic_array::typenum::U2;
+    ///
+    /// let constants: PoseidonConstants<Fp, U2> = PoseidonConstants::new_with_alpha(Strength::Standard, HashType::MerkleTree, None);
+    /// assert_eq!(constants.alpha, 5);
+    ///
+    /// let constants: PoseidonConstants<Fp, U2> = PoseidonConstants::new_with_alpha(Strength::Standard, HashType::MerkleTree, Some(7));
+    /// assert_eq!(constants.alpha, 7);
+    /// ```
+    pub fn new_with_alpha(
+        strength: Strength,
+        hash_type: HashType<F, A>,
+        alpha: Option<u64>,
+    ) -> Self {
+        assert!(hash_type.is_supported());
+        let alpha = alpha.unwrap_or_else(default_alpha::<F>);
+        assert!(
+            is_valid_alpha::<F>(alpha),
+            "x^{alpha} is not a valid S-box for this field"
+        );
+        Self::new_with_alpha_and_mds(strength, hash_type, alpha, generate_mds(A::to_usize() + 1))
+    }
+
+    /// Generates new instance of [`PoseidonConstants`] like [`PoseidonConstants::new_with_strength_and_type`], with the
+    /// MDS matrix [`cauchy_mds`]`(xs, ys)` rather than the default one. This is a fallback for fields where the default
+    /// points are unsuitable, and a way to cross-check the default construction. The constants are checked with
+    /// [`PoseidonConstants::validate_mds`].
+    ///
+    /// Returns an error if `xs` and `ys` do not each have `A + 1` elements, or do not define an MDS matrix.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use neptune::poseidon::PoseidonConstants;
+    /// use neptune::Strength;
+    /// use neptune::hash_type::HashType;
+    /// use pasta_curves::Fp;
+    /// use generic_array::typenum::U2;
+    ///
+    /// let xs = [1, 2, 3].map(Fp::from);
+    /// let ys = [4, 5, 6].map(Fp::from);
+    /// let constants: PoseidonConstants<Fp, U2> = PoseidonConstants::new_with_cauchy_mds(
+    ///     Strength::Standard,
+    ///     HashType::MerkleTree,
+    ///     &xs,
+    ///     &ys,
+    /// )
+    /// .unwrap();
+    ///
+    /// assert_ne!(constants, PoseidonConstants::new());
+    /// ```
+    pub fn new_with_cauchy_mds(
+        strength: Strength,
+        hash_type: HashType<F, A>,
+        xs: &[F],
+        ys: &[F],
+    ) -> Result<Self, Error> {
+        let width = A::to_usize() + 1;
+        for values in [xs, ys] {
+            if values.len() != width {
+                return Err(Error::WidthMismatch {
+                    expected: width,
+                    got: values.len(),
+                });
+            }
+        }
         assert!(hash_type.is_supported());
+        let constants = Self::new_with_alpha_and_mds(
+            strength,
+            hash_type,
+            default_alpha::<F>(),
+            cauchy_mds(xs, ys)?,
+        );
+        constants.validate_mds()?;
+        Ok(constants)
+    }
+
+    /// Generates new instance of [`PoseidonConstants`] from externally generated round constants and MDS matrix, e.g.
+    /// those mandated by a protocol's parameter file, rather than generating them. The numbers of rounds are those of
+    /// `strength` for the S-box `x^5` or whichever [`default_alpha`](crate::default_alpha) is for the field, and the
+    /// hash type is [`HashType::MerkleTree`]: use [`PoseidonConstants::with_hash_type`] for another one. The arity is
+    /// `A`. The sparse matrices and compressed round constants of the optimized hash are derived from the given
+    /// parameters, and the constants are checked with [`PoseidonConstants::validate_mds`].
+    ///
+    /// `round_constants` holds the constants of each round in turn, as they are added in
+    /// [`PoseidonConstants::permute`], and `mds` is applied as described there.
+    ///
+    /// Returns an error if `mds` is not a `width` by `width` MDS matrix, as far as [`PoseidonConstants::validate_mds`]
+    /// can tell, if `round_constants` does not hold exactly `width` constants for each round, or if `strength` is an
+    /// invalid custom strength.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use neptune::poseidon::PoseidonConstants;
+    /// use neptune::Strength;
+    /// use pasta_curves::Fp;
+    /// use generic_array::typenum::U2;
+    ///
+    /// let generated: PoseidonConstants<Fp, U2> = PoseidonConstants::new();
+    /// let imported = PoseidonConstants::from_parts(
+    ///     generated.round_constants.clone().unwrap(),
+    ///     generated.mds_matrices.m.clone(),
+    ///     Strength::Standard,
+    /// )
+    /// .unwrap();
+    ///
+    /// assert_eq!(generated, imported);
+    /// ```
+    pub fn from_parts(
+        round_constants: Vec<F>,
+        mds: Matrix<F>,
+        strength: Strength,
+    ) -> Result<Self, Error> {
+        if let Strength::Custom {
+            full_rounds,
+            partial_rounds,
+        } = strength
+        {
+            Strength::custom(full_rounds, partial_rounds)?;
+        }
         let arity = A::to_usize();
         let width = arity + 1;
-        let mds = generate_mds(width);
-        let (full_rounds, partial_rounds) = round_numbers(arity, &strength);
-        let round_constants = round_constants(arity, &strength);
+        if mds.len() != width {
+            return Err(Error::WidthMismatch {
+                expected: width,
+                got: mds.len(),
+            });
+        }
+        check_mds(&mds)?;
+
+        let alpha = default_alpha::<F>();
+        let (full_rounds, partial_rounds) = round_numbers_with_alpha(arity, &strength, alpha);
+        let expected = width * (full_rounds + partial_rounds);
+        if round_constants.len() != expected {
+            return Err(Error::Other(alloc::format!(
+                "expected {expected} round constants for {full_rounds} full and {partial_rounds} partial rounds, got {}",
+                round_constants.len()
+            )));
+        }
 
-        // Now call new_from_parameters with all the necessary parameters.
-        Self::new_from_parameters(
+        let constants = Self::new_from_parameters(
             width,
             mds,
             round_constants,
             full_rounds,
             partial_rounds,
-            hash_type,
+            HashType::MerkleTree,
             strength,
-        )
+        );
+        constants.validate_mds()?;
+        Ok(constants)
+    }
+
+    fn new_with_alpha_and_mds(
+        strength: Strength,
+        hash_type: HashType<F, A>,
+        alpha: u64,
+        mds: Matrix<F>,
+    ) -> Self {
+        if let Strength::Custom {
+            full_rounds,
+            partial_rounds,
+        } = strength
+        {
+            Strength::custom(full_rounds, partial_rounds).expect("invalid custom strength");
+        }
+        let arity = A::to_usize();
+        let width = arity + 1;
+        let (full_rounds, partial_rounds) = round_numbers_with_alpha(arity, &strength, alpha);
+        let round_constants = round_constants(arity, &strength, alpha);
+
+        // Now call new_from_parameters with all the necessary parameters.
+        Self {
+            alpha,
+            ..Self::new_from_parameters(
+                width,
+                mds,
+                round_constants,
+                full_rounds,
+                partial_rounds,
+                hash_type,
+                strength,
+            )
+        }
     }
 
     /// Generates new instance of [`PoseidonConstants`] with matrix, constants and number of rounds.
-    /// The matrix does not have to be symmetric.
+    /// The matrix does not have to be symmetric. The S-box exponent is the default one for the field, see
+    /// [`PoseidonConstants::new_with_alpha`].
     pub fn new_from_parameters(
         width: usize,
         m: Matrix<F>,
@@ -326,6 +629,7 @@ where
             half_full_rounds,
             partial_rounds,
             hash_type,
+            alpha: default_alpha::<F>(),
             _a: PhantomData::<A>,
         }
     }
@@ -344,8 +648,8 @@ where
     /// assert_eq!(constants.arity(), 8usize);
     /// ```
     #[inline]
-    pub fn arity(&self) -> usize {
-        A::to_usize()
+    pub const fn arity(&self) -> usize {
+        A::USIZE
     }
 
     /// Returns `width` value represented as `usize`. It equals to [`Arity`] + 1.
@@ -362,8 +666,135 @@ where
     /// assert_eq!(constants.width(), 8 + 1);
     /// ```
     #[inline]
-    pub fn width(&self) -> usize {
-        A::ConstantsSize::to_usize()
+    pub const fn width(&self) -> usize {
+        A::ConstantsSize::USIZE
+    }
+
+    /// Returns the number of state elements which do not hold preimage data, i.e. `width - arity`. It is always 1,
+    /// the element holding the domain tag.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use neptune::poseidon::PoseidonConstants;
+    /// use pasta_curves::Fp;
+    /// use generic_array::typenum::U8;
+    ///
+    /// let constants: PoseidonConstants<Fp, U8> = PoseidonConstants::new();
+    ///
+    /// assert_eq!(constants.capacity(), constants.width() - constants.arity());
+    /// ```
+    #[inline]
+    pub const fn capacity(&self) -> usize {
+        self.width() - self.arity()
+    }
+
+    /// Returns the numbers of full and partial rounds of the permutation, `(full_rounds, partial_rounds)`. These are
+    /// the numbers which [`round_numbers_for`](crate::round_numbers_for) computes without generating constants.
+    ///
+    /// ```
+    /// use neptune::poseidon::PoseidonConstants;
+    /// use neptune::{round_numbers_for, Strength};
+    /// use pasta_curves::Fp;
+    /// use generic_array::typenum::U4;
+    ///
+    /// let constants: PoseidonConstants<Fp, U4> = PoseidonConstants::new();
+    ///
+    /// assert_eq!(constants.round_numbers(), (8, 56));
+    /// assert_eq!(constants.round_numbers(), round_numbers_for::<Fp, U4>(&Strength::Standard));
+    /// ```
+    pub fn round_numbers(&self) -> (usize, usize) {
+        (self.full_rounds, self.partial_rounds)
+    }
+
+    /// Checks that the MDS matrix really is MDS (all of its square submatrices are invertible), and that the sparse
+    /// matrices used by the optimized partial rounds are equivalent to applying it. The error identifies the first
+    /// singular submatrix or mismatching partial round found. See [`crate::mds`] for the matrices involved.
+    ///
+    /// Returns [`MdsCheck::Unverified`] for a non-Cauchy matrix too wide for every square submatrix to be checked.
+    pub fn validate_mds(&self) -> Result<MdsCheck, Error> {
+        let check = check_mds(&self.mds_matrices.m)?;
+        // Like the sparse matrices, the dense matrix is applied to the state as a row vector.
+        check_sparse_factorization(
+            &transpose(&self.mds_matrices.m),
+            &self.pre_sparse_matrix,
+            &self.sparse_matrixes,
+        )?;
+        Ok(check)
+    }
+
+    /// Applies the Poseidon permutation to a full-width `state` in place, bypassing domain separation and any
+    /// sponge logic. This is the same permutation (including the sparse matrix optimization) that [`Poseidon::hash`]
+    /// applies to its buffer of the domain tag followed by the preimage.
+    ///
+    /// The permutation consists of `full_rounds / 2` full rounds, followed by `partial_rounds` partial rounds,
+    /// followed by another `full_rounds / 2` full rounds. Round `r` (counting from zero across all rounds) does the
+    /// following, where `width` is [`PoseidonConstants::width`]:
+    ///
+    /// 1. Add `round_constants[r * width + i]` to `state[i]`, for every `i`.
+    /// 2. Replace each element `x` of `state` with `x^alpha` in a full round, or only `state[0]` in a partial round.
+    /// 3. Replace `state` with the matrix-vector product `m * state`, i.e. `state[i] = sum_j m[i][j] * state[j]`,
+    ///    where `m` is `mds_matrices.m`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `state` does not have exactly `width` elements.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use neptune::poseidon::PoseidonConstants;
+    /// use neptune::poseidon::Poseidon;
+    /// use pasta_curves::Fp;
+    /// use generic_array::typenum::U2;
+    ///
+    /// let constants: PoseidonConstants<Fp, U2> = PoseidonConstants::new();
+    /// let preimage = [Fp::from(1), Fp::from(2)];
+    ///
+    /// let mut state = [constants.domain_tag, preimage[0], preimage[1]];
+    /// constants.permute(&mut state);
+    ///
+    /// assert_eq!(state[1], Poseidon::new_with_preimage(&preimage, &constants).hash());
+    /// ```
+    pub fn permute(&self, state: &mut [F]) {
+        assert_eq!(
+            self.width(),
+            state.len(),
+            "state must have exactly `width` elements"
+        );
+
+        let mut poseidon = Poseidon::new(self);
+        poseidon.elements.copy_from_slice(state);
+        poseidon.hash();
+        state.copy_from_slice(&poseidon.elements);
+    }
+
+    /// Checks that a preimage of `len` elements fills exactly [`Arity`] elements, as required to hash it with these
+    /// constants. Returns [`Error::ArityMismatch`] otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use neptune::poseidon::PoseidonConstants;
+    /// use neptune::Error;
+    /// use pasta_curves::Fp;
+    /// use generic_array::typenum::U8;
+    ///
+    /// let constants: PoseidonConstants<Fp, U8> = PoseidonConstants::new();
+    ///
+    /// assert!(constants.validate_preimage_len(8).is_ok());
+    /// assert!(matches!(
+    ///     constants.validate_preimage_len(7),
+    ///     Err(Error::ArityMismatch { expected: 8, got: 7 })
+    /// ));
+    /// ```
+    pub fn validate_preimage_len(&self, len: usize) -> Result<(), Error> {
+        let expected = self.arity();
+        if len == expected {
+            Ok(())
+        } else {
+            Err(Error::ArityMismatch { expected, got: len })
+        }
     }
 }
 
@@ -377,6 +808,117 @@ where
     }
 }
 
+#[cfg(feature = "std")]
+type ConstantsMap = HashMap<(TypeId, TypeId, Strength), Arc<dyn Any + Send + Sync>>;
+#[cfg(feature = "std")]
+type ConstantsCache = RwLock<ConstantsMap>;
+
+/// Returns the [`PoseidonConstants`] (as created by [`PoseidonConstants::new_with_strength`]) for the field `F`,
+/// arity `A` and `strength`. Constants are generated on first use, then memoized process-wide, so later calls
+/// with the same parameters only clone an [`Arc`].
+///
+/// Constants are generated without holding the cache's lock, so a slow generation does not block lookups of other
+/// parameters. Threads which miss on the same parameters at once may each generate them, but all receive the `Arc`
+/// which was cached first.
+///
+/// # Example
+///
+/// ```
+/// use neptune::poseidon::{constants_for, PoseidonConstants};
+/// use neptune::Strength;
+/// use pasta_curves::Fp;
+/// use generic_array::typenum::U2;
+///
+/// let constants = constants_for::<Fp, U2>(Strength::Standard);
+///
+/// assert_eq!(*constants, PoseidonConstants::new());
+/// assert!(std::sync::Arc::ptr_eq(&constants, &constants_for(Strength::Standard)));
+/// ```
+#[cfg(feature = "std")]
+pub fn constants_for<F, A>(strength: Strength) -> Arc<PoseidonConstants<F, A>>
+where
+    F: PrimeField,
+    A: Arity<F> + Send + Sync + 'static,
+{
+    static CACHE: OnceLock<ConstantsCache> = OnceLock::new();
+
+    let cache = CACHE.get_or_init(Default::default);
+    let key = (TypeId::of::<F>(), TypeId::of::<A>(), strength);
+    let cached = cache
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&key)
+        .cloned();
+    let constants = match cached {
+        Some(constants) => constants,
+        None => {
+            let generated = Arc::new(PoseidonConstants::<F, A>::new_with_strength(strength));
+            cache
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(key)
+                .or_insert(generated)
+                .clone()
+        }
+    };
+
+    // Entries are keyed by the `TypeId`s of `F` and `A`, so the downcast cannot fail.
+    constants.downcast().unwrap()
+}
+
+/// Calls `f` with the constants [`constants_for`] returns, looking them up in a copy of its cache private to the
+/// current thread, so that after a thread's first call no lock is taken and no reference count is touched.
+#[cfg(feature = "std")]
+fn with_cached_constants<F, A, R>(
+    strength: Strength,
+    f: impl FnOnce(&PoseidonConstants<F, A>) -> R,
+) -> R
+where
+    F: PrimeField,
+    A: Arity<F> + Send + Sync + 'static,
+{
+    thread_local! {
+        static LOCAL: RefCell<ConstantsMap> = RefCell::default();
+    }
+
+    let key = (TypeId::of::<F>(), TypeId::of::<A>(), strength);
+    LOCAL.with(|local| {
+        let mut local = local.borrow_mut();
+        let constants = local
+            .entry(key)
+            .or_insert_with(|| constants_for::<F, A>(strength) as Arc<dyn Any + Send + Sync>);
+        // Entries are keyed by the `TypeId`s of `F` and `A`, so the downcast cannot fail.
+        f(constants.downcast_ref().unwrap())
+    })
+}
+
+/// Hashes a preimage of arity 2 with the standard-strength Merkle tree constants, which are generated once and
+/// cached by [`constants_for`]. Each thread keeps its own reference to them, so concurrent calls do not contend.
+#[cfg(feature = "std")]
+pub fn hash2<F: PrimeField>(a: F, b: F) -> F {
+    with_cached_constants::<F, U2, _>(DEFAULT_STRENGTH, |constants| {
+        Poseidon::new_with_preimage(&[a, b], constants).hash()
+    })
+}
+
+/// Hashes a preimage of arity 4 with the standard-strength Merkle tree constants, which are generated once and
+/// cached by [`constants_for`]. Each thread keeps its own reference to them, so concurrent calls do not contend.
+#[cfg(feature = "std")]
+pub fn hash4<F: PrimeField>(preimage: [F; 4]) -> F {
+    with_cached_constants::<F, U4, _>(DEFAULT_STRENGTH, |constants| {
+        Poseidon::new_with_preimage(&preimage, constants).hash()
+    })
+}
+
+/// Hashes a preimage of arity 8 with the standard-strength Merkle tree constants, which are generated once and
+/// cached by [`constants_for`]. Each thread keeps its own reference to them, so concurrent calls do not contend.
+#[cfg(feature = "std")]
+pub fn hash8<F: PrimeField>(preimage: [F; 8]) -> F {
+    with_cached_constants::<F, U8, _>(DEFAULT_STRENGTH, |constants| {
+        Poseidon::new_with_preimage(&preimage, constants).hash()
+    })
+}
+
 impl<'a, F, A> Poseidon<'a, F, A>
 where
     F: PrimeField,
@@ -467,7 +1009,9 @@ where
             }
             HashType::VariableLength => panic!("variable-length hashes are not yet supported."),
             _ => {
-                assert_eq!(preimage.len(), A::to_usize(), "Invalid preimage size");
+                if let Err(e) = constants.validate_preimage_len(preimage.len()) {
+                    panic!("Invalid preimage size: {e}");
+                }
 
                 GenericArray::generate(|i| {
                     if i == 0 {
@@ -490,50 +1034,125 @@ where
         }
     }
 
-    /// Replaces the elements with the provided optional items.
-    ///
-    /// # Panics
+    /// Hashes `preimage` using `constants` with their domain separation replaced by `hash_type`. See
+    /// [`PoseidonConstants::with_hash_type`] for which [`HashType`] variants are valid.
     ///
-    /// Panics if the provided slice is not equal to the arity.
+    /// This derives a new set of constants on every call; when hashing repeatedly with the same [`HashType`], derive
+    /// them once with [`PoseidonConstants::with_hash_type`] and use [`Poseidon::new_with_preimage`] instead.
     ///
     /// # Example
     ///
     /// ```
     /// use neptune::poseidon::PoseidonConstants;
     /// use neptune::poseidon::Poseidon;
+    /// use neptune::hash_type::HashType;
     /// use pasta_curves::Fp;
-    /// use ff::Field;
     /// use generic_array::typenum::U2;
     ///
-    /// let constants: PoseidonConstants<Fp, U2> = PoseidonConstants::new_constant_length(1);
-    /// let preimage = vec![Fp::from(u64::MAX)];
-    ///
-    /// let mut poseidon = Poseidon::<Fp, U2>::new_with_preimage(&preimage, &constants);
+    /// let constants: PoseidonConstants<Fp, U2> = PoseidonConstants::new();
+    /// let preimage = [Fp::from(1), Fp::from(2)];
     ///
-    /// assert_eq!(poseidon.elements.len(), constants.width());
-    /// assert_eq!(poseidon.elements[1], Fp::from(u64::MAX));
-    /// assert_eq!(poseidon.elements[2], Fp::ZERO);
+    /// let digest = Poseidon::hash_with_domain(&preimage, &constants, HashType::ConstantLength(2));
+    /// let constant_length: PoseidonConstants<Fp, U2> = PoseidonConstants::new_constant_length(2);
     ///
-    /// let preimage = vec![Fp::from(u64::MIN), Fp::from(u64::MIN)];
-    /// poseidon.set_preimage(&preimage);
-    /// assert_eq!(poseidon.elements.len(), constants.width());
-    /// assert_eq!(poseidon.elements[1], Fp::from(u64::MIN)); // Now it's u64::MIN
-    /// assert_eq!(poseidon.elements[2], Fp::from(u64::MIN)); // Now it's u64::MIN
+    /// assert_eq!(digest, Poseidon::new_with_preimage(&preimage, &constant_length).hash());
     /// ```
-    pub fn set_preimage(&mut self, preimage: &[F]) {
-        self.reset();
-        self.elements[1..].copy_from_slice(preimage);
-        self.pos = self.elements.len();
-    }
-
-    /// Restore the initial state
-    pub fn reset(&mut self) {
-        self.reset_offsets();
-        self.elements[1..].iter_mut().for_each(|l| *l = F::ZERO);
-        self.elements[0] = self.constants.domain_tag;
+    pub fn hash_with_domain(
+        preimage: &[F],
+        constants: &PoseidonConstants<F, A>,
+        hash_type: HashType<F, A>,
+    ) -> F {
+        let constants = constants.with_hash_type(hash_type);
+        Poseidon::new_with_preimage(preimage, &constants).hash()
     }
 
-    pub(crate) fn reset_offsets(&mut self) {
+    /// Hashes between one and [`Arity`] `inputs`, with the unused preimage elements set to zero and the domain
+    /// separation [`HashType::ConstantLength`]`(inputs.len())`, i.e. as if by [`Poseidon::hash_with_domain`]. Since the
+    /// length is part of the domain tag, inputs of different lengths produce different digests even when one is the
+    /// other followed by zeros: `[a]` and `[a, 0]` do not collide, as they would if the inputs were merely zero-padded
+    /// to [`Arity`] elements.
+    ///
+    /// Returns [`Error::ArityMismatch`] if `inputs` is empty or longer than [`Arity`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use neptune::poseidon::PoseidonConstants;
+    /// use neptune::poseidon::Poseidon;
+    /// use pasta_curves::Fp;
+    /// use generic_array::typenum::U8;
+    ///
+    /// let constants: PoseidonConstants<Fp, U8> = PoseidonConstants::new();
+    /// let a = Fp::from(7);
+    ///
+    /// assert_ne!(
+    ///     Poseidon::hash_padded(&[a], &constants).unwrap(),
+    ///     Poseidon::hash_padded(&[a, Fp::from(0)], &constants).unwrap()
+    /// );
+    /// assert!(Poseidon::hash_padded(&[a; 9], &constants).is_err());
+    /// ```
+    pub fn hash_padded(inputs: &[F], constants: &PoseidonConstants<F, A>) -> Result<F, Error> {
+        let arity = constants.arity();
+        if inputs.is_empty() || inputs.len() > arity {
+            return Err(Error::ArityMismatch {
+                expected: arity,
+                got: inputs.len(),
+            });
+        }
+        Ok(Self::hash_with_domain(
+            inputs,
+            constants,
+            HashType::ConstantLength(inputs.len()),
+        ))
+    }
+
+    /// Replaces the elements with the provided optional items.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the provided slice is not equal to the arity.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use neptune::poseidon::PoseidonConstants;
+    /// use neptune::poseidon::Poseidon;
+    /// use pasta_curves::Fp;
+    /// use ff::Field;
+    /// use generic_array::typenum::U2;
+    ///
+    /// let constants: PoseidonConstants<Fp, U2> = PoseidonConstants::new_constant_length(1);
+    /// let preimage = vec![Fp::from(u64::MAX)];
+    ///
+    /// let mut poseidon = Poseidon::<Fp, U2>::new_with_preimage(&preimage, &constants);
+    ///
+    /// assert_eq!(poseidon.elements.len(), constants.width());
+    /// assert_eq!(poseidon.elements[1], Fp::from(u64::MAX));
+    /// assert_eq!(poseidon.elements[2], Fp::ZERO);
+    ///
+    /// let preimage = vec![Fp::from(u64::MIN), Fp::from(u64::MIN)];
+    /// poseidon.set_preimage(&preimage);
+    /// assert_eq!(poseidon.elements.len(), constants.width());
+    /// assert_eq!(poseidon.elements[1], Fp::from(u64::MIN)); // Now it's u64::MIN
+    /// assert_eq!(poseidon.elements[2], Fp::from(u64::MIN)); // Now it's u64::MIN
+    /// ```
+    pub fn set_preimage(&mut self, preimage: &[F]) {
+        if let Err(e) = self.constants.validate_preimage_len(preimage.len()) {
+            panic!("Invalid preimage size: {e}");
+        }
+        self.reset();
+        self.elements[1..].copy_from_slice(preimage);
+        self.pos = self.elements.len();
+    }
+
+    /// Restore the initial state
+    pub fn reset(&mut self) {
+        self.reset_offsets();
+        self.elements[1..].iter_mut().for_each(|l| *l = F::ZERO);
+        self.elements[0] = self.constants.domain_tag;
+    }
+
+    pub(crate) fn reset_offsets(&mut self) {
         self.constants_offset = 0;
         self.current_round = 0;
         self.pos = 1;
@@ -620,6 +1239,17 @@ where
         res
     }
 
+    /// Hashes as in [`HashMode::Correct`], returning the full state after each step of each round, e.g. to pinpoint
+    /// where an implementation diverges from reference test vectors. Each round contributes three states, in order:
+    /// after adding the round constants, after the S-box and after the MDS multiplication. The last state is thus the
+    /// final one, whose element 1 is the digest. This is much slower than [`Poseidon::hash`], and panics if the
+    /// constants' uncompressed `round_constants` have been dropped.
+    pub fn hash_trace(&mut self) -> Vec<Vec<F>> {
+        let trace = hash_correct_trace(self);
+        self.reset_offsets();
+        trace
+    }
+
     /// Performs hashing using underlying [`Poseidon`] buffer of the preimage' field elements
     /// in default (optimized) mode. Always outputs digest expressed as a single field element
     /// of concrete type specified upon [`PoseidonConstants`] and [`Poseidon`] instantiations.
@@ -647,6 +1277,51 @@ where
         self.hash_in_mode(DEFAULT_HASH_MODE)
     }
 
+    /// Hashes an arbitrary byte string using the underlying [`PoseidonConstants`] in a simplex sponge.
+    /// The underlying buffer of preimage elements is left untouched.
+    ///
+    /// Bytes are packed into field elements as follows, so that independent callers agree on the digest:
+    ///
+    /// 1. The message is the length of `bytes` as a little-endian `u64`, followed by `bytes`, followed by a
+    ///    single `0x01` byte, followed by as many `0x00` bytes as needed to make its length a multiple of
+    ///    `F::CAPACITY / 8` (the chunk size, e.g. 31 bytes for 255-bit fields).
+    /// 2. Each chunk is interpreted as a little-endian integer, which is always smaller than the modulus.
+    /// 3. The resulting `n` elements are absorbed using the [`IOPattern`] `[Absorb(n), Squeeze(1)]` with no
+    ///    domain separator, and the single squeezed element is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use neptune::poseidon::PoseidonConstants;
+    /// use neptune::poseidon::Poseidon;
+    /// use pasta_curves::Fp;
+    /// use generic_array::typenum::U2;
+    ///
+    /// let constants: PoseidonConstants<Fp, U2> = PoseidonConstants::new();
+    /// let poseidon = Poseidon::<Fp, U2>::new(&constants);
+    ///
+    /// assert_eq!(poseidon.hash_bytes(b"neptune"), poseidon.hash_bytes(b"neptune"));
+    /// assert_ne!(poseidon.hash_bytes(b""), poseidon.hash_bytes(&[0]));
+    /// ```
+    pub fn hash_bytes(&self, bytes: &[u8]) -> F {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let elements = pack_bytes::<F>(bytes);
+
+        let acc = &mut ();
+        let mut sponge = Sponge::new_with_constants(self.constants, Mode::Simplex);
+        let pattern = IOPattern(vec![
+            SpongeOp::Absorb(elements.len() as u32),
+            SpongeOp::Squeeze(1),
+        ]);
+        SpongeAPI::start(&mut sponge, pattern, DomainSeparator::default(), acc);
+        SpongeAPI::absorb(&mut sponge, elements.len() as u32, &elements, acc);
+        let digest = SpongeAPI::squeeze(&mut sponge, 1, acc)[0];
+        SpongeAPI::finish(&mut sponge, acc).unwrap();
+
+        digest
+    }
+
     pub(crate) fn apply_padding(&mut self) {
         if let HashType::ConstantLength(l) = self.constants.hash_type {
             let final_pos = 1 + (l % self.constants.arity());
@@ -757,6 +1432,7 @@ where
     }
 
     fn full_round(&mut self, last_round: bool) {
+        let alpha = self.constants.alpha;
         let to_take = self.elements.len();
         let post_round_keys = self
             .constants
@@ -787,13 +1463,13 @@ where
                 } else {
                     Some(post)
                 };
-                quintic_s_box(l, None, post_key);
+                s_box(alpha, l, None, post_key);
             });
         // We need this because post_round_keys will have been empty, so it didn't happen in the for_each. :(
         if last_round {
             self.elements
                 .iter_mut()
-                .for_each(|l| quintic_s_box(l, None, None));
+                .for_each(|l| s_box(alpha, l, None, None));
         } else {
             self.constants_offset += self.elements.len();
         }
@@ -804,8 +1480,13 @@ where
     fn partial_round(&mut self) {
         let post_round_key = self.constants.compressed_round_constants[self.constants_offset];
 
-        // Apply the quintic S-Box to the first element
-        quintic_s_box(&mut self.elements[0], None, Some(&post_round_key));
+        // Apply the S-Box to the first element
+        s_box(
+            self.constants.alpha,
+            &mut self.elements[0],
+            None,
+            Some(&post_round_key),
+        );
         self.constants_offset += 1;
 
         self.round_product_mds();
@@ -868,12 +1549,12 @@ where
             }
         }
 
-        let _ = std::mem::replace(&mut self.elements, result);
+        let _ = core::mem::replace(&mut self.elements, result);
     }
 
     pub(crate) fn product_mds_with_matrix_left(&mut self, matrix: &Matrix<F>) {
         let result = left_apply_matrix(matrix, &self.elements);
-        let _ = std::mem::replace(
+        let _ = core::mem::replace(
             &mut self.elements,
             GenericArray::<F, A::ConstantsSize>::generate(|i| result[i]),
         );
@@ -881,30 +1562,251 @@ where
 
     // Sparse matrix in this context means one of the form, M''.
     fn product_mds_with_sparse_matrix(&mut self, sparse_matrix: &SparseMatrix<F>) {
-        let mut result = GenericArray::<F, A::ConstantsSize>::generate(|_| F::ZERO);
+        sparse_matrix.apply(&mut self.elements);
+    }
 
-        // First column is dense.
-        for (i, val) in sparse_matrix.w_hat.iter().enumerate() {
-            let mut tmp = *val;
-            tmp.mul_assign(&self.elements[i]);
-            result[0].add_assign(&tmp);
+    #[cfg(feature = "std")]
+    pub(crate) fn debug(&self, msg: &str) {
+        dbg!(msg, &self.constants_offset, &self.elements);
+    }
+}
+
+/// Packs `bytes` into field elements, as documented by [`Poseidon::hash_bytes`].
+fn pack_bytes<F: PrimeField>(bytes: &[u8]) -> Vec<F> {
+    let chunk_size = F::CAPACITY as usize / 8;
+
+    let mut message = Vec::with_capacity(bytes.len() + 8 + chunk_size);
+    message.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    message.extend_from_slice(bytes);
+    message.push(1);
+    while message.len() % chunk_size != 0 {
+        message.push(0);
+    }
+
+    message.chunks(chunk_size).map(pack_chunk).collect()
+}
+
+/// Interprets a chunk of at most `F::CAPACITY / 8` bytes as a little-endian integer. Trailing zero bytes do not
+/// change the result, so a partial chunk packs as if it were padded with zeros.
+fn pack_chunk<F: PrimeField>(chunk: &[u8]) -> F {
+    let radix = F::from(256);
+    chunk
+        .iter()
+        .rev()
+        .fold(F::ZERO, |acc, byte| acc * radix + F::from(u64::from(*byte)))
+}
+
+/// A [`std::io::Write`] adapter which hashes the bytes written to it exactly as [`Poseidon::hash_bytes`] does, so that
+/// a large message can be streamed (e.g. with [`std::io::copy`]) rather than held in memory. Since the packed message
+/// starts with its length, and the `IOPattern` declares the number of packed elements, the number of bytes to be
+/// written must be known up front.
+///
+/// # Example
+///
+/// ```
+/// use neptune::poseidon::{Poseidon, PoseidonConstants, PoseidonWriter};
+/// use pasta_curves::Fp;
+/// use generic_array::typenum::U2;
+/// use std::io::Write;
+///
+/// let constants: PoseidonConstants<Fp, U2> = PoseidonConstants::new();
+/// let message = b"a message too large to hold in memory";
+///
+/// let mut writer = PoseidonWriter::new(&constants, message.len() as u64).unwrap();
+/// std::io::copy(&mut &message[..], &mut writer).unwrap();
+///
+/// assert_eq!(Poseidon::new(&constants).hash_bytes(message), writer.finalize());
+/// ```
+#[cfg(feature = "std")]
+pub struct PoseidonWriter<'a, F: PrimeField, A: Arity<F>> {
+    sponge: Sponge<'a, F, A>,
+    remaining: u64,
+    chunk: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl<'a, F: PrimeField, A: Arity<F>> PoseidonWriter<'a, F, A> {
+    /// Create a writer for a message of exactly `len` bytes. Returns an error if the packed message has too many
+    /// elements for an `IOPattern`.
+    pub fn new(
+        constants: &'a PoseidonConstants<F, A>,
+        len: u64,
+    ) -> Result<Self, crate::sponge::api::Error> {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let chunk_size = F::CAPACITY as u64 / 8;
+        // The length prefix and the padding byte are packed along with the message. A count which overflows, or does
+        // not even fit in a `u32`, is rejected by `try_new` as well.
+        let element_count = len
+            .checked_add(8 + 1 + chunk_size - 1)
+            .and_then(|bytes| u32::try_from(bytes / chunk_size).ok())
+            .unwrap_or(u32::MAX);
+        let pattern =
+            IOPattern::try_new(vec![SpongeOp::Absorb(element_count), SpongeOp::Squeeze(1)])?;
+
+        let mut sponge = Sponge::new_with_constants(constants, Mode::Simplex);
+        SpongeAPI::start(&mut sponge, pattern, DomainSeparator::default(), &mut ());
+
+        let mut writer = Self {
+            sponge,
+            remaining: len,
+            chunk: Vec::with_capacity(chunk_size as usize),
+        };
+        writer.push(&len.to_le_bytes());
+        Ok(writer)
+    }
+
+    /// Returns the number of bytes which must still be written before finalizing.
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    /// Pad the message, and squeeze its digest.
+    ///
+    /// # Panics
+    ///
+    /// Panics if fewer bytes were written than declared to `new`.
+    pub fn finalize(mut self) -> F {
+        use crate::sponge::api::{complete_absorb, SpongeAPI};
+
+        assert_eq!(
+            0, self.remaining,
+            "fewer bytes were written than declared for the message"
+        );
+        self.push(&[1]);
+        if !self.chunk.is_empty() {
+            self.absorb_chunk();
         }
 
-        for (j, val) in result.iter_mut().enumerate().skip(1) {
-            // Except for first row/column, diagonals are one.
-            val.add_assign(&self.elements[j]);
+        let acc = &mut ();
+        complete_absorb(&mut self.sponge, acc);
+        let digest = SpongeAPI::squeeze(&mut self.sponge, 1, acc)[0];
+        SpongeAPI::finish(&mut self.sponge, acc).unwrap();
 
-            // First row is dense.
-            let mut tmp = sparse_matrix.v_rest[j - 1];
-            tmp.mul_assign(&self.elements[0]);
-            val.add_assign(&tmp);
+        digest
+    }
+
+    /// Buffer `bytes`, absorbing every chunk which is filled.
+    fn push(&mut self, mut bytes: &[u8]) {
+        let chunk_size = self.chunk.capacity();
+        while !bytes.is_empty() {
+            let (head, tail) = bytes.split_at(bytes.len().min(chunk_size - self.chunk.len()));
+            self.chunk.extend_from_slice(head);
+            bytes = tail;
+            if self.chunk.len() == chunk_size {
+                self.absorb_chunk();
+            }
         }
+    }
 
-        let _ = std::mem::replace(&mut self.elements, result);
+    fn absorb_chunk(&mut self) {
+        let element = pack_chunk(&self.chunk);
+        crate::sponge::api::absorb_partial(&mut self.sponge, &[element], &mut ());
+        self.chunk.clear();
     }
+}
 
-    pub(crate) fn debug(&self, msg: &str) {
-        dbg!(msg, &self.constants_offset, &self.elements);
+#[cfg(feature = "std")]
+impl<'a, F: PrimeField, A: Arity<F>> std::io::Write for PoseidonWriter<'a, F, A> {
+    /// Returns an error of kind `InvalidInput`, without writing anything, if `buf` is longer than the remaining length
+    /// of the message.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.len() as u64 > self.remaining {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "more bytes were written than declared for the message",
+            ));
+        }
+        self.remaining -= buf.len() as u64;
+        self.push(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Poseidon constants for one of the common arities 2, 4, 8, 11 and 16, selected at runtime. This lets a single code
+/// path hash preimages whose arity is only known at runtime, e.g. from the records being hashed, at the cost of a
+/// match on every call. The digests are those of [`Poseidon::hash`] with the corresponding [`PoseidonConstants`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum DynPoseidon<F: PrimeField> {
+    U2(PoseidonConstants<F, U2>),
+    U4(PoseidonConstants<F, U4>),
+    U8(PoseidonConstants<F, U8>),
+    U11(PoseidonConstants<F, U11>),
+    U16(PoseidonConstants<F, U16>),
+}
+
+impl<F: PrimeField> DynPoseidon<F> {
+    /// Generate standard-strength constants for `arity`.
+    pub fn new(arity: usize) -> Result<Self, Error> {
+        Self::new_with_strength(arity, DEFAULT_STRENGTH)
+    }
+
+    /// Generate constants of the given `strength` for `arity`. Returns [`Error::Other`] if `arity` is not one of 2,
+    /// 4, 8, 11 and 16.
+    pub fn new_with_strength(arity: usize, strength: Strength) -> Result<Self, Error> {
+        Ok(match arity {
+            2 => Self::U2(PoseidonConstants::new_with_strength(strength)),
+            4 => Self::U4(PoseidonConstants::new_with_strength(strength)),
+            8 => Self::U8(PoseidonConstants::new_with_strength(strength)),
+            11 => Self::U11(PoseidonConstants::new_with_strength(strength)),
+            16 => Self::U16(PoseidonConstants::new_with_strength(strength)),
+            _ => {
+                return Err(Error::Other(alloc::format!(
+                    "no runtime-selectable Poseidon constants for arity {arity}"
+                )))
+            }
+        })
+    }
+
+    pub fn arity(&self) -> usize {
+        match self {
+            Self::U2(constants) => constants.arity(),
+            Self::U4(constants) => constants.arity(),
+            Self::U8(constants) => constants.arity(),
+            Self::U11(constants) => constants.arity(),
+            Self::U16(constants) => constants.arity(),
+        }
+    }
+
+    /// Hash `preimage`, which must have exactly [`DynPoseidon::arity`] elements. Returns [`Error::ArityMismatch`]
+    /// otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use neptune::poseidon::{DynPoseidon, Poseidon, PoseidonConstants};
+    /// use pasta_curves::Fp;
+    /// use generic_array::typenum::U4;
+    ///
+    /// let preimage = [Fp::from(1), Fp::from(2), Fp::from(3), Fp::from(4)];
+    /// let hasher = DynPoseidon::new(preimage.len()).unwrap();
+    /// let constants = PoseidonConstants::<Fp, U4>::new();
+    ///
+    /// assert_eq!(
+    ///     hasher.hash(&preimage).unwrap(),
+    ///     Poseidon::new_with_preimage(&preimage, &constants).hash()
+    /// );
+    /// assert!(hasher.hash(&preimage[..2]).is_err());
+    /// ```
+    pub fn hash(&self, preimage: &[F]) -> Result<F, Error> {
+        let arity = self.arity();
+        if preimage.len() != arity {
+            return Err(Error::ArityMismatch {
+                expected: arity,
+                got: preimage.len(),
+            });
+        }
+        Ok(match self {
+            Self::U2(constants) => Poseidon::new_with_preimage(preimage, constants).hash(),
+            Self::U4(constants) => Poseidon::new_with_preimage(preimage, constants).hash(),
+            Self::U8(constants) => Poseidon::new_with_preimage(preimage, constants).hash(),
+            Self::U11(constants) => Poseidon::new_with_preimage(preimage, constants).hash(),
+            Self::U16(constants) => Poseidon::new_with_preimage(preimage, constants).hash(),
+        })
     }
 }
 
@@ -923,16 +1825,49 @@ where
     F: PrimeField,
     A: Arity<F>,
 {
-    pub(crate) fn new(max_batch_size: usize) -> Self {
+    /// Create a new CPU batch hasher.
+    pub fn new(max_batch_size: usize) -> Self {
         Self::new_with_strength(DEFAULT_STRENGTH, max_batch_size)
     }
 
-    pub(crate) fn new_with_strength(strength: Strength, max_batch_size: usize) -> Self {
+    /// Create a new CPU batch hasher with a specified strength.
+    pub fn new_with_strength(strength: Strength, max_batch_size: usize) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertWidth::<F, A>::OK;
         Self {
             constants: PoseidonConstants::<F, A>::new_with_strength(strength),
             max_batch_size,
         }
     }
+
+    /// Create a new CPU batch hasher with a specified strength and domain separation, e.g. [`HashType::MerkleLeaf`] or
+    /// [`HashType::MerkleNode`] for a [`crate::merkle::MerkleTreeBuilder::new_domain_separated`] tree. The
+    /// `hash_type` must take a preimage of exactly [`Arity`] elements.
+    pub fn new_with_strength_and_type(
+        strength: Strength,
+        hash_type: HashType<F, A>,
+        max_batch_size: usize,
+    ) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertWidth::<F, A>::OK;
+        Self {
+            constants: PoseidonConstants::<F, A>::new_with_strength(strength)
+                .with_hash_type(hash_type),
+            max_batch_size,
+        }
+    }
+
+    pub(crate) fn strength(&self) -> Strength {
+        self.constants.strength
+    }
+
+    fn hash_sequential(&self, preimages: &[GenericArray<F, A>], out: &mut [F]) {
+        let mut p = Poseidon::new(&self.constants);
+        for (preimage, hash) in preimages.iter().zip(out.iter_mut()) {
+            p.set_preimage(preimage);
+            *hash = p.hash();
+        }
+    }
 }
 impl<F, A> BatchHasher<F, A> for SimplePoseidonBatchHasher<F, A>
 where
@@ -940,9 +1875,49 @@ where
     A: Arity<F>,
 {
     fn hash(&mut self, preimages: &[GenericArray<F, A>]) -> Result<Vec<F>, Error> {
+        let mut hashes = vec![F::ZERO; preimages.len()];
+        self.hash_into(preimages, &mut hashes)?;
+        Ok(hashes)
+    }
+
+    /// With the `parallel` feature, preimages are split into chunks of at most `max_batch_size`, which are hashed
+    /// on the rayon thread pool.
+    fn hash_into(&mut self, preimages: &[GenericArray<F, A>], out: &mut [F]) -> Result<(), Error> {
+        if out.len() < preimages.len() {
+            return Err(Error::IndexOutOfBounds);
+        }
+        let out = &mut out[..preimages.len()];
+
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+
+            let chunk_size = (preimages.len() / rayon::current_num_threads())
+                .clamp(1, self.max_batch_size.max(1));
+            let this = &*self;
+            preimages
+                .par_chunks(chunk_size)
+                .zip(out.par_chunks_mut(chunk_size))
+                .for_each(|(preimages, out)| this.hash_sequential(preimages, out));
+        }
+        #[cfg(not(feature = "parallel"))]
+        self.hash_sequential(preimages, out);
+
+        Ok(())
+    }
+
+    fn hash_iter<I>(&mut self, preimages: I) -> Result<Vec<F>, Error>
+    where
+        I: IntoIterator<Item = GenericArray<F, A>>,
+    {
+        // Each preimage is hashed as soon as it is produced, so the preimages are never materialized.
+        let mut p = Poseidon::new(&self.constants);
         Ok(preimages
-            .iter()
-            .map(|preimage| Poseidon::new_with_preimage(preimage, &self.constants).hash())
+            .into_iter()
+            .map(|preimage| {
+                p.set_preimage(&preimage);
+                p.hash()
+            })
             .collect())
     }
 
@@ -962,6 +1937,8 @@ mod tests {
     use ff::Field;
     use generic_array::typenum;
     use pasta_curves::pallas::Scalar as S1;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
 
     #[test]
     fn reset() {
@@ -978,6 +1955,122 @@ mod tests {
         assert_eq!(default.constants_offset, h.constants_offset);
     }
 
+    #[test]
+    fn pack_bytes() {
+        // An empty message packs to its length prefix followed by the 0x01 pad byte.
+        assert_eq!(vec![Fr::from_u128(1 << 64)], super::pack_bytes::<Fr>(&[]));
+        assert_eq!(
+            vec![Fr::from_u128(1 | 0xab << 64 | 1 << 72)],
+            super::pack_bytes::<Fr>(&[0xab])
+        );
+
+        // 8 + 22 + 1 bytes fill the 31-byte chunk exactly; one more byte spills into a second element.
+        assert_eq!(1, super::pack_bytes::<Fr>(&[0xff; 22]).len());
+        assert_eq!(2, super::pack_bytes::<Fr>(&[0xff; 23]).len());
+        assert_eq!(2, super::pack_bytes::<S1>(&[0xff; 23]).len());
+    }
+
+    #[test]
+    fn hash_bytes() {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let constants = PoseidonConstants::<Fr, U4>::new();
+        let poseidon = Poseidon::new(&constants);
+
+        let bytes: Vec<u8> = (0..100).collect();
+        let digest = poseidon.hash_bytes(&bytes);
+
+        // Trailing zeros and truncation must not collide.
+        let mut padded = bytes.clone();
+        padded.push(0);
+        assert_ne!(digest, poseidon.hash_bytes(&padded));
+        assert_ne!(digest, poseidon.hash_bytes(&bytes[..99]));
+
+        let elements = super::pack_bytes::<Fr>(&bytes);
+        let acc = &mut ();
+        let mut sponge = Sponge::new_with_constants(&constants, Mode::Simplex);
+        let pattern = IOPattern(vec![
+            SpongeOp::Absorb(elements.len() as u32),
+            SpongeOp::Squeeze(1),
+        ]);
+        SpongeAPI::start(&mut sponge, pattern, DomainSeparator::default(), acc);
+        SpongeAPI::absorb(&mut sponge, elements.len() as u32, &elements, acc);
+        assert_eq!(vec![digest], SpongeAPI::squeeze(&mut sponge, 1, acc));
+        SpongeAPI::finish(&mut sponge, acc).unwrap();
+    }
+
+    #[test]
+    fn poseidon_writer() {
+        use std::io::Write;
+
+        let constants = PoseidonConstants::<Fr, U4>::new();
+        let poseidon = Poseidon::new(&constants);
+        let bytes: Vec<u8> = (0..=255).cycle().take(1000).collect();
+
+        // Lengths around chunk and rate-block boundaries, written in pieces of various sizes.
+        for len in [0, 1, 22, 23, 53, 123, 124, 1000] {
+            for piece in [1, 7, 31, 1000] {
+                let mut writer = PoseidonWriter::new(&constants, len as u64).unwrap();
+                for chunk in bytes[..len].chunks(piece) {
+                    writer.write_all(chunk).unwrap();
+                }
+                assert_eq!(0, writer.remaining());
+                assert_eq!(poseidon.hash_bytes(&bytes[..len]), writer.finalize());
+            }
+        }
+
+        let mut writer = PoseidonWriter::new(&constants, 2).unwrap();
+        let err = writer.write(&[1, 2, 3]).unwrap_err();
+        assert_eq!(std::io::ErrorKind::InvalidInput, err.kind());
+        assert_eq!(2, writer.remaining());
+        writer.write_all(&[1, 2]).unwrap();
+        assert_eq!(poseidon.hash_bytes(&[1, 2]), writer.finalize());
+
+        for len in [u64::MAX / 2, u64::MAX - 8, u64::MAX] {
+            assert!(matches!(
+                PoseidonWriter::new(&constants, len),
+                Err(crate::sponge::api::Error::CountOverflow { index: 0, .. })
+            ));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "fewer bytes were written than declared for the message")]
+    fn poseidon_writer_short_message() {
+        let constants = PoseidonConstants::<Fr, U4>::new();
+        PoseidonWriter::new(&constants, 1).unwrap().finalize();
+    }
+
+    #[test]
+    fn constants_cache() {
+        let standard = constants_for::<Fr, U4>(Strength::Standard);
+        let strengthened = constants_for::<Fr, U4>(Strength::Strengthened);
+
+        assert_eq!(*standard, PoseidonConstants::<Fr, U4>::new());
+        assert_eq!(
+            *strengthened,
+            PoseidonConstants::<Fr, U4>::new_with_strength(Strength::Strengthened)
+        );
+        assert!(Arc::ptr_eq(&standard, &constants_for(Strength::Standard)));
+        assert!(!Arc::ptr_eq(&standard, &strengthened));
+
+        // Distinct fields and arities get distinct entries.
+        assert_eq!(
+            *constants_for::<S1, U4>(Strength::Standard),
+            PoseidonConstants::<S1, U4>::new()
+        );
+        assert_eq!(
+            *constants_for::<Fr, U8>(Strength::Standard),
+            PoseidonConstants::<Fr, U8>::new()
+        );
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| std::thread::spawn(|| constants_for::<Fr, U2>(Strength::Standard)))
+            .collect();
+        let shared: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert!(shared.iter().all(|c| Arc::ptr_eq(c, &shared[0])));
+    }
+
     #[test]
     fn hash_det() {
         let test_arity = 2;
@@ -1007,6 +2100,219 @@ mod tests {
         assert_eq!(result, h2.hash());
     }
 
+    #[test]
+    #[should_panic(expected = "Invalid preimage size: Expected a preimage of 4 elements, got 3.")]
+    fn set_preimage_arity_mismatch() {
+        let constants = PoseidonConstants::<Fr, U4>::new();
+        let mut p = Poseidon::new(&constants);
+        p.set_preimage(&[Fr::ONE; 3]);
+    }
+
+    #[test]
+    fn custom_strength() {
+        let strength = Strength::custom(6, 40).unwrap();
+        let constants = PoseidonConstants::<Fr, U4>::new_with_strength(strength);
+        assert_eq!(6, constants.full_rounds);
+        assert_eq!(40, constants.partial_rounds);
+
+        let preimage = [Fr::from(1), Fr::from(2), Fr::from(3), Fr::from(4)];
+        let mut p = Poseidon::<Fr, U4>::new_with_preimage(&preimage, &constants);
+        let digest = p.hash_in_mode(Correct);
+        p.set_preimage(&preimage);
+        assert_eq!(digest, p.hash_in_mode(OptimizedStatic));
+        p.set_preimage(&preimage);
+        assert_eq!(digest, p.hash_in_mode(OptimizedDynamic));
+
+        let standard = PoseidonConstants::<Fr, U4>::new();
+        assert_ne!(
+            digest,
+            Poseidon::<Fr, U4>::new_with_preimage(&preimage, &standard).hash()
+        );
+
+        let mut batcher = SimplePoseidonBatchHasher::<Fr, U4>::new_with_strength(strength, 1);
+        assert_eq!(
+            vec![digest],
+            batcher.hash(&[GenericArray::from(preimage)]).unwrap()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid custom strength")]
+    fn invalid_custom_strength() {
+        PoseidonConstants::<Fr, U4>::new_with_strength(Strength::Custom {
+            full_rounds: 0,
+            partial_rounds: 40,
+        });
+    }
+
+    #[test]
+    fn permute() {
+        let constants = PoseidonConstants::<Fr, U4>::new();
+
+        let mut state: Vec<Fr> = (0..constants.width() as u64).map(Fr::from).collect();
+        let mut p = Poseidon::new(&constants);
+        p.elements.copy_from_slice(&state);
+        p.hash_in_mode(Correct);
+
+        constants.permute(&mut state);
+        assert_eq!(p.elements.as_slice(), state.as_slice());
+    }
+
+    #[test]
+    #[should_panic]
+    fn permute_wrong_width() {
+        PoseidonConstants::<Fr, U4>::new().permute(&mut [Fr::ZERO; 4]);
+    }
+
+    #[test]
+    fn hash_with_domain() {
+        let constants = PoseidonConstants::<Fr, U4>::new();
+        let preimage: Vec<Fr> = (1..5).map(Fr::from).collect();
+
+        let merkle = Poseidon::new_with_preimage(&preimage, &constants).hash();
+        let constant_length =
+            Poseidon::hash_with_domain(&preimage, &constants, HashType::ConstantLength(4));
+        let encryption = Poseidon::hash_with_domain(&preimage, &constants, HashType::Encryption);
+
+        // `HashType::VariableLength` is not yet supported, so compare against the other supported types instead.
+        assert_ne!(merkle, constant_length);
+        assert_ne!(merkle, encryption);
+        assert_ne!(constant_length, encryption);
+
+        assert_eq!(
+            merkle,
+            Poseidon::hash_with_domain(&preimage, &constants, HashType::MerkleTree)
+        );
+        assert_eq!(
+            constant_length,
+            Poseidon::new_with_preimage(
+                &preimage,
+                &PoseidonConstants::<Fr, U4>::new_constant_length(4)
+            )
+            .hash()
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn with_unsupported_hash_type() {
+        PoseidonConstants::<Fr, U4>::new().with_hash_type(HashType::VariableLength);
+    }
+
+    #[test]
+    fn fixed_size_hashes() {
+        let preimage: Vec<Fr> = (1..9).map(Fr::from).collect();
+
+        let c2 = PoseidonConstants::<Fr, U2>::new();
+        let c4 = PoseidonConstants::<Fr, U4>::new();
+        let c8 = PoseidonConstants::<Fr, U8>::new();
+        assert_eq!(
+            Poseidon::new_with_preimage(&preimage[..2], &c2).hash(),
+            hash2(preimage[0], preimage[1])
+        );
+        assert_eq!(
+            Poseidon::new_with_preimage(&preimage[..4], &c4).hash(),
+            hash4([preimage[0], preimage[1], preimage[2], preimage[3]])
+        );
+        assert_eq!(
+            Poseidon::new_with_preimage(&preimage, &c8).hash(),
+            hash8(preimage.clone().try_into().unwrap())
+        );
+
+        // Each thread has its own copy of the cache, filled from the shared one.
+        let expected = hash2(preimage[0], preimage[1]);
+        let threads: Vec<_> = (0..4)
+            .map(|_| std::thread::spawn(|| hash2(Fr::from(1), Fr::from(2))))
+            .collect();
+        assert!(threads.into_iter().all(|t| t.join().unwrap() == expected));
+        assert_eq!(
+            Poseidon::new_with_preimage(
+                &[S1::from(1), S1::from(2)],
+                &PoseidonConstants::<S1, U2>::new()
+            )
+            .hash(),
+            hash2(S1::from(1), S1::from(2))
+        );
+    }
+
+    #[test]
+    fn batch_hash_into() {
+        let mut batcher = SimplePoseidonBatchHasher::<Fr, U4>::new(8);
+        let preimages: Vec<GenericArray<Fr, U4>> = (0..5u64)
+            .map(|i| GenericArray::generate(|j| Fr::from(i * 4 + j as u64)))
+            .collect();
+        let expected = batcher.hash(&preimages).unwrap();
+
+        let mut out = vec![Fr::ZERO; 6];
+        batcher.hash_into(&preimages, &mut out).unwrap();
+        assert_eq!(expected, out[..5]);
+        assert_eq!(Fr::ZERO, out[5]);
+
+        assert!(matches!(
+            batcher.hash_into(&preimages, &mut out[..4]),
+            Err(Error::IndexOutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn batch_hash_chunked() {
+        let constants = PoseidonConstants::<Fr, U2>::new();
+        let preimages: Vec<GenericArray<Fr, U2>> = (0..100u64)
+            .map(|i| GenericArray::generate(|j| Fr::from(i * 2 + j as u64)))
+            .collect();
+        let expected: Vec<Fr> = preimages
+            .iter()
+            .map(|preimage| Poseidon::new_with_preimage(preimage, &constants).hash())
+            .collect();
+
+        // With the `parallel` feature, these exercise chunks smaller than, equal to and larger than the batch.
+        for max_batch_size in [0, 1, 3, 100, 1000] {
+            let mut batcher = SimplePoseidonBatchHasher::<Fr, U2>::new(max_batch_size);
+            for len in [0, 1, 7, 100] {
+                assert_eq!(
+                    expected[..len],
+                    batcher.hash(&preimages[..len]).unwrap(),
+                    "max_batch_size {max_batch_size}, {len} preimages"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn batch_hash_pasta() {
+        batch_hash_field_aux::<S1>();
+        batch_hash_field_aux::<pasta_curves::vesta::Scalar>();
+    }
+
+    fn batch_hash_field_aux<F: PrimeField>() {
+        let constants = PoseidonConstants::<F, U4>::new();
+        let preimages: Vec<GenericArray<F, U4>> = (0..10u64)
+            .map(|i| GenericArray::generate(|j| F::from(i * 4 + j as u64)))
+            .collect();
+        let expected: Vec<F> = preimages
+            .iter()
+            .map(|preimage| Poseidon::new_with_preimage(preimage, &constants).hash())
+            .collect();
+
+        let mut batcher = SimplePoseidonBatchHasher::<F, U4>::new(4);
+        assert_eq!(expected, batcher.hash(&preimages).unwrap());
+    }
+
+    #[test]
+    fn batch_hash_iter() {
+        let mut batcher = SimplePoseidonBatchHasher::<Fr, U4>::new(8);
+        let preimages: Vec<GenericArray<Fr, U4>> = (0..20u64)
+            .map(|i| GenericArray::generate(|j| Fr::from(i * 4 + j as u64)))
+            .collect();
+
+        let expected = batcher.hash(&preimages).unwrap();
+        assert_eq!(
+            expected,
+            batcher.hash_iter(preimages.iter().cloned()).unwrap()
+        );
+        assert!(batcher.hash_iter(std::iter::empty()).unwrap().is_empty());
+    }
+
     #[test]
     fn hash_values() {
         hash_values_cases(Strength::Standard);
@@ -1113,6 +2419,7 @@ mod tests {
                     }
                 }
             }
+            Strength::Custom { .. } => panic!("Strength lacks test vector: {}", strength),
             Strength::Strengthened =>
             // Strengthened round constants.
             {
@@ -1234,6 +2541,7 @@ mod tests {
                     _ => unimplemented!(),
                 }
             }
+            Strength::Custom { .. } => panic!("Strength lacks test vector: {}", strength),
             Strength::Strengthened => match test_arity {
                 2 => scalar_from_u64s([
                     0xcbd4499072dcaff6,
@@ -1306,6 +2614,396 @@ mod tests {
         assert_eq!(digest_correct, digest_optimized_static);
     }
 
+    #[test]
+    fn hash_compare_optimized_wide_arities() {
+        for strength in [Strength::Standard, Strength::Strengthened] {
+            hash_compare_optimized_aux::<U11>(strength);
+            hash_compare_optimized_aux::<U16>(strength);
+            hash_compare_optimized_aux::<U36>(strength);
+        }
+    }
+
+    fn hash_compare_optimized_aux<A: Arity<Fr>>(strength: Strength) {
+        let constants = PoseidonConstants::<Fr, A>::new_with_strength(strength);
+        assert_eq!(A::to_usize(), constants.arity());
+        assert_eq!(A::to_usize() + 1, constants.width());
+        assert_eq!(1, constants.capacity());
+
+        let preimage: Vec<Fr> = (0..A::to_usize() as u64).map(Fr::from).collect();
+        let p = Poseidon::<Fr, A>::new_with_preimage(&preimage, &constants);
+
+        let digest_correct = p.clone().hash_in_mode(Correct);
+        assert_eq!(digest_correct, p.clone().hash_in_mode(OptimizedDynamic));
+        assert_eq!(digest_correct, p.clone().hash_in_mode(OptimizedStatic));
+    }
+
+    #[test]
+    fn hash_alpha() {
+        use crate::test_field::Fp as F3;
+
+        // x^3 is the default S-box of the test field, whereas x^7 is valid but not the default for BLS12-381.
+        let constants = PoseidonConstants::<F3, U2>::new();
+        assert_eq!(3, constants.alpha);
+        hash_alpha_aux(&constants);
+        hash_alpha_aux(&PoseidonConstants::<F3, U4>::new());
+
+        let constants = PoseidonConstants::<Fr, U4>::new_with_alpha(
+            Strength::Standard,
+            HashType::MerkleTree,
+            Some(7),
+        );
+        assert_eq!(7, constants.alpha);
+        hash_alpha_aux(&constants);
+
+        // Fewer rounds are needed with higher exponents.
+        let quintic = PoseidonConstants::<Fr, U4>::new();
+        assert!(constants.partial_rounds < quintic.partial_rounds);
+        let cubic = PoseidonConstants::<F3, U4>::new();
+        assert!(cubic.partial_rounds > quintic.partial_rounds);
+    }
+
+    #[test]
+    fn hash_bn254() {
+        use crate::test_bn254::Fr as Bn;
+
+        // The BN254 scalar field is 254 bits wide and, like BLS12-381, uses the x^5 S-box.
+        for strength in [Strength::Standard, Strength::Strengthened] {
+            let constants = PoseidonConstants::<Bn, U2>::new_with_strength(strength);
+            assert_eq!(5, constants.alpha);
+            constants.validate_mds().unwrap();
+            hash_alpha_aux(&constants);
+
+            let constants = PoseidonConstants::<Bn, U8>::new_with_strength(strength);
+            constants.validate_mds().unwrap();
+            hash_alpha_aux(&constants);
+        }
+        batch_hash_field_aux::<Bn>();
+    }
+
+    #[test]
+    #[should_panic(expected = "x^3 is not a valid S-box for this field")]
+    fn hash_invalid_alpha() {
+        PoseidonConstants::<Fr, U2>::new_with_alpha(
+            Strength::Standard,
+            HashType::MerkleTree,
+            Some(3),
+        );
+    }
+
+    fn hash_alpha_aux<F: PrimeField, A: Arity<F>>(constants: &PoseidonConstants<F, A>) {
+        let preimage: Vec<F> = (0..A::to_usize() as u64).map(F::from).collect();
+        let p = Poseidon::<F, A>::new_with_preimage(&preimage, constants);
+
+        let digest_correct = p.clone().hash_in_mode(Correct);
+        assert_eq!(digest_correct, p.clone().hash_in_mode(OptimizedDynamic));
+        assert_eq!(digest_correct, p.clone().hash_in_mode(OptimizedStatic));
+
+        let trace = p.clone().hash_trace();
+        for i in 0..constants.width() {
+            assert_eq!(trace[0][i].pow_vartime([constants.alpha]), trace[1][i]);
+        }
+    }
+
+    #[test]
+    fn hash_trace() {
+        let constants = PoseidonConstants::<Fr, U4>::new();
+        let preimage: Vec<Fr> = (0..4).map(Fr::from).collect();
+        let mut p = Poseidon::<Fr, U4>::new_with_preimage(&preimage, &constants);
+        let initial = p.elements.to_vec();
+        let mut p2 = p.clone();
+
+        let trace = p.hash_trace();
+        let rounds = constants.full_rounds + constants.partial_rounds;
+        assert_eq!(3 * rounds, trace.len());
+        assert!(trace.iter().all(|state| state.len() == constants.width()));
+        assert_eq!(p2.hash(), trace[trace.len() - 1][1]);
+
+        // The first full round adds the round constants, then applies the S-box to every element.
+        let round_constants = constants.round_constants.as_ref().unwrap();
+        for i in 0..constants.width() {
+            assert_eq!(initial[i] + round_constants[i], trace[0][i]);
+            assert_eq!(trace[0][i].pow_vartime([5]), trace[1][i]);
+        }
+
+        // Partial rounds only apply the S-box to the first element.
+        let first_partial = 3 * constants.half_full_rounds;
+        assert_eq!(
+            trace[first_partial][0].pow_vartime([5]),
+            trace[first_partial + 1][0]
+        );
+        assert_eq!(trace[first_partial][1..], trace[first_partial + 1][1..]);
+    }
+
+    #[test]
+    fn validate_mds() {
+        validate_mds_aux::<U2>();
+        validate_mds_aux::<U4>();
+        validate_mds_aux::<U8>();
+        validate_mds_aux::<U11>();
+        validate_mds_aux::<U16>();
+        validate_mds_aux::<U36>();
+    }
+
+    #[test]
+    fn constant_length_domain_separation() {
+        let a = Fr::from(7);
+        let constants: Vec<_> = (1..=4)
+            .map(PoseidonConstants::<Fr, U4>::new_constant_length)
+            .collect();
+
+        // Every preimage is padded to the same state `[a, 0, 0, 0]`, so only the domain tag differs.
+        let digests: Vec<_> = constants
+            .iter()
+            .enumerate()
+            .map(|(i, constants)| {
+                let mut preimage = vec![Fr::ZERO; i + 1];
+                preimage[0] = a;
+                let mut p = Poseidon::new_with_preimage(&preimage, constants);
+                assert_eq!(&[a, Fr::ZERO, Fr::ZERO, Fr::ZERO], &p.elements[1..]);
+                p.hash()
+            })
+            .collect();
+        for (i, digest) in digests.iter().enumerate() {
+            assert!(!digests[..i].contains(digest));
+        }
+    }
+
+    #[test]
+    fn hash_padded() {
+        let constants = PoseidonConstants::<Fr, U8>::new();
+        let a = Fr::from(7);
+
+        let mut digests = std::collections::HashSet::new();
+        for len in 1..=8 {
+            let mut inputs = vec![Fr::ZERO; len];
+            inputs[0] = a;
+            let digest = Poseidon::hash_padded(&inputs, &constants).unwrap();
+            assert_eq!(
+                Poseidon::new_with_preimage(
+                    &inputs,
+                    &constants.with_hash_type(HashType::ConstantLength(len))
+                )
+                .hash(),
+                digest
+            );
+            digests.insert(digest.to_repr());
+        }
+        // Zero-padded inputs of each length are distinct from one another.
+        assert_eq!(8, digests.len());
+
+        for len in [0, 9] {
+            assert!(matches!(
+                Poseidon::hash_padded(&vec![a; len], &constants),
+                Err(Error::ArityMismatch { expected: 8, got }) if got == len
+            ));
+        }
+    }
+
+    #[test]
+    fn dyn_poseidon() {
+        fn check<A: Arity<Fr>>() {
+            let arity = A::to_usize();
+            let preimage: Vec<Fr> = (0..arity as u64).map(Fr::from).collect();
+            for strength in [Strength::Standard, Strength::Strengthened] {
+                let hasher = DynPoseidon::<Fr>::new_with_strength(arity, strength).unwrap();
+                assert_eq!(arity, hasher.arity());
+                let constants = PoseidonConstants::<Fr, A>::new_with_strength(strength);
+                assert_eq!(
+                    Poseidon::new_with_preimage(&preimage, &constants).hash(),
+                    hasher.hash(&preimage).unwrap()
+                );
+                assert!(matches!(
+                    hasher.hash(&preimage[1..]),
+                    Err(Error::ArityMismatch { expected, got }) if expected == arity && got == arity - 1
+                ));
+            }
+        }
+
+        check::<U2>();
+        check::<U4>();
+        check::<U8>();
+        check::<U11>();
+        check::<U16>();
+        for arity in [0, 1, 3, 24] {
+            assert!(matches!(
+                DynPoseidon::<Fr>::new(arity),
+                Err(Error::Other(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn from_parts() {
+        let generated = PoseidonConstants::<Fr, U4>::new_with_strength(Strength::Strengthened);
+        let round_constants = generated.round_constants.clone().unwrap();
+        let mds = generated.mds_matrices.m.clone();
+        let imported = PoseidonConstants::<Fr, U4>::from_parts(
+            round_constants.clone(),
+            mds.clone(),
+            Strength::Strengthened,
+        )
+        .unwrap();
+        assert_eq!(generated, imported);
+
+        // The imported constants are usable by sponges.
+        let elements: Vec<Fr> = (0..6).map(Fr::from).collect();
+        let imported = imported.with_hash_type(HashType::Sponge);
+        let mut sponge = Sponge::new_with_constants(&imported, Mode::Simplex);
+        sponge.absorb_elements(&elements, &mut ()).unwrap();
+        let generated = generated.with_hash_type(HashType::Sponge);
+        let mut expected = Sponge::new_with_constants(&generated, Mode::Simplex);
+        expected.absorb_elements(&elements, &mut ()).unwrap();
+        assert_eq!(
+            expected.squeeze_elements(2, &mut ()),
+            sponge.squeeze_elements(2, &mut ())
+        );
+
+        // Too few or too many round constants for the strength.
+        for strength in [Strength::Standard, Strength::custom(8, 100).unwrap()] {
+            assert!(matches!(
+                PoseidonConstants::<Fr, U4>::from_parts(
+                    round_constants.clone(),
+                    mds.clone(),
+                    strength
+                ),
+                Err(Error::Other(_))
+            ));
+        }
+        assert!(PoseidonConstants::<Fr, U4>::from_parts(
+            round_constants.clone(),
+            mds.clone(),
+            Strength::Custom {
+                full_rounds: 7,
+                partial_rounds: 60
+            }
+        )
+        .is_err());
+
+        // An MDS matrix of the wrong width, and one which is not MDS.
+        assert!(matches!(
+            PoseidonConstants::<Fr, U2>::from_parts(
+                round_constants.clone(),
+                mds.clone(),
+                Strength::Standard
+            ),
+            Err(Error::WidthMismatch {
+                expected: 3,
+                got: 5
+            })
+        ));
+        let mut singular = mds;
+        singular[1][2] = Fr::from(0);
+        assert!(matches!(
+            PoseidonConstants::<Fr, U4>::from_parts(
+                round_constants,
+                singular,
+                Strength::Strengthened
+            ),
+            Err(Error::Other(_))
+        ));
+
+        // A non-Cauchy matrix too wide to be checked exhaustively is accepted, though not verified to be MDS.
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let wide: Matrix<Fr> = (0..9)
+            .map(|_| (0..9).map(|_| Fr::random(&mut rng)).collect())
+            .collect();
+        let generated = PoseidonConstants::<Fr, U8>::new();
+        let imported = PoseidonConstants::<Fr, U8>::from_parts(
+            generated.round_constants.clone().unwrap(),
+            wide,
+            Strength::Standard,
+        )
+        .unwrap();
+        assert_eq!(MdsCheck::Unverified, imported.validate_mds().unwrap());
+        assert_eq!(MdsCheck::Verified, generated.validate_mds().unwrap());
+    }
+
+    #[test]
+    fn from_parts_reference() {
+        use crate::test_bn254::Fr as Bn;
+
+        // The Grain LFSR round constants of the reference Poseidon instance for width 3 over BN254, which has 8 full
+        // and 57 partial rounds, with the default MDS matrix. The expected output was computed independently, with a
+        // direct implementation of the rounds as described for `permute`.
+        let generated = PoseidonConstants::<Bn, U2>::new();
+        let round_constants = crate::round_constants::generate_constants::<Bn>(1, 0, 254, 3, 8, 57);
+        let constants = PoseidonConstants::<Bn, U2>::from_parts(
+            round_constants,
+            generated.mds_matrices.m.clone(),
+            Strength::custom(8, 57).unwrap(),
+        )
+        .unwrap();
+
+        let mut state = [Bn::from(0), Bn::from(1), Bn::from(2)];
+        constants.permute(&mut state);
+        assert_eq!(
+            "0x11161ceb8e399d1f1e9bf6b4fef913e08d9ffeb15aca9226f7e3aed870cdc2d5",
+            format!("{:?}", state[0])
+        );
+    }
+
+    #[test]
+    fn new_with_cauchy_mds() {
+        let xs = [1, 2, 3, 4, 5].map(Fr::from);
+        let ys = [6, 7, 8, 9, 10].map(Fr::from);
+        let constants = PoseidonConstants::<Fr, U4>::new_with_cauchy_mds(
+            Strength::Standard,
+            HashType::MerkleTree,
+            &xs,
+            &ys,
+        )
+        .unwrap();
+        let default = PoseidonConstants::<Fr, U4>::new();
+        assert_eq!(default.round_constants, constants.round_constants);
+        assert_ne!(default.mds_matrices, constants.mds_matrices);
+
+        // The optimized permutation agrees with the reference one for the alternative matrix.
+        let preimage = [1, 2, 3, 4].map(Fr::from);
+        let mut p = Poseidon::<Fr, U4>::new_with_preimage(&preimage, &constants);
+        let mut q = p.clone();
+        assert_eq!(
+            p.hash_in_mode(HashMode::Correct),
+            q.hash_in_mode(HashMode::OptimizedStatic)
+        );
+        assert_ne!(
+            Poseidon::<Fr, U4>::new_with_preimage(&preimage, &default).hash(),
+            p.hash()
+        );
+
+        assert!(matches!(
+            PoseidonConstants::<Fr, U4>::new_with_cauchy_mds(
+                Strength::Standard,
+                HashType::MerkleTree,
+                &xs[..4],
+                &ys[..4],
+            ),
+            Err(Error::WidthMismatch {
+                expected: 5,
+                got: 4
+            })
+        ));
+        assert!(PoseidonConstants::<Fr, U4>::new_with_cauchy_mds(
+            Strength::Standard,
+            HashType::MerkleTree,
+            &xs,
+            &xs,
+        )
+        .is_err());
+    }
+
+    fn validate_mds_aux<A: Arity<Fr>>() {
+        let constants = PoseidonConstants::<Fr, A>::new();
+        constants.validate_mds().unwrap();
+
+        let mut tampered = constants.clone();
+        tampered.sparse_matrixes[1].v_rest[0] += Fr::from(1);
+        assert!(tampered.validate_mds().is_err());
+
+        let mut tampered = constants.clone();
+        tampered.mds_matrices.m[0][0] = Fr::from(0);
+        assert!(tampered.validate_mds().is_err());
+    }
+
     #[test]
     fn default_is_standard() {
         let default_constants = PoseidonConstants::<Fr, U8>::new();
@@ -1317,6 +3015,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn constants_eq_and_hash() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::collections::HashSet;
+
+        fn hash_of<A: Arity<Fr>>(constants: &PoseidonConstants<Fr, A>) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            constants.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let constants = PoseidonConstants::<Fr, U4>::new();
+        let independent = PoseidonConstants::<Fr, U4>::new();
+        assert_eq!(constants, independent);
+        assert_eq!(hash_of(&constants), hash_of(&independent));
+
+        let distinct = [
+            PoseidonConstants::<Fr, U4>::new_with_strength(Strength::Strengthened),
+            PoseidonConstants::<Fr, U4>::new_constant_length(3),
+            PoseidonConstants::<Fr, U4>::new_with_strength_and_type(
+                Strength::Standard,
+                HashType::Sponge,
+            ),
+        ];
+        for other in &distinct {
+            assert_ne!(constants, *other);
+            assert_ne!(hash_of(&constants), hash_of(other));
+        }
+
+        let set: HashSet<_> = distinct.iter().chain([&constants, &independent]).collect();
+        assert_eq!(4, set.len());
+    }
+
     #[cfg(feature = "abomonation")]
     #[test]
     fn roundtrip_abomonation() {
@@ -2,7 +2,8 @@
 //! These are tested (in `poseidon::test`) to be equivalent to the 'static optimized' version
 //! used for actual hashing by the neptune library.
 use crate::poseidon::{Arity, Poseidon};
-use crate::{matrix, quintic_s_box};
+use crate::{matrix, s_box};
+use alloc::vec::Vec;
 use ff::PrimeField;
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -37,12 +38,45 @@ where
     p.elements[1]
 }
 
+/// Like `hash_correct`, but returns the state after each step of each round: adding the round constants, applying the
+/// S-box and multiplying by the MDS matrix.
+pub(crate) fn hash_correct_trace<F, A>(p: &mut Poseidon<'_, F, A>) -> Vec<Vec<F>>
+where
+    F: PrimeField,
+    A: Arity<F>,
+{
+    let half_full_rounds = p.constants.half_full_rounds;
+    let partial_rounds = p.constants.partial_rounds;
+    let rounds = 2 * half_full_rounds + partial_rounds;
+
+    let mut trace = Vec::with_capacity(3 * rounds);
+    for round in 0..rounds {
+        add_round_constants(p);
+        trace.push(p.elements.to_vec());
+
+        let is_partial = round >= half_full_rounds && round < half_full_rounds + partial_rounds;
+        if is_partial {
+            s_box(p.constants.alpha, &mut p.elements[0], None, None);
+        } else {
+            p.elements
+                .iter_mut()
+                .for_each(|l| s_box(p.constants.alpha, l, None, None));
+        }
+        trace.push(p.elements.to_vec());
+
+        p.product_mds();
+        trace.push(p.elements.to_vec());
+    }
+
+    trace
+}
+
 pub(crate) fn full_round<F, A>(p: &mut Poseidon<'_, F, A>)
 where
     F: PrimeField,
     A: Arity<F>,
 {
-    // Apply the quintic S-Box to all elements, after adding the round key.
+    // Apply the S-Box to all elements, after adding the round key.
     // Round keys are added in the S-box to match circuits (where the addition is free)
     // and in preparation for the shift to adding round keys after (rather than before) applying the S-box.
 
@@ -59,7 +93,7 @@ where
         .iter_mut()
         .zip(pre_round_keys)
         .for_each(|(l, pre)| {
-            quintic_s_box(l, pre, None);
+            s_box(p.constants.alpha, l, pre, None);
         });
 
     p.constants_offset += p.elements.len();
@@ -78,8 +112,8 @@ where
     // Every element of the hash buffer is incremented by the round constants
     add_round_constants(p);
 
-    // Apply the quintic S-Box to the first element
-    quintic_s_box(&mut p.elements[0], None, None);
+    // Apply the S-Box to the first element
+    s_box(p.constants.alpha, &mut p.elements[0], None, None);
 
     // Multiply the elements by the constant MDS matrix
     p.product_mds();
@@ -187,14 +221,14 @@ pub(crate) fn full_round_dynamic<F, A>(
             .iter_mut()
             .zip(pre_round_keys.zip(post_round_keys))
             .for_each(|(l, (pre, post))| {
-                quintic_s_box(l, pre, Some(post));
+                s_box(p.constants.alpha, l, pre, Some(post));
             });
     } else {
         p.elements
             .iter_mut()
             .zip(pre_round_keys)
             .for_each(|(l, pre)| {
-                quintic_s_box(l, pre, None);
+                s_box(p.constants.alpha, l, pre, None);
             });
     }
     let mut consumed = 0;
@@ -219,8 +253,8 @@ where
     F: PrimeField,
     A: Arity<F>,
 {
-    // Apply the quintic S-Box to the first element
-    quintic_s_box(&mut p.elements[0], None, None);
+    // Apply the S-Box to the first element
+    s_box(p.constants.alpha, &mut p.elements[0], None, None);
 
     // Multiply the elements by the constant MDS matrix
     p.product_mds();
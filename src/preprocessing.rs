@@ -1,6 +1,7 @@
 use crate::matrix::{apply_matrix, left_apply_matrix, vec_add};
 use crate::mds::MdsMatrices;
 use crate::quintic_s_box;
+use alloc::{vec, vec::Vec};
 use ff::PrimeField;
 
 // - Compress constants by pushing them back through linear layers and through the identity components of partial layers.
@@ -1,13 +1,15 @@
 use super::sources::{generate_program, DerivedConstants};
+use crate::batch_hasher::BatcherBackend;
 use crate::error::{ClError, Error};
 use crate::hash_type::HashType;
-use crate::poseidon::PoseidonConstants;
-use crate::{Arity, BatchHasher, NeptuneField, Strength, DEFAULT_STRENGTH};
-use ec_gpu_gen::rust_gpu_tools::{program_closures, Device, Program};
+use crate::poseidon::{AssertWidth, PoseidonConstants};
+use crate::{Arity, BatchHasher, HashTiming, NeptuneField, Strength, DEFAULT_STRENGTH};
+use ec_gpu_gen::rust_gpu_tools::{program_closures, Device, GPUError, Program};
 use ff::{Field, PrimeField};
 use generic_array::{typenum, ArrayLength, GenericArray};
 use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::time::Instant;
 use typenum::{U11, U2, U8};
 
 #[cfg(feature = "bls")]
@@ -67,6 +69,7 @@ where
     constants_buffer: Buffer<F>,
     max_batch_size: usize,
     program: Program,
+    pub(crate) last_timing: Option<HashTiming>,
 }
 
 impl<F, A> GpuConstants<F, A>
@@ -121,6 +124,18 @@ where
         strength: Strength,
         max_batch_size: usize,
     ) -> Result<Self, Error> {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertWidth::<F, A>::OK;
+        let max_batch_size = if max_batch_size == 0 {
+            Self::recommended_batch_size(device)
+        } else {
+            max_batch_size
+        };
+        if let Strength::Custom { .. } = strength {
+            return Err(Error::GpuError(format!(
+                "{strength} strength is not supported on the GPU"
+            )));
+        }
         let constants = GpuConstants(PoseidonConstants::<F, A>::new_with_strength(strength));
         let program = ec_gpu_gen::program!(device)?;
 
@@ -150,12 +165,37 @@ where
             constants_buffer,
             max_batch_size,
             program,
+            last_timing: None,
         })
     }
 
+    /// Returns a batch size whose buffers fit comfortably in the global memory of `device`: each preimage takes
+    /// `A + 1` field elements of device memory, its `A` elements and its digest, and half of the memory is left for
+    /// the constants and for other users of the device. The size is a multiple of the kernel's local work size. Passing
+    /// a `max_batch_size` of 0 to any GPU batcher constructor uses this size.
+    pub fn recommended_batch_size(device: &Device) -> usize {
+        batch_size_for_memory(
+            device.memory(),
+            (A::to_usize() + 1) * std::mem::size_of::<F>(),
+        )
+    }
+
     pub(crate) fn device(&self) -> Device {
         self.device.clone()
     }
+
+    pub(crate) fn strength(&self) -> Strength {
+        self.constants.strength()
+    }
+
+    pub(crate) fn backend(&self) -> BatcherBackend {
+        match self.program {
+            #[cfg(feature = "cuda")]
+            Program::Cuda(_) => BatcherBackend::Cuda(self.device.name()),
+            #[cfg(feature = "opencl")]
+            Program::Opencl(_) => BatcherBackend::OpenCl(self.device.name()),
+        }
+    }
 }
 
 const LOCAL_WORK_SIZE: usize = 256;
@@ -164,43 +204,89 @@ where
     F: NeptuneField,
     A: Arity<F>,
 {
+    /// Failures to allocate the device buffers, e.g. for lack of device memory, and to launch the kernel are returned
+    /// as [`Error::ClError`], and batches larger than `max_batch_size` as [`Error::GpuError`], so that neither aborts
+    /// the process.
     fn hash(&mut self, preimages: &[GenericArray<F, A>]) -> Result<Vec<F>, Error> {
         use std::any::TypeId;
 
         let local_work_size = LOCAL_WORK_SIZE;
         let max_batch_size = self.max_batch_size;
         let batch_size = preimages.len();
-        assert!(batch_size <= max_batch_size);
+        if batch_size > max_batch_size {
+            return Err(Error::GpuError(format!(
+                "batch of {batch_size} preimages exceeds the maximum batch size of {max_batch_size}"
+            )));
+        }
+        // Zero-length device buffers cannot be created.
+        if batch_size == 0 {
+            return Ok(Vec::new());
+        }
 
         let global_work_size = calc_global_work_size(batch_size, local_work_size);
         let num_hashes = preimages.len();
 
         let kernel_name = self.constants.kernel_name();
 
-        let closures = program_closures!(|program, _args| -> Result<Vec<F>, Error> {
-            let kernel = program.create_kernel(&kernel_name, global_work_size, local_work_size)?;
-            let preimages_buffer = program.create_buffer_from_slice(preimages)?;
-            let result_buffer = unsafe { program.create_buffer::<F>(num_hashes)? };
-
+        let closures = program_closures!(|program, _args| -> Result<(Vec<F>, HashTiming), Error> {
+            let start = Instant::now();
+            let preimages_buffer = program
+                .create_buffer_from_slice(preimages)
+                .map_err(allocation_failed)?;
+            let result_buffer =
+                unsafe { program.create_buffer::<F>(num_hashes) }.map_err(allocation_failed)?;
+            let uploaded = Instant::now();
+
+            let kernel = program
+                .create_kernel(&kernel_name, global_work_size, local_work_size)
+                .map_err(launch_failed)?;
             kernel
                 .arg(&self.constants_buffer)
                 .arg(&preimages_buffer)
                 .arg(&result_buffer)
                 .arg(&(preimages.len() as i32))
-                .run()?;
+                .run()
+                .map_err(launch_failed)?;
+            let launched = Instant::now();
 
             let mut frs = vec![F::ZERO; num_hashes];
             program.read_into_buffer(&result_buffer, &mut frs)?;
-            Ok(frs.to_vec())
+            let timing = HashTiming {
+                upload: uploaded - start,
+                kernel: launched - uploaded,
+                download: launched.elapsed(),
+            };
+            Ok((frs, timing))
         });
 
-        let results = self.program.run(closures, ())?;
+        let (results, timing) = self.program.run(closures, ())?;
+        self.last_timing = Some(timing);
         Ok(results)
     }
 
     fn max_batch_size(&self) -> usize {
         self.max_batch_size
     }
+
+    fn last_hash_timing(&self) -> Option<HashTiming> {
+        self.last_timing
+    }
+}
+
+fn allocation_failed(e: GPUError) -> Error {
+    Error::ClError(ClError::BufferAllocationFailed(e.to_string()))
+}
+
+fn launch_failed(e: GPUError) -> Error {
+    Error::ClError(ClError::KernelLaunchFailed(e.to_string()))
+}
+
+/// Returns the largest multiple of `LOCAL_WORK_SIZE` (but at least `LOCAL_WORK_SIZE`) preimages of `per_preimage`
+/// bytes each which fit in half of `memory` bytes. The batch size is passed to the kernel as an `i32`, so it is capped
+/// accordingly.
+fn batch_size_for_memory(memory: u64, per_preimage: usize) -> usize {
+    let fitting = (memory / 2 / per_preimage as u64).min(i32::MAX as u64) as usize;
+    (fitting / LOCAL_WORK_SIZE).max(1) * LOCAL_WORK_SIZE
 }
 
 /// Set `global_work_size` to the smallest value possible, so that the
@@ -250,6 +336,31 @@ mod test {
         assert_eq!(expected_hashes, cl_hashes);
     }
 
+    #[test]
+    fn test_recommended_batch_size() {
+        // 1 GiB for preimages of 3 32-byte elements.
+        assert_eq!(5_592_320, batch_size_for_memory(1 << 30, 96));
+        assert_eq!(LOCAL_WORK_SIZE, batch_size_for_memory(1 << 10, 96));
+        assert!(batch_size_for_memory(u64::MAX, 96) <= i32::MAX as usize);
+
+        let device = *Device::all().first().expect("Cannot get a device");
+        let recommended = ClBatchHasher::<Fr, U2>::recommended_batch_size(device);
+        assert_eq!(0, recommended % LOCAL_WORK_SIZE);
+        let hasher = ClBatchHasher::<Fr, U2>::new(device, 0).unwrap();
+        assert_eq!(recommended, hasher.max_batch_size());
+    }
+
+    #[test]
+    fn test_batch_size_errors() {
+        let device = *Device::all().first().expect("Cannot get a device");
+        let mut hasher = ClBatchHasher::<Fr, U2>::new(device, 4).unwrap();
+
+        assert!(hasher.hash(&[]).unwrap().is_empty());
+        let preimages = vec![GenericArray::<Fr, U2>::generate(|i| Fr::from(i as u64)); 5];
+        assert!(matches!(hasher.hash(&preimages), Err(Error::GpuError(_))));
+        assert_eq!(4, hasher.hash(&preimages[..4]).unwrap().len());
+    }
+
     #[test]
     fn test_calc_global_work_size() {
         let inputs = vec![
@@ -143,10 +143,10 @@ where
 
 /// Returns derived constants based on the arity.
 ///
-/// It returns both, the standard and the strengthened constants.
+/// It returns both, the standard and the strengthened constants. The kernels implement the x^5 S-box only.
 fn derive_constants(arity: usize) -> (DerivedConstants, DerivedConstants) {
-    let (full_standard, partial_standard) = round_numbers_base(arity);
-    let (full_strengthened, partial_strengthened) = round_numbers_strengthened(arity);
+    let (full_standard, partial_standard) = round_numbers_base(arity, 5);
+    let (full_strengthened, partial_strengthened) = round_numbers_strengthened(arity, 5);
     (
         DerivedConstants::new(arity, full_standard, partial_standard),
         DerivedConstants::new(arity, full_strengthened, partial_strengthened),
@@ -1,7 +1,8 @@
 //! A port of `calc_round_numbers.py`
 //! https://extgit.iaik.tugraz.at/krypto/hadeshash/-/blob/9d80ec0473ad7cde5a12f3aac46439ad0da68c0a/code/scripts/calc_round_numbers.py
 //! from Python2 to Rust for a (roughly) 256-bit prime field (e.g. BLS12-381's scalar field) and
-//! 128-bit security level.
+//! 128-bit security level. The script is specific to the x^5 S-box; other exponents `alpha` are supported by scaling
+//! its interpolation and Gröbner basis bounds with `log_alpha(2)` (see `round_numbers_are_secure`).
 
 // The number of bits of the Poseidon prime field modulus. Denoted `n` in the Poseidon paper
 // (where `n = ceil(log2(p))`). Note that BLS12-381's scalar field modulus is 255 bits, however we
@@ -19,10 +20,10 @@ const fn n_sboxes(t: usize, rf: usize, rp: usize) -> usize {
     t * rf + rp
 }
 
-// Returns the round numbers for a given arity `(R_F, R_P)`.
-pub(crate) fn round_numbers_base(arity: usize) -> (usize, usize) {
+// Returns the round numbers for a given arity `(R_F, R_P)` and S-box exponent `alpha`.
+pub(crate) fn round_numbers_base(arity: usize, alpha: u64) -> (usize, usize) {
     let t = arity + 1;
-    calc_round_numbers(t, true)
+    calc_round_numbers(t, true, alpha)
 }
 
 // In case of newly-discovered attacks, we may need stronger security.
@@ -33,11 +34,11 @@ pub(crate) fn round_numbers_base(arity: usize) -> (usize, usize) {
 // Then it is unlikely that a new attack breaks through this number,
 // but even if this happens then the complexity is almost surely above 2^64, and you will be safe."
 // - D Khovratovich
-pub(crate) fn round_numbers_strengthened(arity: usize) -> (usize, usize) {
-    let (full_round, partial_rounds) = round_numbers_base(arity);
+pub(crate) fn round_numbers_strengthened(arity: usize, alpha: u64) -> (usize, usize) {
+    let (full_round, partial_rounds) = round_numbers_base(arity, alpha);
 
     // Increase by 25%, rounding up.
-    let strengthened_partial_rounds = f64::ceil(partial_rounds as f64 * 1.25) as usize;
+    let strengthened_partial_rounds = (partial_rounds * 5 + 3) / 4;
 
     (full_round, strengthened_partial_rounds)
 }
@@ -45,17 +46,17 @@ pub(crate) fn round_numbers_strengthened(arity: usize) -> (usize, usize) {
 // Returns the round numbers for a given width `t`. Here, the `security_margin` parameter does not
 // indicate that we are calculating `R_F` and `R_P` for the "strengthened" round numbers, done in
 // the function `round_numbers_strengthened()`.
-pub(crate) fn calc_round_numbers(t: usize, security_margin: bool) -> (usize, usize) {
+pub(crate) fn calc_round_numbers(t: usize, security_margin: bool, alpha: u64) -> (usize, usize) {
     let mut rf = 0;
     let mut rp = 0;
     let mut n_sboxes_min = usize::MAX;
 
     for mut rf_test in (2..=1000).step_by(2) {
         for mut rp_test in 4..200 {
-            if round_numbers_are_secure(t, rf_test, rp_test) {
+            if round_numbers_are_secure(t, rf_test, rp_test, alpha) {
                 if security_margin {
                     rf_test += 2;
-                    rp_test = (1.075 * rp_test as f32).ceil() as usize;
+                    rp_test = ceil(1.075 * rp_test as f32) as usize;
                 }
                 let n_sboxes = n_sboxes(t, rf_test, rp_test);
                 if n_sboxes < n_sboxes_min || (n_sboxes == n_sboxes_min && rf_test < rf) {
@@ -72,28 +73,81 @@ pub(crate) fn calc_round_numbers(t: usize, security_margin: bool) -> (usize, usi
 
 // Returns `true` if the provided round numbers satisfy the security inequalities specified in the
 // Poseidon paper.
-fn round_numbers_are_secure(t: usize, rf: usize, rp: usize) -> bool {
+//
+// The script's coefficients `0.43`, `0.21` and `0.14` are `log_5(2)`, `log_5(2) / 2` and `log_5(2) / 3` truncated to
+// two decimals. For other S-box exponents, the same truncation is applied to `log_alpha(2)`, which reproduces the
+// script's coefficients exactly for `alpha = 5`.
+fn round_numbers_are_secure(t: usize, rf: usize, rp: usize, alpha: u64) -> bool {
     let (rp, t, n, m) = (rp as f32, t as f32, PRIME_BITLEN as f32, M as f32);
+    let log_alpha_2 = 1.0 / log2(alpha as f32);
+    let coefficient = |divisor: f32| floor(100.0 * log_alpha_2 / divisor) / 100.0;
     let rf_stat = if m <= (n - 3.0) * (t + 1.0) {
         6.0
     } else {
         10.0
     };
-    let rf_interp = 0.43 * m + t.log2() - rp;
-    let rf_grob_1 = 0.21 * n - rp;
-    let rf_grob_2 = (0.14 * n - 1.0 - rp) / (t - 1.0);
+    let rf_interp = coefficient(1.0) * m + log2(t) - rp;
+    let rf_grob_1 = coefficient(2.0) * n - rp;
+    let rf_grob_2 = (coefficient(3.0) * n - 1.0 - rp) / (t - 1.0);
     let rf_max = [rf_stat, rf_interp, rf_grob_1, rf_grob_2]
         .iter()
-        .map(|rf| rf.ceil() as usize)
+        .map(|rf| ceil(*rf) as usize)
         .max()
         .unwrap();
     rf >= rf_max
 }
 
+// `f32::ceil`, `f32::floor` and `f32::log2` require `std`, so the float operations needed above are implemented here.
+// `ceil` and `floor` are exact and `log2` is accurate to well within `f32` precision for the small, positive inputs
+// used.
+fn ceil(x: f32) -> f32 {
+    let truncated = x as i64 as f32;
+    if truncated < x {
+        truncated + 1.0
+    } else {
+        truncated
+    }
+}
+
+fn floor(x: f32) -> f32 {
+    -ceil(-x)
+}
+
+fn log2(x: f32) -> f32 {
+    debug_assert!(x.is_normal() && x > 0.0);
+    let bits = x.to_bits();
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127;
+    let mantissa = f64::from(f32::from_bits((bits & 0x007f_ffff) | 0x3f80_0000));
+
+    // ln(m) = 2 * atanh((m - 1) / (m + 1)), whose series converges quickly for m in [1, 2).
+    let z = (mantissa - 1.0) / (mantissa + 1.0);
+    let z2 = z * z;
+    let mut term = z;
+    let mut ln = 0.0;
+    for k in 0..16 {
+        ln += term / f64::from(2 * k + 1);
+        term *= z2;
+    }
+
+    (f64::from(exponent) + 2.0 * ln / core::f64::consts::LN_2) as f32
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_float_helpers() {
+        for x in [-10.5f32, -2.0, -0.25, 0.0, 0.25, 1.0, 7.075, 68.8, 1000.0] {
+            assert_eq!(x.ceil(), ceil(x), "ceil({x})");
+            assert_eq!(x.floor(), floor(x), "floor({x})");
+        }
+        for t in 2..=1025 {
+            let t = t as f32;
+            assert_eq!(t.log2(), log2(t), "log2({t})");
+        }
+    }
+
     use std::fs;
 
     #[test]
@@ -121,7 +175,7 @@ mod tests {
             (65, 61),
         ];
         for (t, rp_expected) in cases.iter() {
-            let (rf, rp) = calc_round_numbers(*t, true);
+            let (rf, rp) = calc_round_numbers(*t, true, 5);
             assert_eq!(rf, 8);
             assert_eq!(rp, *rp_expected);
         }
@@ -169,7 +223,7 @@ mod tests {
         );
 
         for line in lines {
-            let (rf, rp) = calc_round_numbers(line.t, true);
+            let (rf, rp) = calc_round_numbers(line.t, true, 5);
             let sbox_cost = n_sboxes(line.t, rf, rp);
             let size_cost = sbox_cost * PRIME_BITLEN;
 
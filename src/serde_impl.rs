@@ -1,15 +1,16 @@
+use alloc::{format, vec::Vec};
+use core::fmt;
+use core::marker::PhantomData;
 use ff::PrimeField;
 use serde::{
     de::{self, Deserializer, MapAccess, SeqAccess, Visitor},
     ser::{SerializeStruct, Serializer},
     Deserialize, Serialize,
 };
-use std::fmt;
-use std::marker::PhantomData;
 
 use crate::hash_type::HashType;
 use crate::poseidon::PoseidonConstants;
-use crate::Arity;
+use crate::{default_alpha, is_valid_alpha, Arity};
 
 impl<F, A> Serialize for PoseidonConstants<F, A>
 where
@@ -20,7 +21,7 @@ where
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("PoseidonConstants", 8)?;
+        let mut state = serializer.serialize_struct("PoseidonConstants", 9)?;
         state.serialize_field("mds", &self.mds_matrices)?;
         state.serialize_field("crc", &self.compressed_round_constants)?;
         state.serialize_field("psm", &self.pre_sparse_matrix)?;
@@ -29,6 +30,7 @@ where
         state.serialize_field("rf", &self.full_rounds)?;
         state.serialize_field("rp", &self.partial_rounds)?;
         state.serialize_field("ht", &self.hash_type)?;
+        state.serialize_field("a", &self.alpha)?;
         state.end()
     }
 }
@@ -53,6 +55,7 @@ where
             Rf,
             Rp,
             Ht,
+            A,
         }
 
         struct PoseidonConstantsVisitor<F, A>
@@ -103,8 +106,10 @@ where
                 let hash_type: HashType<F, A> = seq
                     .next_element()?
                     .ok_or_else(|| de::Error::invalid_length(7, &self))?;
+                // Constants serialized before the S-box exponent was configurable use the default one.
+                let alpha = seq.next_element()?.unwrap_or_else(default_alpha::<F>);
 
-                Ok(PoseidonConstants {
+                let constants = PoseidonConstants {
                     mds_matrices,
                     round_constants: None,
                     compressed_round_constants,
@@ -116,8 +121,11 @@ where
                     half_full_rounds: full_rounds / 2,
                     partial_rounds,
                     hash_type,
+                    alpha,
                     _a: PhantomData::<A>,
-                })
+                };
+                validate(&constants)?;
+                Ok(constants)
             }
 
             fn visit_map<V>(self, mut map: V) -> Result<PoseidonConstants<F, A>, V::Error>
@@ -132,6 +140,7 @@ where
                 let mut full_rounds = None;
                 let mut partial_rounds = None;
                 let mut hash_type = None;
+                let mut alpha = None;
 
                 while let Some(key) = map.next_key()? {
                     match key {
@@ -185,6 +194,12 @@ where
                             }
                             hash_type = Some(map.next_value()?);
                         }
+                        Field::A => {
+                            if alpha.is_some() {
+                                return Err(de::Error::duplicate_field("alpha"));
+                            }
+                            alpha = Some(map.next_value()?);
+                        }
                     }
                 }
 
@@ -203,7 +218,9 @@ where
                     partial_rounds.ok_or_else(|| de::Error::missing_field("partial_rounds"))?;
                 let hash_type: HashType<F, A> =
                     hash_type.ok_or_else(|| de::Error::missing_field("hash_type"))?;
-                Ok(PoseidonConstants {
+                // Constants serialized before the S-box exponent was configurable use the default one.
+                let alpha = alpha.unwrap_or_else(default_alpha::<F>);
+                let constants = PoseidonConstants {
                     mds_matrices,
                     round_constants: None,
                     compressed_round_constants,
@@ -215,8 +232,11 @@ where
                     half_full_rounds: full_rounds / 2,
                     partial_rounds,
                     hash_type,
+                    alpha,
                     _a: PhantomData::<A>,
-                })
+                };
+                validate(&constants)?;
+                Ok(constants)
             }
         }
 
@@ -229,6 +249,7 @@ where
             "full_rounds",
             "partial_rounds",
             "hash_type",
+            "alpha",
         ];
         deserializer.deserialize_struct(
             "PoseidonConstants",
@@ -241,15 +262,59 @@ where
     }
 }
 
+/// Checks that deserialized constants are consistent with the arity `A`, so that constants generated for another
+/// arity are rejected rather than silently producing wrong hashes.
+fn validate<F, A, E>(constants: &PoseidonConstants<F, A>) -> Result<(), E>
+where
+    F: PrimeField,
+    A: Arity<F>,
+    E: de::Error,
+{
+    let width = A::to_usize() + 1;
+    let is_square = |m: &[Vec<F>]| m.len() == width && m.iter().all(|row| row.len() == width);
+
+    if !is_square(&constants.mds_matrices.m) || !is_square(&constants.pre_sparse_matrix) {
+        return Err(E::custom(format_args!(
+            "matrices are not {width}x{width} as required by arity {}",
+            width - 1
+        )));
+    }
+    if constants.sparse_matrixes.len() != constants.partial_rounds
+        || constants
+            .sparse_matrixes
+            .iter()
+            .any(|sm| sm.w_hat.len() != width || sm.v_rest.len() != width - 1)
+    {
+        return Err(E::custom(
+            "sparse matrices do not match arity and partial rounds",
+        ));
+    }
+    if !is_valid_alpha::<F>(constants.alpha) {
+        return Err(E::custom(format_args!(
+            "x^{} is not a valid S-box for this field",
+            constants.alpha
+        )));
+    }
+    let round_constants = width * constants.full_rounds + constants.partial_rounds;
+    if constants.compressed_round_constants.len() != round_constants {
+        return Err(E::invalid_length(
+            constants.compressed_round_constants.len(),
+            &format!("{round_constants} compressed round constants").as_str(),
+        ));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::Poseidon;
+    use crate::Strength;
     use blstrs::Scalar as Fr;
     use ff::Field;
     use generic_array::typenum;
     use pasta_curves::pallas::Scalar as S1;
-    use typenum::{U1, U2};
+    use typenum::{U1, U2, U4, U8};
 
     #[test]
     fn serde_roundtrip() {
@@ -266,6 +331,49 @@ mod tests {
         );
     }
 
+    #[test]
+    fn serde_alpha() {
+        let mut constants = PoseidonConstants::<Fr, U2>::new_with_alpha(
+            Strength::Standard,
+            HashType::MerkleTree,
+            Some(7),
+        );
+        constants.round_constants = None;
+
+        let json = serde_json::to_vec(&constants).unwrap();
+        assert_eq!(
+            constants,
+            serde_json::from_slice::<PoseidonConstants<Fr, U2>>(&json).unwrap()
+        );
+
+        // A missing exponent is the default one, but an invalid one is rejected.
+        let mut value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        value.as_object_mut().unwrap().remove("a");
+        let constants: PoseidonConstants<Fr, U2> = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(5, constants.alpha);
+
+        value["a"] = 3.into();
+        assert!(serde_json::from_value::<PoseidonConstants<Fr, U2>>(value).is_err());
+    }
+
+    #[test]
+    fn serde_rejects_mismatched_arity() {
+        let constants = PoseidonConstants::<S1, U2>::new();
+
+        let json = serde_json::to_vec(&constants).unwrap();
+        assert!(serde_json::from_slice::<PoseidonConstants<S1, U4>>(&json).is_err());
+        assert!(serde_json::from_slice::<PoseidonConstants<S1, U1>>(&json).is_err());
+
+        let bin = bincode::serialize(&constants).unwrap();
+        assert!(bincode::deserialize::<PoseidonConstants<S1, U4>>(&bin).is_err());
+
+        // Consistent constants of other arities and strengths still round-trip.
+        let mut constants = PoseidonConstants::<Fr, U8>::new_with_strength(Strength::Strengthened);
+        constants.round_constants = None;
+        let json = serde_json::to_vec(&constants).unwrap();
+        assert_eq!(constants, serde_json::from_slice(&json).unwrap());
+    }
+
     #[test]
     fn serde_hash_blstrs() {
         let constants = PoseidonConstants::<Fr, U2>::new();
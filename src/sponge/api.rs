@@ -3,42 +3,450 @@
 /// The API is defined by the `SpongeAPI` trait, which is implemented in terms of the `InnerSpongeAPI` trait.
 /// `Neptune` provides implementations of `InnerSpongeAPI` for both `sponge::Sponge` and `sponge_circuit::SpongeCircuit`.
 use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
+use alloc::{vec, vec::Vec};
+use core::fmt;
 use ff::PrimeField;
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     ParameterUsageMismatch,
+    /// The operation at `index` does not match the one declared by the `IOPattern`.
+    /// `expected` is `None` if the pattern has no operation left at `index`.
+    PatternMismatch {
+        expected: Option<SpongeOp>,
+        got: SpongeOp,
+        index: usize,
+    },
+    /// The op at `index` of an `IOPattern` has a count of zero.
+    ZeroCount {
+        index: usize,
+    },
+    /// The op at `index` of an `IOPattern` has a count which does not fit in 31 bits.
+    CountOverflow {
+        index: usize,
+        count: u32,
+    },
+    /// The bytes passed to `IOPattern::from_bytes` are not a valid encoding; decoding failed at `offset`.
+    InvalidEncoding {
+        offset: usize,
+    },
+    /// The string parsed as an `IOPattern` has an invalid op starting at byte `offset`.
+    InvalidSyntax {
+        offset: usize,
+    },
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ParameterUsageMismatch => write!(
+                f,
+                "The sponge was not used as declared by its IOPattern."
+            ),
+            Error::PatternMismatch {
+                expected: Some(expected),
+                got,
+                index,
+            } => write!(
+                f,
+                "Operation {index} of the IOPattern is {expected:?}, got {got:?}."
+            ),
+            Error::PatternMismatch {
+                expected: None,
+                got,
+                index,
+            } => write!(
+                f,
+                "The IOPattern has no operation {index}, got {got:?}."
+            ),
+            Error::ZeroCount { index } => {
+                write!(f, "Operation {index} of the IOPattern has a count of zero.")
+            }
+            Error::CountOverflow { index, count } => write!(
+                f,
+                "Operation {index} of the IOPattern has count {count}, which does not fit in 31 bits."
+            ),
+            Error::InvalidEncoding { offset } => {
+                write!(f, "Invalid IOPattern encoding at byte {offset}.")
+            }
+            Error::InvalidSyntax { offset } => {
+                write!(f, "Invalid IOPattern op at byte {offset}.")
+            }
+        }
+    }
+}
+
+/// Serializes as a single-entry map from the lowercase name of the op to its count, e.g. `{"absorb":3}` in JSON, or
+/// as the string `"ratchet"`. The capitalized names of earlier versions are still accepted when deserializing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SpongeOp {
+    #[serde(alias = "Absorb")]
     Absorb(u32),
+    #[serde(alias = "Squeeze")]
     Squeeze(u32),
+    /// Permute and zero the rate, keeping only the capacity, as `SpongeAPI::ratchet` does. Ratchets are never
+    /// coalesced with each other or with the ops around them.
+    #[serde(alias = "Ratchet")]
+    Ratchet,
 }
 
-#[derive(Clone, Debug)]
+/// Serializes as the sequence of its ops, e.g. `[{"absorb":3},{"squeeze":1}]` in JSON, which makes a language-neutral
+/// transcript specification. Deserializing checks the counts as `IOPattern::try_new` does, and coalesces consecutive
+/// ops in the same direction as `IOPattern::push` does.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
 pub struct IOPattern(pub Vec<SpongeOp>);
 
+impl<'de> Deserialize<'de> for IOPattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let ops =
+            Self::try_new(Vec::deserialize(deserializer)?).map_err(serde::de::Error::custom)?;
+        let mut pattern = Self(Vec::with_capacity(ops.len()));
+        for op in ops.0 {
+            pattern.push(op);
+            // Both counts fit in 31 bits, so their sum can't overflow before this check.
+            let count = pattern.0[pattern.len() - 1].count();
+            if count >> 31 != 0 {
+                let index = pattern.len() - 1;
+                return Err(serde::de::Error::custom(Error::CountOverflow {
+                    index,
+                    count,
+                }));
+            }
+        }
+        Ok(pattern)
+    }
+}
+
 impl IOPattern {
+    /// Create an `IOPattern`, checking that every absorb and squeeze has a non-zero count which fits in 31 bits.
+    pub fn try_new(ops: Vec<SpongeOp>) -> Result<Self, Error> {
+        for (index, op) in ops.iter().enumerate() {
+            let count = op.count();
+            if count == 0 && !op.is_ratchet() {
+                return Err(Error::ZeroCount { index });
+            }
+            if count >> 31 != 0 {
+                return Err(Error::CountOverflow { index, count });
+            }
+        }
+        Ok(Self(ops))
+    }
+
     pub fn value(&self, domain_separator: u32) -> u128 {
+        self.value_u128(u128::from(domain_separator))
+    }
+
+    /// Like `value`, but accepts a domain separator wider than `u32`.
+    /// Domain separators which fit in a `u32` yield the same value as `value`.
+    pub fn value_u128(&self, domain_separator: u128) -> u128 {
         let mut hasher = Hasher::new();
 
-        for op in self.0.iter() {
+        for op in self {
             hasher.update_op(*op);
         }
-        hasher.finalize(domain_separator)
+        hasher.finalize_u128(domain_separator)
+    }
+
+    /// Returns whether this pattern, with `domain_separator`, produces `tag` as its `value`. This is a debugging aid
+    /// for identifying which of a set of candidate patterns produced an observed tag: the tag is a one-way hash of
+    /// the pattern, so the pattern itself cannot be recovered from it.
+    pub fn matches_tag(&self, tag: u128, domain_separator: u32) -> bool {
+        self.value(domain_separator) == tag
+    }
+
+    /// Returns the number of permutations performed by a `SpongeAPI` sponge of the given `rate` which follows this
+    /// pattern, by replaying its position bookkeeping one op at a time.
+    pub(crate) fn permutation_count(&self, rate: usize) -> usize {
+        let mut permutations = 0;
+        let (mut absorb_pos, mut squeeze_pos) = (0, 0);
+        for op in self {
+            // An element is read or written at `pos`, after permuting if `pos` has reached `rate`.
+            let pos = match op {
+                SpongeOp::Absorb(_) => absorb_pos,
+                SpongeOp::Squeeze(_) => squeeze_pos,
+                SpongeOp::Ratchet => {
+                    permutations += 1;
+                    absorb_pos = 0;
+                    squeeze_pos = rate;
+                    continue;
+                }
+            };
+            let end = pos + op.count() as usize;
+            let crossed = end.saturating_sub(1) / rate;
+            permutations += crossed;
+            match op {
+                SpongeOp::Absorb(_) => {
+                    absorb_pos = end - crossed * rate;
+                    squeeze_pos = rate;
+                }
+                SpongeOp::Squeeze(_) => {
+                    squeeze_pos = end - crossed * rate;
+                    if crossed > 0 {
+                        absorb_pos = 0;
+                    }
+                }
+                SpongeOp::Ratchet => unreachable!(),
+            }
+        }
+        permutations
+    }
+
+    /// Returns whether the pattern is in absorb-then-squeeze normal form: once consecutive ops of the same kind are
+    /// coalesced, as they are for the tag, it is a single absorb followed by a single squeeze. Patterns which
+    /// interleave absorbs and squeezes, lack either kind, or contain an op with a count of zero are not simple.
+    pub fn is_simple(&self) -> bool {
+        let absorbs = self.0.iter().take_while(|op| op.is_absorb()).count();
+        let squeezes = &self.0[absorbs..];
+        absorbs > 0
+            && !squeezes.is_empty()
+            && squeezes.iter().all(|op| op.is_squeeze())
+            && self.0.iter().all(|op| op.count() > 0)
     }
 
     pub fn op_at(&self, i: usize) -> Option<&SpongeOp> {
         self.0.get(i)
     }
+
+    pub fn ops(&self) -> &[SpongeOp] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Encode the pattern compactly: each op is encoded as the LEB128 varint of its count shifted left by one, with
+    /// the low bit set for squeezes. A ratchet is encoded as the varint of `1 << 33`, which is too large to encode a
+    /// count.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len() * 2);
+        for op in self {
+            let mut value = match op {
+                SpongeOp::Absorb(n) => u64::from(*n) << 1,
+                SpongeOp::Squeeze(n) => u64::from(*n) << 1 | 1,
+                SpongeOp::Ratchet => RATCHET_ENCODING,
+            };
+            loop {
+                let byte = (value & 0x7f) as u8;
+                value >>= 7;
+                if value == 0 {
+                    bytes.push(byte);
+                    break;
+                }
+                bytes.push(byte | 0x80);
+            }
+        }
+        bytes
+    }
+
+    /// Decode a pattern encoded by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut ops = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let start = offset;
+            let mut value = 0u64;
+            let mut shift = 0;
+            loop {
+                let byte = *bytes
+                    .get(offset)
+                    .ok_or(Error::InvalidEncoding { offset: start })?;
+                // A count takes at most 32 bits, so an op takes at most 5 bytes.
+                if shift > 28 {
+                    return Err(Error::InvalidEncoding { offset });
+                }
+                value |= u64::from(byte & 0x7f) << shift;
+                shift += 7;
+                offset += 1;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            if value == RATCHET_ENCODING {
+                ops.push(SpongeOp::Ratchet);
+                continue;
+            }
+            let count =
+                u32::try_from(value >> 1).map_err(|_| Error::InvalidEncoding { offset: start })?;
+            ops.push(if value & 1 == 0 {
+                SpongeOp::Absorb(count)
+            } else {
+                SpongeOp::Squeeze(count)
+            });
+        }
+        Ok(Self(ops))
+    }
+
+    /// Deserialize the ops of a pattern as they are, without checking or coalescing them. This is used for the pattern
+    /// of a `SpongeState`, whose `io_count` indexes into the ops of the pattern the sponge was started with.
+    pub(crate) fn deserialize_ops<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::deserialize(deserializer).map(Self)
+    }
+
+    /// Append `op`, coalescing it with the last op if both have the same direction.
+    pub fn push(&mut self, op: SpongeOp) {
+        match self.0.last_mut() {
+            Some(last) if last.matches(op) => *last = last.combine(op),
+            _ => self.0.push(op),
+        }
+    }
+}
+
+/// Formats the op compactly, as `A` for absorb or `S` for squeeze, followed by its count in decimal, e.g. `A3`, or as
+/// `R` for a ratchet.
+impl fmt::Display for SpongeOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpongeOp::Absorb(n) => write!(f, "A{n}"),
+            SpongeOp::Squeeze(n) => write!(f, "S{n}"),
+            SpongeOp::Ratchet => write!(f, "R"),
+        }
+    }
+}
+
+impl core::str::FromStr for SpongeOp {
+    type Err = Error;
+
+    /// Parse an op formatted by its `Display` impl. The count must consist of decimal digits only.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "R" {
+            return Ok(SpongeOp::Ratchet);
+        }
+        let (direction, count) = s.split_at(s.find(|c: char| c.is_ascii_digit()).unwrap_or(0));
+        let count = Some(count)
+            .filter(|count| !count.is_empty() && count.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|count| count.parse().ok());
+        match (direction, count) {
+            ("A", Some(count)) => Ok(SpongeOp::Absorb(count)),
+            ("S", Some(count)) => Ok(SpongeOp::Squeeze(count)),
+            _ => Err(Error::InvalidSyntax { offset: 0 }),
+        }
+    }
+}
+
+/// Formats the pattern as its ops, formatted as by [`SpongeOp`]'s `Display` impl and separated by single spaces, e.g.
+/// `A3 S1 A2 S1`. The empty pattern is formatted as the empty string. This can be parsed back with `str::parse`.
+impl fmt::Display for IOPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, op) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{op}")?;
+        }
+        Ok(())
+    }
+}
+
+impl core::str::FromStr for IOPattern {
+    type Err = Error;
+
+    /// Parse a pattern formatted by its `Display` impl. Ops may be separated by any amount of whitespace. As with
+    /// `IOPattern::from_bytes`, counts are not checked: use `IOPattern::try_new` for that. Consecutive ops in the same
+    /// direction are kept separate, so that parsing round-trips.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_whitespace()
+            .map(|op| {
+                op.parse().map_err(|_| Error::InvalidSyntax {
+                    offset: op.as_ptr() as usize - s.as_ptr() as usize,
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl<'a> IntoIterator for &'a IOPattern {
+    type Item = &'a SpongeOp;
+    type IntoIter = core::slice::Iter<'a, SpongeOp>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// Derive a domain separator from a human-readable `label`, as used by `Sponge::new_named`.
+///
+/// The separator is the first 16 bytes of the BLAKE2s-256 digest of the UTF-8 encoding of `label`, read as a
+/// little-endian `u128`. The derivation is part of the stable API: a given label always yields the same separator.
+pub fn label_domain_separator(label: &str) -> u128 {
+    let digest = blake2s_simd::blake2s(label.as_bytes());
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(&digest.as_bytes()[..16]);
+    u128::from_le_bytes(bytes)
+}
+
+/// A domain separator passed to `SpongeAPI::start`, which is mixed into the tag of the `IOPattern`.
+///
+/// The default separator is not set, and `start` replaces it with the sponge's own separator: that is 0, unless the
+/// sponge was constructed with a label by `Sponge::new_named`. Any separator which is set, including an explicit 0, is
+/// used as given.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DomainSeparator(Option<u128>);
+
+impl DomainSeparator {
+    pub const fn from_u32(domain_separator: u32) -> Self {
+        Self(Some(domain_separator as u128))
+    }
+
+    pub const fn from_u128(domain_separator: u128) -> Self {
+        Self(Some(domain_separator))
+    }
+
+    /// The separator derived from `label` by `label_domain_separator`.
+    pub fn from_label(label: &str) -> Self {
+        Self(Some(label_domain_separator(label)))
+    }
+
+    /// Returns the separator, or 0 if it is not set.
+    pub const fn value(&self) -> u128 {
+        match self.0 {
+            Some(domain_separator) => domain_separator,
+            None => 0,
+        }
+    }
+
+    /// Returns whether the separator is not set, so that `start` uses the sponge's own.
+    pub const fn is_default(&self) -> bool {
+        self.0.is_none()
+    }
+}
+
+impl From<u32> for DomainSeparator {
+    fn from(domain_separator: u32) -> Self {
+        Self::from_u32(domain_separator)
+    }
+}
+
+impl From<u128> for DomainSeparator {
+    fn from(domain_separator: u128) -> Self {
+        Self::from_u128(domain_separator)
+    }
 }
 
 // A large 128-bit prime, per https://primes.utm.edu/lists/2small/100bit.html.
 const HASHER_BASE: u128 = (0 - 159) as u128;
 
+/// Computes the tag of an `IOPattern` incrementally, one `SpongeOp` at a time, without materializing the pattern.
+/// Feeding the ops of a pattern to `update_op` and calling `finalize` yields the same value as `IOPattern::value`.
 #[derive(Clone, Copy, Debug)]
-pub(crate) struct Hasher {
+pub struct Hasher {
     x: u128,
     x_i: u128,
     state: u128,
@@ -57,12 +465,12 @@ impl Default for Hasher {
 }
 
 impl Hasher {
-    pub(crate) fn new() -> Self {
+    pub fn new() -> Self {
         Default::default()
     }
 
     /// Update hasher's current op to coalesce absorb/squeeze runs.
-    pub(crate) fn update_op(&mut self, op: SpongeOp) {
+    pub fn update_op(&mut self, op: SpongeOp) {
         if self.current_op.matches(op) {
             self.current_op = self.current_op.combine(op)
         } else {
@@ -72,7 +480,7 @@ impl Hasher {
     }
 
     fn finish_op(&mut self) {
-        if self.current_op.count() == 0 {
+        if self.current_op.count() == 0 && !self.current_op.is_ratchet() {
             return;
         };
         let op_value = self.current_op.value();
@@ -88,9 +496,27 @@ impl Hasher {
             .0;
     }
 
-    pub(crate) fn finalize(&mut self, domain_separator: u32) -> u128 {
+    pub fn finalize(&mut self, domain_separator: u32) -> u128 {
+        self.finalize_u128(u128::from(domain_separator))
+    }
+
+    /// Finalize with a domain separator wider than `u32`. A separator which fits in a `u32` is absorbed exactly as by
+    /// `finalize`. A wider one is absorbed as a zero, which no op contributes, followed by all four of its `u32` limbs,
+    /// least significant first. Since the ops of a pattern contribute non-zero values, this fixed-width encoding
+    /// cannot be mistaken for further ops followed by a narrow separator, or for a different pattern with another
+    /// wide separator.
+    pub fn finalize_u128(&mut self, domain_separator: u128) -> u128 {
         self.finish_op();
-        self.update(domain_separator);
+
+        match u32::try_from(domain_separator) {
+            Ok(domain_separator) => self.update(domain_separator),
+            Err(_) => {
+                self.update(0);
+                for limb in 0..4 {
+                    self.update((domain_separator >> (32 * limb)) as u32);
+                }
+            }
+        }
         self.state
     }
 }
@@ -99,13 +525,15 @@ impl SpongeOp {
     pub const fn reset(&self) -> Self {
         match self {
             Self::Absorb(_) => Self::Squeeze(0),
-            Self::Squeeze(_) => Self::Absorb(0),
+            Self::Squeeze(_) | Self::Ratchet => Self::Absorb(0),
         }
     }
 
+    /// Returns the number of elements absorbed or squeezed, which is 0 for a ratchet.
     pub const fn count(&self) -> u32 {
         match self {
             Self::Absorb(n) | Self::Squeeze(n) => *n,
+            Self::Ratchet => 0,
         }
     }
 
@@ -117,19 +545,30 @@ impl SpongeOp {
         matches!(self, Self::Squeeze(_))
     }
 
+    pub const fn is_ratchet(&self) -> bool {
+        matches!(self, Self::Ratchet)
+    }
+
     pub fn combine(&self, other: Self) -> Self {
         assert!(self.matches(other));
 
         match self {
             Self::Absorb(n) => Self::Absorb(n + other.count()),
             Self::Squeeze(n) => Self::Squeeze(n + other.count()),
+            Self::Ratchet => unreachable!(),
         }
     }
 
+    /// Returns whether `other` can be coalesced with this op: both absorb or both squeeze.
     pub const fn matches(&self, other: Self) -> bool {
-        self.is_absorb() == other.is_absorb()
+        matches!(
+            (self, other),
+            (Self::Absorb(_), Self::Absorb(_)) | (Self::Squeeze(_), Self::Squeeze(_))
+        )
     }
 
+    /// Returns the op's contribution to the pattern tag. A ratchet contributes `1 << 31`, the value of an absorb of
+    /// zero elements, which is never contributed since zero counts are skipped.
     pub fn value(&self) -> u32 {
         match self {
             Self::Absorb(n) => {
@@ -140,19 +579,145 @@ impl SpongeOp {
                 assert_eq!(0, n >> 31);
                 *n
             }
+            Self::Ratchet => 1 << 31,
         }
     }
 }
 
+/// The `to_bytes` encoding of a ratchet, before varint encoding.
+const RATCHET_ENCODING: u64 = 1 << 33;
+
+/// The largest count of an op, or of consecutive ops in the same direction, which `IOPattern::value` accepts.
+#[cfg(feature = "arbitrary")]
+const MAX_ARBITRARY_COUNT: u32 = (1 << 31) - 1;
+
+/// Generates only ops accepted by `IOPattern::try_new`: the count is non-zero and fits in 31 bits.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for SpongeOp {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let count = u.int_in_range(1..=MAX_ARBITRARY_COUNT)?;
+        Ok(if u.arbitrary()? {
+            Self::Absorb(count)
+        } else {
+            Self::Squeeze(count)
+        })
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        arbitrary::size_hint::and(u32::size_hint(depth), bool::size_hint(depth))
+    }
+}
+
+/// Generates only patterns accepted by `IOPattern::try_new` whose `value` can be computed: since consecutive ops in
+/// the same direction are combined, their total count must fit in 31 bits too.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for IOPattern {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let len = u.arbitrary_len::<SpongeOp>()?;
+        let mut ops: Vec<SpongeOp> = Vec::with_capacity(len);
+        let mut run_count = 0;
+        for _ in 0..len {
+            let mut absorb: bool = u.arbitrary()?;
+            match ops.last() {
+                Some(last) if last.is_absorb() == absorb => {
+                    if run_count == MAX_ARBITRARY_COUNT {
+                        absorb = !absorb;
+                        run_count = 0;
+                    }
+                }
+                _ => run_count = 0,
+            }
+
+            let count = u.int_in_range(1..=MAX_ARBITRARY_COUNT - run_count)?;
+            run_count += count;
+            ops.push(if absorb {
+                SpongeOp::Absorb(count)
+            } else {
+                SpongeOp::Squeeze(count)
+            });
+        }
+        Ok(Self(ops))
+    }
+}
+
 pub trait SpongeAPI<F: PrimeField, A: Arity<F>> {
     type Acc;
     type Value;
 
-    /// Optional `domain_separator` defaults to 0
-    fn start(&mut self, p: IOPattern, domain_separator: Option<u32>, _: &mut Self::Acc);
+    /// Start the sponge with `p`. A `u32` may be passed for `domain_separator`, and `DomainSeparator::default()` is 0.
+    fn start(
+        &mut self,
+        p: IOPattern,
+        domain_separator: impl Into<DomainSeparator>,
+        acc: &mut Self::Acc,
+    ) {
+        self.start_with_domain(p, domain_separator.into().value(), acc);
+    }
+    /// Like `start`, with its former signature: `None` is the default separator, and `Some` an explicit one.
+    #[deprecated(note = "pass a `u32` or a `DomainSeparator` to `start` instead")]
+    fn start_optional(&mut self, p: IOPattern, domain_separator: Option<u32>, acc: &mut Self::Acc) {
+        let domain_separator =
+            domain_separator.map_or_else(DomainSeparator::default, DomainSeparator::from_u32);
+        self.start(p, domain_separator, acc);
+    }
+    /// Like `start`, but accepts a domain separator wider than `u32`.
+    fn start_with_domain(&mut self, p: IOPattern, domain_separator: u128, _: &mut Self::Acc);
+    /// Absorb the `length` elements of `elements` as the next op of the `IOPattern`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `elements` does not have `length` elements, if the next op of the `IOPattern` is not
+    /// `Absorb(length)`, or if `length` is zero, even when the pattern declares it (see `try_absorb`).
     fn absorb(&mut self, length: u32, elements: &[Self::Value], acc: &mut Self::Acc);
+    /// Squeeze `length` elements as the next op of the `IOPattern`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the next op of the `IOPattern` is not `Squeeze(length)`, or if `length` is zero, even when the
+    /// pattern declares it (see `try_absorb`).
     fn squeeze(&mut self, length: u32, acc: &mut Self::Acc) -> Vec<Self::Value>;
     fn finish(&mut self, _: &mut Self::Acc) -> Result<(), Error>;
+    /// Like `finish`, but clears the state whether or not every op of the `IOPattern` was performed, for streaming
+    /// protocols which cannot declare their exact pattern in advance: they can declare a pattern long enough for any
+    /// run, and finish after any prefix of it. Returns the number of ops which were performed.
+    ///
+    /// This weakens domain separation. The tag which initializes the capacity commits to the declared pattern rather
+    /// than to the ops performed, so a run which stops early produces the same outputs as the start of a longer run
+    /// with the same pattern, and a transcript does not show where the run ended. Protocols which need to tell such
+    /// runs apart must do so themselves, e.g. by absorbing a length or a terminator before squeezing.
+    fn finish_partial(&mut self, _: &mut Self::Acc) -> usize;
+
+    /// Like `absorb`, but returns an error instead of panicking if the operation does not match the `IOPattern`.
+    /// The sponge state is left untouched when an error is returned.
+    ///
+    /// Absorbing or squeezing zero elements is rejected with `Error::ZeroCount`, even if the pattern declares it, as
+    /// `IOPattern::try_new` rejects such ops: they would still advance the pattern, and a zero-length absorb would
+    /// force the next squeeze to permute.
+    fn try_absorb(
+        &mut self,
+        length: u32,
+        elements: &[Self::Value],
+        acc: &mut Self::Acc,
+    ) -> Result<(), Error>;
+    /// Like `squeeze`, but returns an error instead of panicking if the operation does not match the `IOPattern`.
+    /// The sponge state is left untouched when an error is returned.
+    fn try_squeeze(&mut self, length: u32, acc: &mut Self::Acc) -> Result<Vec<Self::Value>, Error>;
+    /// Like `try_squeeze`, but writes the squeezed elements into `out` rather than allocating a `Vec`. Returns
+    /// `Error::ParameterUsageMismatch` if `out` does not have `length` elements, like `try_absorb`. The sponge state is
+    /// left untouched when an error is returned.
+    fn squeeze_into(
+        &mut self,
+        length: u32,
+        out: &mut [Self::Value],
+        acc: &mut Self::Acc,
+    ) -> Result<(), Error>;
+    /// Perform the next op of the `IOPattern`, which must be `SpongeOp::Ratchet`: permute, so that the state depends
+    /// on everything absorbed so far, then zero the rate, keeping only the capacity. Permuting is invertible, but
+    /// not once the rate is discarded, so the state after a ratchet no longer determines the elements squeezed
+    /// before it: a transcript whose state leaks later does not reveal its earlier outputs. The next squeeze permutes
+    /// again before reading the rate. Returns `Error::PatternMismatch`, leaving the sponge untouched, if the pattern
+    /// expects a different op.
+    fn ratchet(&mut self, acc: &mut Self::Acc) -> Result<(), Error>;
 }
 
 pub trait InnerSpongeAPI<F: PrimeField, A: Arity<F>> {
@@ -184,17 +749,123 @@ pub trait InnerSpongeAPI<F: PrimeField, A: Arity<F>> {
     fn pattern(&self) -> &IOPattern;
     fn set_pattern(&mut self, pattern: IOPattern);
 
+    fn io_count(&self) -> usize;
     fn increment_io_count(&mut self) -> usize;
 
     fn zero() -> Self::Value;
+
+    /// The domain separator used by `SpongeAPI::start` in place of the default one.
+    fn domain_separator(&self) -> u128 {
+        0
+    }
+
+    /// Whether an absorb permutes before returning, so that the next squeeze reads the rate without permuting first.
+    fn squeeze_before_permute(&self) -> bool {
+        false
+    }
+
+    /// Whether absorbed elements overwrite the rate elements rather than being added to them, as in `Mode::Overwrite`.
+    fn absorb_overwrites(&self) -> bool {
+        false
+    }
+}
+
+/// Check that `op` is the next operation expected by the sponge's `IOPattern`.
+fn check_op<F: PrimeField, A: Arity<F>, S: InnerSpongeAPI<F, A>>(
+    sponge: &S,
+    op: SpongeOp,
+) -> Result<(), Error> {
+    let index = sponge.io_count();
+    let expected = sponge.pattern().op_at(index);
+
+    if expected == Some(&op) {
+        Ok(())
+    } else {
+        Err(Error::PatternMismatch {
+            expected: expected.copied(),
+            got: op,
+            index,
+        })
+    }
+}
+
+/// Check that the next operation does not have a count of zero.
+fn check_nonzero<F: PrimeField, A: Arity<F>, S: InnerSpongeAPI<F, A>>(
+    sponge: &S,
+    length: u32,
+) -> Result<(), Error> {
+    if length == 0 {
+        Err(Error::ZeroCount {
+            index: sponge.io_count(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Absorb `elements` into the rate elements, permuting at rate boundaries, as part of the current absorb op. The
+/// `IOPattern` is neither checked nor advanced, so the caller must absorb exactly as many elements as the op declares
+/// before calling `complete_absorb`. This lets an op be absorbed in parts, as they become available.
+pub(crate) fn absorb_partial<F: PrimeField, A: Arity<F>, S: InnerSpongeAPI<F, A>>(
+    sponge: &mut S,
+    elements: &[S::Value],
+    acc: &mut S::Acc,
+) {
+    let rate = sponge.rate();
+
+    for element in elements.iter() {
+        if sponge.absorb_pos() == rate {
+            sponge.permute(acc);
+            sponge.set_absorb_pos(0);
+        }
+        if sponge.absorb_overwrites() {
+            sponge.add_rate_element(sponge.absorb_pos(), element);
+        } else {
+            let old = sponge.read_rate_element(sponge.absorb_pos());
+            sponge.add_rate_element(sponge.absorb_pos(), &S::add(old, element));
+        }
+        sponge.set_absorb_pos(sponge.absorb_pos() + 1);
+    }
+}
+
+/// Complete the current absorb op, after all of its elements have been absorbed with `absorb_partial`.
+pub(crate) fn complete_absorb<F: PrimeField, A: Arity<F>, S: InnerSpongeAPI<F, A>>(
+    sponge: &mut S,
+    acc: &mut S::Acc,
+) {
+    sponge.increment_io_count();
+    if sponge.squeeze_before_permute() {
+        sponge.permute(acc);
+        sponge.set_absorb_pos(0);
+        sponge.set_squeeze_pos(0);
+    } else {
+        sponge.set_squeeze_pos(sponge.rate());
+    }
 }
 
 impl<F: PrimeField, A: Arity<F>, S: InnerSpongeAPI<F, A>> SpongeAPI<F, A> for S {
     type Acc = <S as InnerSpongeAPI<F, A>>::Acc;
     type Value = <S as InnerSpongeAPI<F, A>>::Value;
 
-    fn start(&mut self, p: IOPattern, domain_separator: Option<u32>, acc: &mut Self::Acc) {
-        let p_value = p.value(domain_separator.unwrap_or(0));
+    /// A `domain_separator` which is not set is replaced by the sponge's own, which is 0 unless it was constructed with
+    /// a label.
+    fn start(
+        &mut self,
+        p: IOPattern,
+        domain_separator: impl Into<DomainSeparator>,
+        acc: &mut Self::Acc,
+    ) {
+        let domain_separator = domain_separator.into();
+        let domain_separator = if domain_separator.is_default() {
+            self.domain_separator()
+        } else {
+            domain_separator.value()
+        };
+        self.start_with_domain(p, domain_separator, acc);
+    }
+
+    fn start_with_domain(&mut self, p: IOPattern, domain_separator: u128, acc: &mut Self::Acc) {
+        let p_value = p.value_u128(domain_separator);
 
         self.set_pattern(p);
         self.initialize_state(p_value, acc);
@@ -205,43 +876,89 @@ impl<F: PrimeField, A: Arity<F>, S: InnerSpongeAPI<F, A>> SpongeAPI<F, A> for S
 
     fn absorb(&mut self, length: u32, elements: &[Self::Value], acc: &mut Self::Acc) {
         assert_eq!(length as usize, elements.len());
-        let rate = self.rate();
+        assert!(length != 0, "cannot absorb zero elements");
 
-        for element in elements.iter() {
-            if self.absorb_pos() == rate {
-                self.permute(acc);
-                self.set_absorb_pos(0);
-            }
-            let old = self.read_rate_element(self.absorb_pos());
-            self.add_rate_element(self.absorb_pos(), &S::add(old, element));
-            self.set_absorb_pos(self.absorb_pos() + 1);
-        }
         let op = SpongeOp::Absorb(length);
-        let old_count = self.increment_io_count();
-        assert_eq!(Some(&op), self.pattern().op_at(old_count));
+        assert_eq!(Some(&op), self.pattern().op_at(self.io_count()));
 
-        self.set_squeeze_pos(rate);
+        self.try_absorb(length, elements, acc).unwrap();
     }
 
     fn squeeze(&mut self, length: u32, acc: &mut Self::Acc) -> Vec<Self::Value> {
-        let rate = self.rate();
+        assert!(length != 0, "cannot squeeze zero elements");
+
+        let op = SpongeOp::Squeeze(length);
+        assert_eq!(Some(&op), self.pattern().op_at(self.io_count()));
+
+        self.try_squeeze(length, acc).unwrap()
+    }
+
+    fn try_absorb(
+        &mut self,
+        length: u32,
+        elements: &[Self::Value],
+        acc: &mut Self::Acc,
+    ) -> Result<(), Error> {
+        if length as usize != elements.len() {
+            return Err(Error::ParameterUsageMismatch);
+        }
+        check_nonzero(self, length)?;
+        check_op(self, SpongeOp::Absorb(length))?;
+
+        absorb_partial(self, elements, acc);
+        complete_absorb(self, acc);
+        Ok(())
+    }
 
-        let mut out = Vec::with_capacity(length as usize);
+    fn try_squeeze(&mut self, length: u32, acc: &mut Self::Acc) -> Result<Vec<Self::Value>, Error> {
+        // Check before allocating, since `length` may be arbitrarily large.
+        check_nonzero(self, length)?;
+        check_op(self, SpongeOp::Squeeze(length))?;
 
-        for _ in 0..length {
+        let mut out: Vec<_> = (0..length).map(|_| S::zero()).collect();
+        self.squeeze_into(length, &mut out, acc)?;
+        Ok(out)
+    }
+
+    fn squeeze_into(
+        &mut self,
+        length: u32,
+        out: &mut [Self::Value],
+        acc: &mut Self::Acc,
+    ) -> Result<(), Error> {
+        if length as usize != out.len() {
+            return Err(Error::ParameterUsageMismatch);
+        }
+        check_nonzero(self, length)?;
+        check_op(self, SpongeOp::Squeeze(length))?;
+
+        let rate = self.rate();
+
+        for elt in out.iter_mut() {
             if self.squeeze_pos() == rate {
                 self.permute(acc);
                 self.set_squeeze_pos(0);
                 self.set_absorb_pos(0);
             }
-            out.push(self.read_rate_element(self.squeeze_pos()));
+            *elt = self.read_rate_element(self.squeeze_pos());
             self.set_squeeze_pos(self.squeeze_pos() + 1);
         }
-        let op = SpongeOp::Squeeze(length);
-        let old_count = self.increment_io_count();
-        assert_eq!(Some(&op), self.pattern().op_at(old_count));
+        self.increment_io_count();
+
+        Ok(())
+    }
 
-        out
+    fn ratchet(&mut self, acc: &mut Self::Acc) -> Result<(), Error> {
+        check_op(self, SpongeOp::Ratchet)?;
+
+        self.permute(acc);
+        for i in 0..self.rate() {
+            self.add_rate_element(i, &S::zero());
+        }
+        self.set_absorb_pos(0);
+        self.set_squeeze_pos(self.rate());
+        self.increment_io_count();
+        Ok(())
     }
 
     fn finish(&mut self, acc: &mut Self::Acc) -> Result<(), Error> {
@@ -249,12 +966,17 @@ impl<F: PrimeField, A: Arity<F>, S: InnerSpongeAPI<F, A>> SpongeAPI<F, A> for S
         self.initialize_state(0, acc);
         let final_io_count = self.increment_io_count();
 
-        if final_io_count == self.pattern().0.len() {
+        if final_io_count == self.pattern().len() {
             Ok(())
         } else {
             Err(Error::ParameterUsageMismatch)
         }
     }
+
+    fn finish_partial(&mut self, acc: &mut Self::Acc) -> usize {
+        self.initialize_state(0, acc);
+        self.increment_io_count()
+    }
 }
 
 #[cfg(test)]
@@ -308,4 +1030,380 @@ mod test {
             0,
         );
     }
+
+    #[test]
+    fn test_pattern_push() {
+        let mut pattern = IOPattern(vec![]);
+        assert!(pattern.is_empty());
+
+        pattern.push(SpongeOp::Absorb(2));
+        pattern.push(SpongeOp::Absorb(1));
+        pattern.push(SpongeOp::Squeeze(1));
+        pattern.push(SpongeOp::Squeeze(1));
+        pattern.push(SpongeOp::Absorb(4));
+
+        assert_eq!(3, pattern.len());
+        assert_eq!(
+            &[
+                SpongeOp::Absorb(3),
+                SpongeOp::Squeeze(2),
+                SpongeOp::Absorb(4)
+            ],
+            pattern.ops()
+        );
+        assert_eq!(
+            pattern.ops().to_vec(),
+            (&pattern).into_iter().copied().collect::<Vec<_>>()
+        );
+
+        // Coalescing does not change the tag.
+        let uncoalesced = IOPattern(vec![
+            SpongeOp::Absorb(2),
+            SpongeOp::Absorb(1),
+            SpongeOp::Squeeze(1),
+            SpongeOp::Squeeze(1),
+            SpongeOp::Absorb(4),
+        ]);
+        assert_eq!(uncoalesced.value(0), pattern.value(0));
+    }
+
+    #[test]
+    fn test_pattern_json() {
+        let pattern = IOPattern(vec![
+            SpongeOp::Absorb(3),
+            SpongeOp::Squeeze(1),
+            SpongeOp::Ratchet,
+        ]);
+        let json = r#"[{"absorb":3},{"squeeze":1},"ratchet"]"#;
+        assert_eq!(json, serde_json::to_string(&pattern).unwrap());
+        assert_eq!(pattern, serde_json::from_str(json).unwrap());
+
+        // Consecutive ops in the same direction are coalesced, but not across a ratchet.
+        let json = r#"[{"absorb":1},{"absorb":2},{"squeeze":1},"ratchet","ratchet",{"squeeze":2}]"#;
+        assert_eq!(
+            IOPattern(vec![
+                SpongeOp::Absorb(3),
+                SpongeOp::Squeeze(1),
+                SpongeOp::Ratchet,
+                SpongeOp::Ratchet,
+                SpongeOp::Squeeze(2),
+            ]),
+            serde_json::from_str(json).unwrap()
+        );
+
+        // The capitalized names of the derived representation are still accepted.
+        assert_eq!(
+            pattern,
+            serde_json::from_str(r#"[{"Absorb":3},{"Squeeze":1},"Ratchet"]"#).unwrap()
+        );
+
+        for (json, message) in [
+            (
+                r#"[{"absorb":1},{"squeeze":0}]"#,
+                "Operation 1 of the IOPattern has a count of zero.",
+            ),
+            (
+                r#"[{"squeeze":2147483648}]"#,
+                "Operation 0 of the IOPattern has count 2147483648, which does not fit in 31 bits.",
+            ),
+            (
+                r#"[{"squeeze":1},{"absorb":2147483647},{"absorb":1}]"#,
+                "Operation 1 of the IOPattern has count 2147483648, which does not fit in 31 bits.",
+            ),
+        ] {
+            let err = serde_json::from_str::<IOPattern>(json).unwrap_err();
+            assert!(err.to_string().starts_with(message), "{err}");
+        }
+        assert!(serde_json::from_str::<IOPattern>(r#"[{"permute":1}]"#).is_err());
+    }
+
+    #[test]
+    fn test_pattern_is_simple() {
+        for (pattern, simple) in [
+            ("A3 S1", true),
+            ("A1 A2 S1 S4", true),
+            ("A2 S1 A1 S1", false),
+            ("S1 A1", false),
+            ("A3", false),
+            ("S3", false),
+            ("", false),
+        ] {
+            assert_eq!(
+                simple,
+                pattern.parse::<IOPattern>().unwrap().is_simple(),
+                "{pattern}"
+            );
+        }
+        assert!(!IOPattern(vec![SpongeOp::Absorb(1), SpongeOp::Squeeze(0)]).is_simple());
+    }
+
+    #[test]
+    fn test_ratchet_op() {
+        let p: IOPattern = "A2 R R S1".parse().unwrap();
+        assert_eq!(
+            IOPattern(vec![
+                SpongeOp::Absorb(2),
+                SpongeOp::Ratchet,
+                SpongeOp::Ratchet,
+                SpongeOp::Squeeze(1)
+            ]),
+            p
+        );
+        assert_eq!("A2 R R S1", p.to_string());
+        assert_eq!(p, IOPattern::from_bytes(&p.to_bytes()).unwrap());
+        assert_eq!(p, IOPattern::try_new(p.0.clone()).unwrap());
+        assert!(!p.is_simple());
+
+        // Ratchets are tagged, and neither coalesce with each other nor separate the ops around them.
+        let tags: Vec<u128> = ["A2 S1", "A2 R S1", "A2 R R S1", "A1 R A1 S1", "A2 S1 R"]
+            .iter()
+            .map(|p| p.parse::<IOPattern>().unwrap().value(0))
+            .collect();
+        for (i, tag) in tags.iter().enumerate() {
+            assert!(!tags[..i].contains(tag));
+        }
+        let mut pushed = IOPattern(vec![]);
+        for op in [SpongeOp::Ratchet, SpongeOp::Ratchet, SpongeOp::Absorb(1)] {
+            pushed.push(op);
+        }
+        assert_eq!(3, pushed.len());
+    }
+
+    #[test]
+    fn test_pattern_try_new() {
+        let ops = vec![SpongeOp::Absorb(2), SpongeOp::Squeeze((1 << 31) - 1)];
+        assert_eq!(ops, IOPattern::try_new(ops.clone()).unwrap().0);
+
+        assert!(matches!(
+            IOPattern::try_new(vec![SpongeOp::Absorb(2), SpongeOp::Squeeze(0)]),
+            Err(Error::ZeroCount { index: 1 })
+        ));
+        assert!(matches!(
+            IOPattern::try_new(vec![SpongeOp::Absorb(1 << 31)]),
+            Err(Error::CountOverflow {
+                index: 0,
+                count: 0x80000000
+            })
+        ));
+    }
+
+    #[test]
+    fn test_error_display() {
+        let err: Box<dyn std::error::Error> = Box::new(Error::PatternMismatch {
+            expected: Some(SpongeOp::Absorb(2)),
+            got: SpongeOp::Squeeze(1),
+            index: 1,
+        });
+        assert_eq!(
+            "Operation 1 of the IOPattern is Absorb(2), got Squeeze(1).",
+            err.to_string()
+        );
+        assert_eq!(
+            "Invalid IOPattern encoding at byte 3.",
+            Error::InvalidEncoding { offset: 3 }.to_string()
+        );
+    }
+
+    #[test]
+    fn test_matches_tag() {
+        let candidates = [
+            IOPattern(vec![SpongeOp::Absorb(2), SpongeOp::Squeeze(1)]),
+            IOPattern(vec![
+                SpongeOp::Absorb(1),
+                SpongeOp::Absorb(1),
+                SpongeOp::Squeeze(1),
+            ]),
+            IOPattern(vec![SpongeOp::Absorb(3), SpongeOp::Squeeze(1)]),
+            IOPattern(vec![SpongeOp::Absorb(2), SpongeOp::Squeeze(2)]),
+        ];
+        let tag = IOPattern(vec![SpongeOp::Absorb(3), SpongeOp::Squeeze(1)]).value(7);
+
+        let matching: Vec<_> = candidates
+            .iter()
+            .filter(|p| p.matches_tag(tag, 7))
+            .collect();
+        assert_eq!(vec![&candidates[2]], matching);
+        assert!(!candidates[2].matches_tag(tag, 8));
+
+        // Consecutive ops of the same kind are combined, so these patterns are indistinguishable by tag.
+        assert!(candidates[1].matches_tag(candidates[0].value(7), 7));
+    }
+
+    #[test]
+    fn test_pattern_bytes() {
+        let patterns = [
+            IOPattern(vec![]),
+            IOPattern(vec![SpongeOp::Absorb(1), SpongeOp::Squeeze(1)]),
+            IOPattern(vec![
+                SpongeOp::Absorb(63),
+                SpongeOp::Squeeze(64),
+                SpongeOp::Absorb(300),
+                SpongeOp::Squeeze((1 << 31) - 1),
+                SpongeOp::Absorb(2),
+            ]),
+        ];
+        for p in patterns {
+            let bytes = p.to_bytes();
+            let decoded = IOPattern::from_bytes(&bytes).unwrap();
+            assert_eq!(p, decoded);
+            assert_eq!(p.value(123), decoded.value(123));
+
+            let json = serde_json::to_string(&p).unwrap();
+            assert_eq!(p, serde_json::from_str(&json).unwrap());
+        }
+
+        let p = IOPattern(vec![SpongeOp::Absorb(0), SpongeOp::Squeeze(u32::MAX)]);
+        assert_eq!(p, IOPattern::from_bytes(&p.to_bytes()).unwrap());
+
+        // Small counts take a single byte per op.
+        assert_eq!(
+            vec![0x02, 0x03],
+            IOPattern(vec![SpongeOp::Absorb(1), SpongeOp::Squeeze(1)]).to_bytes()
+        );
+
+        // Truncated and overlong encodings are rejected.
+        assert!(matches!(
+            IOPattern::from_bytes(&[0x02, 0x80]),
+            Err(Error::InvalidEncoding { offset: 1 })
+        ));
+        assert!(matches!(
+            IOPattern::from_bytes(&[0xff, 0xff, 0xff, 0xff, 0x7f]),
+            Err(Error::InvalidEncoding { offset: 0 })
+        ));
+        assert!(matches!(
+            IOPattern::from_bytes(&[0x80; 6]),
+            Err(Error::InvalidEncoding { offset: 5 })
+        ));
+    }
+
+    #[test]
+    fn test_pattern_display() {
+        let p = IOPattern(vec![
+            SpongeOp::Absorb(3),
+            SpongeOp::Squeeze(1),
+            SpongeOp::Absorb(2),
+            SpongeOp::Squeeze(1),
+        ]);
+        assert_eq!("A3 S1 A2 S1", p.to_string());
+        assert_eq!(p, "  A3 S1\tA2\n S1 ".parse().unwrap());
+
+        for p in [
+            IOPattern(vec![]),
+            p,
+            IOPattern(vec![
+                SpongeOp::Absorb(1),
+                SpongeOp::Absorb(1),
+                SpongeOp::Squeeze(0),
+                SpongeOp::Squeeze(u32::MAX),
+            ]),
+        ] {
+            assert_eq!(p, p.to_string().parse().unwrap());
+        }
+
+        for (s, offset) in [
+            ("A3 X1", 3),
+            ("A3 S", 3),
+            ("A3  S+1", 4),
+            ("a3", 0),
+            ("A3S1", 0),
+            ("S4294967296", 0),
+        ] {
+            assert!(
+                matches!(s.parse::<IOPattern>(), Err(Error::InvalidSyntax { offset: o }) if o == offset),
+                "{s}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_streaming_hasher() {
+        let ops = [
+            SpongeOp::Absorb(1),
+            SpongeOp::Absorb(1),
+            SpongeOp::Squeeze(3),
+            SpongeOp::Absorb(7),
+            SpongeOp::Squeeze(1),
+            SpongeOp::Squeeze(2),
+        ];
+
+        for domain_separator in [0, 1, 123] {
+            let mut hasher = Hasher::new();
+            for op in ops {
+                hasher.update_op(op);
+            }
+
+            assert_eq!(
+                IOPattern(ops.to_vec()).value(domain_separator),
+                hasher.finalize(domain_separator)
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "arbitrary")]
+    fn test_arbitrary() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        let bytes: Vec<u8> = (0..=255).cycle().take(4096).collect();
+        let mut u = Unstructured::new(&bytes);
+        while !u.is_empty() {
+            let pattern = IOPattern::arbitrary(&mut u).unwrap();
+            assert!(IOPattern::try_new(pattern.0.clone()).is_ok());
+            pattern.value(0);
+        }
+    }
+
+    #[test]
+    fn test_domain_separator_conversions() {
+        assert_eq!(0, DomainSeparator::default().value());
+        assert!(DomainSeparator::default().is_default());
+        assert!(!DomainSeparator::from_u32(0).is_default());
+        assert_eq!(0, DomainSeparator::from_u32(0).value());
+        assert_eq!(DomainSeparator::from_u32(7), 7u32.into());
+        assert_eq!(DomainSeparator::from_u128(1 << 64), (1u128 << 64).into());
+        assert_eq!(
+            label_domain_separator("label"),
+            DomainSeparator::from_label("label").value()
+        );
+    }
+
+    #[test]
+    fn test_wide_domain_separator() {
+        let pattern = IOPattern(vec![SpongeOp::Absorb(2), SpongeOp::Squeeze(2)]);
+
+        for domain_separator in [0, 1, 123, u32::MAX] {
+            assert_eq!(
+                pattern.value(domain_separator),
+                pattern.value_u128(u128::from(domain_separator))
+            );
+        }
+
+        let mut tags = std::collections::HashSet::new();
+        for domain_separator in [1u128, 1 << 32, 1 << 64, 1 << 96, (1 << 32) + 1, u128::MAX] {
+            assert!(tags.insert(pattern.value_u128(domain_separator)));
+        }
+    }
+
+    #[test]
+    fn test_wide_domain_separator_collisions() {
+        // The limbs of a wide separator must not be read as the values of further ops.
+        let short = IOPattern(vec![SpongeOp::Absorb(2)]);
+        let long = IOPattern(vec![SpongeOp::Absorb(2), SpongeOp::Squeeze(5)]);
+        assert_ne!(long.value(7), short.value_u128(5 | 7 << 32));
+        let ratchet = IOPattern(vec![SpongeOp::Absorb(2), SpongeOp::Ratchet]);
+        assert_ne!(ratchet.value(7), short.value_u128(1 << 31 | 7 << 32));
+
+        // Nor as a different pattern with another wide separator, or with trailing zero limbs omitted.
+        let squeeze = SpongeOp::Squeeze(5).value();
+        assert_ne!(
+            long.value_u128(1 << 64),
+            short.value_u128(u128::from(squeeze) | 1 << 96)
+        );
+        let mut tags = std::collections::HashSet::new();
+        for pattern in [&short, &long, &ratchet] {
+            for domain_separator in [7, 1 << 32, 5 | 7 << 32, 7 << 64, 7 << 96] {
+                assert!(tags.insert(pattern.value_u128(domain_separator)));
+            }
+        }
+    }
 }
@@ -5,6 +5,7 @@ use crate::mds::SparseMatrix;
 use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
 use crate::sponge::{
     api::{Hasher, IOPattern, InnerSpongeAPI, SpongeOp},
+    padding::{Padding, PaddingState},
     vanilla::{Direction, Mode, SpongeTrait},
 };
 use crate::Strength;
@@ -12,10 +13,20 @@ use bellpepper::util_cs::witness_cs::{SizedWitness, WitnessCS};
 use bellpepper_core::boolean::Boolean;
 use bellpepper_core::num::{self, AllocatedNum};
 use bellpepper_core::{ConstraintSystem, LinearCombination, Namespace, SynthesisError};
-use ff::{Field, PrimeField};
+use ff::{Field, PrimeField, PrimeFieldBits};
 use std::collections::VecDeque;
 use std::marker::PhantomData;
 
+/// A sponge synthesizing its permutations into a constraint system.
+///
+/// Absorbing permutes as soon as the rate is full, so absorbed elements are never buffered. The only buffer is the
+/// queue of a duplex sponge, which keeps the squeezable elements that a permutation would otherwise overwrite until
+/// they are squeezed: it stays empty in simplex mode, and in duplex mode it holds at most the number of elements
+/// absorbed but not yet squeezed.
+///
+/// The sponge is driven by the constraint system `C` it synthesizes into, which is its accumulator. This is usually a
+/// [`Namespace`] of the caller's constraint system, but can as well be the constraint system itself, when composing
+/// with gadgets which manage their own namespaces.
 pub struct SpongeCircuit<'a, F, A, C>
 where
     F: PrimeField,
@@ -34,13 +45,118 @@ where
     pattern: IOPattern,
     io_count: usize,
     poseidon: Poseidon<'a, F, A>,
-    _c: PhantomData<C>,
+    padding: Option<&'a dyn Padding<F>>,
+    // The sponge only borrows `C` while synthesizing, so it must not keep a borrowed `C`, such as a `Namespace`, alive.
+    _c: PhantomData<fn() -> C>,
 }
 
-impl<'a, F: PrimeField, A: Arity<F>, CS: 'a + ConstraintSystem<F>> SpongeTrait<'a, F, A>
+impl<'a, F: PrimeField, A: Arity<F>, CS: ConstraintSystem<F>> SpongeCircuit<'a, F, A, CS> {
+    /// Returns the number of constraints synthesized by each permutation of the sponge state.
+    pub fn constraints_per_permutation(&self) -> usize {
+        PoseidonCircuit2::constraints_per_permutation(self.constants)
+    }
+
+    /// Returns the number of permutations performed so far.
+    pub fn permutation_count(&self) -> usize {
+        self.permutation_count
+    }
+
+    /// Returns the number of squeezable elements held in the duplex queue.
+    pub fn queue_len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Pad the final block with `padding`, as `Sponge::set_padding` does. The padding is preserved by `reset`.
+    pub fn set_padding(&mut self, padding: &'a dyn Padding<F>) {
+        self.padding = Some(padding);
+    }
+
+    /// Squeeze exactly `count` elements. Unlike `squeeze_elements`, which returns fewer elements than requested if
+    /// squeezing fails, this returns an error unless all `count` elements could be squeezed.
+    pub fn squeeze_exact(
+        &mut self,
+        count: usize,
+        ns: &mut CS,
+    ) -> Result<Vec<Elt<F>>, SynthesisError> {
+        let mut elements = Vec::with_capacity(count);
+        for _ in 0..count {
+            match self.squeeze(ns)? {
+                Some(squeezed) => elements.push(squeezed),
+                None => return Err(SynthesisError::Unsatisfiable),
+            }
+        }
+        Ok(elements)
+    }
+
+    /// Squeeze exactly `count` elements, each allocated so that its variable can be wired into other constraints.
+    ///
+    /// Squeezed elements are otherwise `Elt::Num`: the output of a permutation is a linear combination of variables
+    /// allocated by its last round, so `squeeze` spends no constraint on it, and `Elt::lc` can be used in constraints
+    /// directly. Allocating costs one constraint for each squeezed `Elt::Num`.
+    pub fn squeeze_allocated(
+        &mut self,
+        count: usize,
+        ns: &mut CS,
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        let squeezed = self.squeeze_exact(count, ns)?;
+        let first = self.squeezed() - count;
+        squeezed
+            .iter()
+            .enumerate()
+            .map(|(i, elt)| {
+                elt.ensure_allocated(
+                    &mut ns.namespace(|| format!("squeezed allocation {}", first + i)),
+                    true,
+                )
+            })
+            .collect()
+    }
+
+    /// Squeeze exactly `count_bits` bits, in little-endian order. Each squeezed element is constrained to its canonical
+    /// bit decomposition and contributes only its low `F::CAPACITY` bits, since its top bit is biased. As few elements
+    /// as possible are squeezed, and the bits of the last one beyond `count_bits` are discarded.
+    pub fn squeeze_bits(
+        &mut self,
+        count_bits: usize,
+        ns: &mut CS,
+    ) -> Result<Vec<Boolean>, SynthesisError>
+    where
+        F: PrimeFieldBits,
+    {
+        let bits_per_element = F::CAPACITY as usize;
+        let count = (count_bits + bits_per_element - 1) / bits_per_element;
+
+        let mut bits = Vec::with_capacity(count * bits_per_element);
+        for element in self.squeeze_exact(count, ns)? {
+            let mut cs = ns.namespace(|| format!("squeezed bits {}", bits.len()));
+            let allocated = element.ensure_allocated(&mut cs, true)?;
+            let element_bits = allocated.to_bits_le_strict(&mut cs)?;
+            bits.extend(element_bits.into_iter().take(bits_per_element));
+        }
+        bits.truncate(count_bits);
+
+        Ok(bits)
+    }
+
+    /// Absorb the constant `val` without allocating it. Like a constant term of a linear combination, it is folded
+    /// into the state element it is added to, so no variable or constraint is spent on it until the state is
+    /// permuted, which costs the same whatever was absorbed.
+    pub fn absorb_const(&mut self, val: F, ns: &mut CS) -> Result<(), SynthesisError> {
+        self.absorb(&Elt::num_from_fr::<CS>(val), ns)
+    }
+
+    /// Absorb `num` without allocating it. The linear combination is folded into the state element it is added to,
+    /// so no variable or equality constraint is spent on it. A bare `LinearCombination` must first be tracked as a
+    /// [`num::Num`] by the gadget producing it, since the witness value cannot be recovered from it.
+    pub fn absorb_num(&mut self, num: num::Num<F>, ns: &mut CS) -> Result<(), SynthesisError> {
+        self.absorb(&Elt::Num(num), ns)
+    }
+}
+
+impl<'a, F: PrimeField, A: Arity<F>, CS: ConstraintSystem<F>> SpongeTrait<'a, F, A>
     for SpongeCircuit<'a, F, A, CS>
 {
-    type Acc = Namespace<'a, F, CS>;
+    type Acc = CS;
     type Elt = Elt<F>;
     type Error = SynthesisError;
 
@@ -58,10 +174,25 @@ impl<'a, F: PrimeField, A: Arity<F>, CS: 'a + ConstraintSystem<F>> SpongeTrait<'
             pattern: IOPattern(Vec::new()),
             poseidon: Poseidon::new(constants),
             io_count: 0,
+            padding: None,
             _c: Default::default(),
         }
     }
 
+    fn reset(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.direction = Direction::Absorbing;
+        self.absorbed = 0;
+        self.squeezed = 0;
+        self.squeeze_pos = 0;
+        self.permutation_count = 0;
+        self.state.reset::<CS>();
+        self.queue.clear();
+        self.pattern.0.clear();
+        self.io_count = 0;
+        self.poseidon.reset();
+    }
+
     fn mode(&self) -> Mode {
         self.mode
     }
@@ -127,7 +258,14 @@ impl<'a, F: PrimeField, A: Arity<F>, CS: 'a + ConstraintSystem<F>> SpongeTrait<'
     }
 
     fn pad(&mut self) {
-        self.state.apply_padding::<CS>();
+        match self.padding {
+            Some(padding) => {
+                let pos = SpongeTrait::absorb_pos(self);
+                padding.apply(&mut &mut self.state.elements[1..], pos);
+                padding.apply(&mut &mut self.poseidon.elements[1..], pos);
+            }
+            None => self.state.apply_padding::<CS>(),
+        }
     }
 
     fn permute_state(&mut self, ns: &mut Self::Acc) -> Result<(), Self::Error> {
@@ -167,6 +305,9 @@ impl<'a, F: PrimeField, A: Arity<F>, CS: 'a + ConstraintSystem<F>> SpongeTrait<'
     }
 
     fn absorb_aux(&mut self, elt: &Self::Elt) -> Self::Elt {
+        if self.is_overwrite() {
+            return elt.clone();
+        }
         // Elt::add always returns `Ok`, so `unwrap` is safe.
         self.element(SpongeTrait::absorb_pos(self) + SpongeTrait::capacity(self))
             .add_ref(elt)
@@ -184,12 +325,15 @@ impl<'a, F: PrimeField, A: Arity<F>, CS: 'a + ConstraintSystem<F>> SpongeTrait<'
     }
 }
 
-impl<'a, F: PrimeField, A: Arity<F>, CS: 'a + ConstraintSystem<F>> InnerSpongeAPI<F, A>
+impl<'a, F: PrimeField, A: Arity<F>, CS: ConstraintSystem<F>> InnerSpongeAPI<F, A>
     for SpongeCircuit<'a, F, A, CS>
 {
-    type Acc = Namespace<'a, F, CS>;
+    type Acc = CS;
     type Value = Elt<F>;
 
+    /// The tag becomes a constant of the circuit: the capacity element is the linear combination `tag * ONE`, so the
+    /// tag appears as a coefficient in the constraints of the first permutation. It is thereby enforced without a
+    /// variable or constraint of its own, and no witness can substitute a different tag.
     fn initialize_capacity(&mut self, tag: u128, _acc: &mut Self::Acc) {
         let mut repr = F::Repr::default();
         repr.as_mut()[..16].copy_from_slice(&tag.to_le_bytes());
@@ -242,11 +386,66 @@ impl<'a, F: PrimeField, A: Arity<F>, CS: 'a + ConstraintSystem<F>> InnerSpongeAP
         self.pattern = pattern
     }
 
+    fn io_count(&self) -> usize {
+        self.io_count
+    }
+
     fn increment_io_count(&mut self) -> usize {
         let old_count = self.io_count;
         self.io_count += 1;
         old_count
     }
+
+    fn absorb_overwrites(&self) -> bool {
+        self.is_overwrite()
+    }
+}
+
+/// Padding constants are `Elt::Num`s, so padding spends no constraints.
+impl<F: PrimeField> PaddingState<F> for &mut [Elt<F>] {
+    fn len(&self) -> usize {
+        <[Elt<F>]>::len(self)
+    }
+
+    // Constants only refer to the `one` input, which is the same in every constraint system.
+    fn set(&mut self, index: usize, value: F) {
+        self[index] = Elt::num_from_fr::<WitnessCS<F>>(value);
+    }
+
+    fn add(&mut self, index: usize, value: F) {
+        // Elt::add always returns `Ok`, so `unwrap` is safe.
+        self[index] = self[index]
+            .clone()
+            .add(Elt::num_from_fr::<WitnessCS<F>>(value))
+            .unwrap();
+    }
+}
+
+/// The cost of synthesizing a sponge circuit, as estimated by [`estimate_cost`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CostEstimate {
+    pub permutations: usize,
+    pub constraints: usize,
+}
+
+/// Estimate the cost of synthesizing a sponge circuit of arity `A` and the given `strength` which follows `pattern`
+/// via the [`SpongeAPI`](crate::sponge::api::SpongeAPI), without generating constants or synthesizing anything. The
+/// default S-box exponent of `F` is assumed.
+pub fn estimate_cost<F: PrimeField, A: Arity<F>>(
+    pattern: &IOPattern,
+    strength: Strength,
+) -> CostEstimate {
+    let rate = A::to_usize();
+    let alpha = crate::default_alpha::<F>();
+    let (full_rounds, partial_rounds) = crate::round_numbers_with_alpha(rate, &strength, alpha);
+
+    let permutations = pattern.permutation_count(rate);
+
+    CostEstimate {
+        permutations,
+        constraints: permutations
+            * circuit2::permutation_constraints(rate + 1, full_rounds, partial_rounds, alpha),
+    }
 }
 
 #[cfg(test)]
@@ -268,18 +467,28 @@ mod tests {
 
         // Exercise duplex sponges with eventual size less, equal to, and greater to rate.
         for size in 2..10 {
-            test_simplex_aux::<Fr, typenum::U4, _>(&mut rng, size);
+            test_simplex_aux::<Fr, typenum::U4, _>(&mut rng, size, Mode::Simplex);
         }
     }
 
-    fn test_simplex_aux<F: PrimeField, A: Arity<F>, R: Rng>(rng: &mut R, n: usize) {
+    #[test]
+    fn test_overwrite_circuit() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+
+        // Exercise overwrite sponges with eventual size less, equal to, and greater to rate.
+        for size in 2..10 {
+            test_simplex_aux::<Fr, typenum::U4, _>(&mut rng, size, Mode::Overwrite);
+        }
+    }
+
+    fn test_simplex_aux<F: PrimeField, A: Arity<F>, R: Rng>(rng: &mut R, n: usize, mode: Mode) {
         let c = Sponge::<F, A>::simplex_constants(n);
 
-        let mut circuit = SpongeCircuit::new_with_constants(&c, Mode::Simplex);
+        let mut circuit = SpongeCircuit::new_with_constants(&c, mode);
         let mut cs = TestConstraintSystem::<F>::new();
         let mut ns = cs.namespace(|| "ns");
 
-        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        let mut sponge = Sponge::new_with_constants(&c, mode);
         let acc = &mut ();
 
         let mut elements = Vec::with_capacity(n);
@@ -307,12 +516,13 @@ mod tests {
         assert!(root_cs.is_satisfied());
         assert_eq!(result.len(), allocated_result.len());
 
-        result
+        assert!(result
             .iter()
             .zip(&allocated_result)
-            .all(|(a, b)| *a == b.val().unwrap());
+            .all(|(a, b)| *a == b.val().unwrap()));
 
-        let permutation_constraints = 288; // For U4.
+        let permutation_constraints = circuit.constraints_per_permutation();
+        assert_eq!(288, permutation_constraints); // For U4.
         let permutations_per_direction = (n - 1) / A::to_usize();
         let final_absorption_permutation = 1;
         let expected_permutations = 2 * permutations_per_direction + final_absorption_permutation;
@@ -335,6 +545,389 @@ mod tests {
         assert_eq!(n, allocated_result.len());
     }
 
+    #[test]
+    fn test_squeeze_exact() {
+        let c = Sponge::<Fr, typenum::U2>::duplex_constants();
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let mut ns = cs.namespace(|| "ns");
+
+        let mut circuit = SpongeCircuit::new_with_constants(&c, Mode::Duplex);
+        let mut short_circuit = SpongeCircuit::new_with_constants(&c, Mode::Duplex);
+        let elt = Elt::Allocated(AllocatedNum::alloc_infallible(&mut ns, || Fr::from(1)));
+        circuit.absorb(&elt, &mut ns).unwrap();
+        short_circuit.absorb(&elt, &mut ns).unwrap();
+
+        // A duplex sponge can only squeeze as many elements as were absorbed.
+        assert_eq!(1, short_circuit.squeeze_elements(2, &mut ns).len());
+        assert!(circuit.squeeze_exact(2, &mut ns).is_err());
+
+        circuit.absorb(&elt, &mut ns).unwrap();
+        assert_eq!(1, circuit.squeeze_exact(1, &mut ns).unwrap().len());
+    }
+
+    #[test]
+    fn test_squeeze_allocated() {
+        let c = Sponge::<Fr, typenum::U2>::simplex_constants(2);
+        let inputs = [Fr::from(1), Fr::from(2)];
+
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        sponge.absorb_elements(&inputs, &mut ()).unwrap();
+        let expected = sponge.squeeze_elements(3, &mut ());
+
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let mut ns = cs.namespace(|| "ns");
+        let elts: Vec<_> = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                Elt::Allocated(AllocatedNum::alloc_infallible(
+                    ns.namespace(|| format!("elt{i}")),
+                    || *x,
+                ))
+            })
+            .collect();
+        let mut circuit = SpongeCircuit::new_with_constants(&c, Mode::Simplex);
+        circuit.absorb_elements(&elts, &mut ns).unwrap();
+        let squeezed = circuit.squeeze_allocated(3, &mut ns).unwrap();
+
+        // Each squeezed variable can be constrained directly.
+        let twice = squeezed[0]
+            .add(ns.namespace(|| "twice"), &squeezed[0])
+            .unwrap();
+        ns.enforce(
+            || "twice the first output",
+            |lc| lc + squeezed[0].get_variable() + squeezed[0].get_variable(),
+            |lc| lc + TestConstraintSystem::<Fr>::one(),
+            |lc| lc + twice.get_variable(),
+        );
+
+        assert_eq!(
+            expected,
+            squeezed
+                .iter()
+                .map(|x| x.get_value().unwrap())
+                .collect::<Vec<_>>()
+        );
+        drop(ns);
+        assert!(cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_long_absorb_memory() {
+        let n = 101;
+        let c = Sponge::<Fr, typenum::U2>::simplex_constants(n);
+        let inputs: Vec<Fr> = (0..n as u64).map(Fr::from).collect();
+
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        sponge.absorb_elements(&inputs, &mut ()).unwrap();
+        let expected = sponge.squeeze_elements(1, &mut ());
+
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let mut ns = cs.namespace(|| "ns");
+        let mut circuit = SpongeCircuit::new_with_constants(&c, Mode::Simplex);
+        for (i, x) in inputs.iter().enumerate() {
+            let elt = Elt::Allocated(AllocatedNum::alloc_infallible(
+                ns.namespace(|| format!("elt{i}")),
+                || *x,
+            ));
+            circuit.absorb(&elt, &mut ns).unwrap();
+
+            // Each full rate is permuted immediately, and nothing is queued.
+            assert_eq!((i + 1) / 2, circuit.permutation_count());
+            assert_eq!(0, circuit.queue_len());
+        }
+        let squeezed = circuit.squeeze_exact(1, &mut ns).unwrap();
+
+        assert_eq!(expected[0], squeezed[0].val().unwrap());
+        drop(ns);
+        assert!(cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_direct_sponge_circuit() {
+        let c = Sponge::<Fr, typenum::U2>::simplex_constants(3);
+        let inputs = [Fr::from(1), Fr::from(2), Fr::from(3)];
+
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let mut ns = cs.namespace(|| "ns");
+        let mut circuit = SpongeCircuit::new_with_constants(&c, Mode::Simplex);
+        let elts: Vec<_> = inputs
+            .iter()
+            .map(|x| Elt::num_from_fr::<TestConstraintSystem<Fr>>(*x))
+            .collect();
+        circuit.absorb_elements(&elts, &mut ns).unwrap();
+        let expected = circuit.squeeze_exact(1, &mut ns).unwrap();
+
+        let mut direct_cs = TestConstraintSystem::<Fr>::new();
+        let mut direct = SpongeCircuit::new_with_constants(&c, Mode::Simplex);
+        direct.absorb_elements(&elts, &mut direct_cs).unwrap();
+        let result = direct.squeeze_exact(1, &mut direct_cs).unwrap();
+
+        assert_eq!(expected[0].val(), result[0].val());
+        assert_eq!(circuit.permutation_count(), direct.permutation_count());
+        assert!(direct_cs.is_satisfied());
+        assert_eq!(
+            direct.constraints_per_permutation() * direct.permutation_count(),
+            direct_cs.num_constraints()
+        );
+    }
+
+    #[test]
+    fn test_wide_arity_circuit_consistency() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+
+        test_wide_arity_aux::<typenum::U11, _>(&mut rng);
+        test_wide_arity_aux::<typenum::U16, _>(&mut rng);
+        test_wide_arity_aux::<typenum::U36, _>(&mut rng);
+    }
+
+    fn test_wide_arity_aux<A: Arity<Fr>, R: Rng>(rng: &mut R) {
+        // Absorb and squeeze more than one rate's worth, so every direction permutes at least once.
+        let n = A::to_usize() + 3;
+        let c = Sponge::<Fr, A>::duplex_constants();
+        let inputs: Vec<Fr> = (0..n).map(|_| Fr::random(&mut *rng)).collect();
+
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Duplex);
+        sponge.absorb_elements(&inputs, &mut ()).unwrap();
+        let expected = sponge.squeeze_elements(n, &mut ());
+
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let mut ns = cs.namespace(|| "ns");
+        let elts: Vec<_> = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                Elt::Allocated(
+                    AllocatedNum::alloc(ns.namespace(|| format!("elt{i}")), || Ok(*x)).unwrap(),
+                )
+            })
+            .collect();
+        let mut circuit = SpongeCircuit::new_with_constants(&c, Mode::Duplex);
+        circuit.absorb_elements(&elts, &mut ns).unwrap();
+        let result = circuit.squeeze_elements(n, &mut ns);
+
+        assert!(ns.get_root().is_satisfied());
+        assert_eq!(
+            expected,
+            result
+                .iter()
+                .map(|elt| elt.val().unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_absorb_num() {
+        let c = Sponge::<Fr, typenum::U2>::simplex_constants(2);
+        let (a, b) = (Fr::from(3), Fr::from(4));
+
+        // Absorb `a + b` as a linear combination.
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let mut ns = cs.namespace(|| "ns");
+        let x = AllocatedNum::alloc(ns.namespace(|| "a"), || Ok(a)).unwrap();
+        let y = AllocatedNum::alloc(ns.namespace(|| "b"), || Ok(b)).unwrap();
+        let mut circuit = SpongeCircuit::new_with_constants(&c, Mode::Simplex);
+        circuit
+            .absorb_num(num::Num::from(x.clone()).add(&y.clone().into()), &mut ns)
+            .unwrap();
+        circuit.absorb_num(num::Num::from(y), &mut ns).unwrap();
+        let result = circuit.squeeze_exact(1, &mut ns).unwrap();
+        let root_cs = ns.get_root();
+        assert!(root_cs.is_satisfied());
+        let num_constraints = root_cs.num_constraints();
+
+        // Allocate `a + b` and constrain it to equal the sum instead.
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let mut ns = cs.namespace(|| "ns");
+        let x = AllocatedNum::alloc(ns.namespace(|| "a"), || Ok(a)).unwrap();
+        let y = AllocatedNum::alloc(ns.namespace(|| "b"), || Ok(b)).unwrap();
+        let sum = AllocatedNum::alloc(ns.namespace(|| "sum"), || Ok(a + b)).unwrap();
+        ns.enforce(
+            || "sum equals a + b",
+            |lc| lc + x.get_variable() + y.get_variable(),
+            |lc| lc + TestConstraintSystem::<Fr>::one(),
+            |lc| lc + sum.get_variable(),
+        );
+        let mut circuit = SpongeCircuit::new_with_constants(&c, Mode::Simplex);
+        circuit
+            .absorb_elements(&[Elt::Allocated(sum), Elt::Allocated(y)], &mut ns)
+            .unwrap();
+        let expected = circuit.squeeze_exact(1, &mut ns).unwrap();
+        let root_cs = ns.get_root();
+        assert!(root_cs.is_satisfied());
+
+        assert_eq!(expected[0].val(), result[0].val());
+        assert_eq!(num_constraints + 1, root_cs.num_constraints());
+    }
+
+    #[test]
+    fn test_elements_until_permute() {
+        let c = Sponge::<Fr, typenum::U4>::duplex_constants();
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let mut ns = cs.namespace(|| "ns");
+        let mut circuit = SpongeCircuit::new_with_constants(&c, Mode::Duplex);
+
+        for i in 0..10 {
+            let remaining = circuit.elements_until_permute();
+            assert_eq!(4 - i % 4, remaining);
+            let permutations = circuit.permutation_count();
+            let x =
+                AllocatedNum::alloc(ns.namespace(|| format!("x{i}")), || Ok(Fr::from(i as u64)))
+                    .unwrap();
+            circuit.absorb(&Elt::Allocated(x), &mut ns).unwrap();
+            assert_eq!(
+                usize::from(remaining == 1),
+                circuit.permutation_count() - permutations
+            );
+        }
+    }
+
+    #[test]
+    fn test_absorb_const() {
+        let c = Sponge::<Fr, typenum::U11>::simplex_constants(11);
+        let constants: Vec<Fr> = (1..=10).map(Fr::from).collect();
+        let x = Fr::from(1234);
+
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let mut ns = cs.namespace(|| "ns");
+        let allocated = AllocatedNum::alloc(ns.namespace(|| "x"), || Ok(x)).unwrap();
+        let mut circuit = SpongeCircuit::new_with_constants(&c, Mode::Simplex);
+        for constant in &constants {
+            circuit.absorb_const(*constant, &mut ns).unwrap();
+        }
+        // Ten constants cost nothing before the state is permuted.
+        assert_eq!(0, ns.get_root().num_constraints());
+
+        circuit.absorb(&Elt::Allocated(allocated), &mut ns).unwrap();
+        let result = circuit.squeeze_exact(1, &mut ns).unwrap();
+        let root_cs = ns.get_root();
+        assert!(root_cs.is_satisfied());
+        assert_eq!(
+            circuit.constraints_per_permutation(),
+            root_cs.num_constraints()
+        );
+
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        sponge.absorb_elements(&constants, &mut ()).unwrap();
+        sponge.absorb(&x, &mut ()).unwrap();
+        assert_eq!(
+            sponge.squeeze(&mut ()).unwrap(),
+            Some(result[0].val().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_squeeze_bits() {
+        let c = Sponge::<Fr, typenum::U2>::simplex_constants(3);
+        let inputs = [Fr::from(1), Fr::from(2)];
+        let capacity = Fr::CAPACITY as usize;
+        let count_bits = 2 * capacity + 10;
+
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        sponge.absorb_elements(&inputs, &mut ()).unwrap();
+        let expected = sponge.squeeze_elements(3, &mut ());
+
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let mut ns = cs.namespace(|| "ns");
+        let elts: Vec<_> = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                Elt::Allocated(
+                    AllocatedNum::alloc(ns.namespace(|| format!("elt{i}")), || Ok(*x)).unwrap(),
+                )
+            })
+            .collect();
+        let mut circuit = SpongeCircuit::new_with_constants(&c, Mode::Simplex);
+        circuit.absorb_elements(&elts, &mut ns).unwrap();
+        let bits = circuit.squeeze_bits(count_bits, &mut ns).unwrap();
+
+        assert!(ns.get_root().is_satisfied());
+        assert_eq!(count_bits, bits.len());
+        assert_eq!(3, circuit.squeezed);
+
+        for (element, chunk) in expected.iter().zip(bits.chunks(capacity)) {
+            let repr = element.to_repr();
+            let repr_bit = |i: usize| (repr.as_ref()[i / 8] >> (i % 8)) & 1 == 1;
+
+            // The low bits, plus the dropped top bit, reconstruct the squeezed element.
+            let mut reconstructed = Fr::ZERO;
+            let mut coeff = Fr::ONE;
+            for (i, bit) in chunk.iter().enumerate() {
+                assert_eq!(Some(repr_bit(i)), bit.get_value());
+                if bit.get_value().unwrap() {
+                    reconstructed += coeff;
+                }
+                coeff = coeff.double();
+            }
+            if chunk.len() == capacity {
+                if repr_bit(capacity) {
+                    reconstructed += coeff;
+                }
+                assert_eq!(*element, reconstructed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_type_circuit() {
+        let inputs = [Fr::from(1), Fr::from(2), Fr::from(3)];
+
+        for hash_type in [HashType::Encryption, HashType::ConstantLength(3)] {
+            let c = PoseidonConstants::<Fr, typenum::U2>::new_with_strength_and_type(
+                Strength::Standard,
+                hash_type,
+            );
+
+            let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+            sponge.absorb_elements(&inputs, &mut ()).unwrap();
+            let expected = sponge.squeeze(&mut ()).unwrap().unwrap();
+
+            let mut cs = TestConstraintSystem::<Fr>::new();
+            let elts: Vec<_> = inputs
+                .iter()
+                .enumerate()
+                .map(|(i, x)| {
+                    Elt::Allocated(
+                        AllocatedNum::alloc(cs.namespace(|| format!("elt{i}")), || Ok(*x)).unwrap(),
+                    )
+                })
+                .collect();
+            let mut circuit = SpongeCircuit::new_with_constants(&c, Mode::Simplex);
+            circuit.absorb_elements(&elts, &mut cs).unwrap();
+            let result = circuit.squeeze_exact(1, &mut cs).unwrap();
+
+            assert!(cs.is_satisfied());
+            assert_eq!(Some(expected), result[0].val());
+        }
+    }
+
+    #[test]
+    fn test_custom_strength_circuit() {
+        let strength = Strength::custom(6, 40).unwrap();
+        let c = Sponge::<Fr, typenum::U2>::api_constants(strength);
+        let inputs = [Fr::from(1), Fr::from(2)];
+        let acc = &mut ();
+
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        sponge.absorb_elements(&inputs, acc).unwrap();
+        let expected = sponge.squeeze(acc).unwrap().unwrap();
+
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let mut circuit = SpongeCircuit::new_with_constants(&c, Mode::Simplex);
+        let elts: Vec<_> = inputs
+            .iter()
+            .map(|x| Elt::num_from_fr::<TestConstraintSystem<Fr>>(*x))
+            .collect();
+        circuit.absorb_elements(&elts, &mut cs).unwrap();
+        let result = circuit.squeeze_exact(1, &mut cs).unwrap();
+
+        assert_eq!(Some(expected), result[0].val());
+        assert_eq!(3 * 3 * 6 + 3 * 40, circuit.constraints_per_permutation());
+        assert!(cs.is_satisfied());
+        assert_eq!(circuit.constraints_per_permutation(), cs.num_constraints());
+    }
+
     #[test]
     fn test_sponge_duplex_circuit_consistency() {
         let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
@@ -355,6 +948,13 @@ mod tests {
             let size = rng.gen_range(15..25);
             test_duplex_consistency_aux::<Fr, typenum::U2, _>(&mut rng, size, 10);
         }
+
+        // The circuit is generic over the field, e.g. the 254-bit BN254 scalar field.
+        for size in 4..10 {
+            test_duplex_consistency_aux::<crate::test_bn254::Fr, typenum::U4, _>(
+                &mut rng, size, 10,
+            );
+        }
     }
 
     fn test_duplex_consistency_aux<F: PrimeField, A: Arity<F>, R: Rng>(
@@ -442,7 +1042,7 @@ mod tests {
 
     #[test]
     fn test_sponge_api_circuit_simple() {
-        use crate::sponge::api::SpongeAPI;
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
 
         let parameter = IOPattern(vec![
             SpongeOp::Absorb(1),
@@ -464,8 +1064,8 @@ mod tests {
             let mut wns = wcs.namespace(|| "ns");
             let wacc = &mut wns;
 
-            sponge.start(parameter.clone(), None, acc);
-            wsponge.start(parameter.clone(), None, wacc);
+            sponge.start(parameter.clone(), DomainSeparator::default(), acc);
+            wsponge.start(parameter.clone(), DomainSeparator::default(), wacc);
             sponge.state.debug();
             wsponge.state.debug();
             SpongeAPI::absorb(
@@ -540,7 +1140,7 @@ mod tests {
             let mut sponge = Sponge::new_with_constants(&p, Mode::Simplex);
             let acc = &mut ();
 
-            sponge.start(parameter, None, acc);
+            sponge.start(parameter, DomainSeparator::default(), acc);
             SpongeAPI::absorb(&mut sponge, 1, &[Fr::from(123)], acc);
             SpongeAPI::absorb(
                 &mut sponge,
@@ -568,10 +1168,230 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_padding_circuit() {
+        use crate::sponge::padding::{Pad10Star, ZeroPad};
+
+        let c = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let elements: Vec<Fr> = (1..=6).map(Fr::from).collect();
+
+        for padding in [&Pad10Star as &dyn Padding<Fr>, &ZeroPad] {
+            let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+            sponge.set_padding(padding);
+            sponge.absorb_elements(&elements, &mut ()).unwrap();
+            let expected = sponge.squeeze_elements(elements.len(), &mut ());
+
+            let mut cs = TestConstraintSystem::<Fr>::new();
+            let mut ns = cs.namespace(|| "ns");
+            let allocated: Vec<_> = elements
+                .iter()
+                .enumerate()
+                .map(|(i, element)| {
+                    Elt::Allocated(
+                        AllocatedNum::alloc(&mut ns.namespace(|| format!("elt{i}")), || {
+                            Ok(*element)
+                        })
+                        .unwrap(),
+                    )
+                })
+                .collect();
+
+            let mut circuit = SpongeCircuit::new_with_constants(&c, Mode::Simplex);
+            circuit.set_padding(padding);
+            circuit.absorb_elements(&allocated, &mut ns).unwrap();
+            let result = circuit.squeeze_elements(elements.len(), &mut ns);
+
+            assert!(ns.get_root().is_satisfied());
+            assert_eq!(
+                expected,
+                result
+                    .iter()
+                    .map(|elt| elt.val().unwrap())
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_tag_is_constrained() {
+        use crate::sponge::api::SpongeAPI;
+
+        let p = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let pattern = IOPattern(vec![SpongeOp::Absorb(2), SpongeOp::Squeeze(1)]);
+        let synthesize = |domain_separator: u32| {
+            let mut cs = TestConstraintSystem::<Fr>::new();
+            let mut ns = cs.namespace(|| "ns");
+            let inputs: Vec<_> = (0..2)
+                .map(|i| {
+                    Elt::Allocated(
+                        AllocatedNum::alloc(&mut ns.namespace(|| format!("input {i}")), || {
+                            Ok(Fr::from(i))
+                        })
+                        .unwrap(),
+                    )
+                })
+                .collect();
+
+            let mut sponge = SpongeCircuit::new_with_constants(&p, Mode::Simplex);
+            sponge.start(pattern.clone(), domain_separator, &mut ns);
+            SpongeAPI::absorb(&mut sponge, 2, &inputs, &mut ns);
+            let output = SpongeAPI::squeeze(&mut sponge, 1, &mut ns);
+            sponge.finish(&mut ns).unwrap();
+            drop(ns);
+
+            assert!(cs.is_satisfied());
+            (cs, output[0].val().unwrap())
+        };
+
+        let (mut cs, output) = synthesize(0);
+        let (other_cs, other_output) = synthesize(1);
+        assert_ne!(output, other_output);
+
+        // The tag is part of the constraints rather than of the witness.
+        assert_ne!(cs.hash(), other_cs.hash());
+
+        // A witness computed with another tag does not satisfy the circuit, although it only differs in the tag.
+        let aux_paths = other_cs
+            .pretty_print_list()
+            .into_iter()
+            .filter_map(|line| line.strip_prefix("AUX ").map(str::to_string));
+        for (path, value) in aux_paths.zip(other_cs.scalar_aux()) {
+            cs.set(&path, value);
+        }
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_sponge_api_overwrite_circuit() {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let p = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let pattern = IOPattern(vec![SpongeOp::Absorb(6), SpongeOp::Squeeze(1)]);
+        let elements: Vec<Fr> = (1..7).map(Fr::from).collect();
+
+        let mut sponge = Sponge::new_with_constants(&p, Mode::Overwrite);
+        sponge.start(pattern.clone(), DomainSeparator::default(), &mut ());
+        SpongeAPI::absorb(&mut sponge, 6, &elements, &mut ());
+        let expected = SpongeAPI::squeeze(&mut sponge, 1, &mut ());
+
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let elts: Vec<_> = elements
+            .iter()
+            .map(|x| Elt::num_from_fr::<TestConstraintSystem<Fr>>(*x))
+            .collect();
+        let mut circuit = SpongeCircuit::new_with_constants(&p, Mode::Overwrite);
+        circuit.start(pattern, DomainSeparator::default(), &mut cs);
+        SpongeAPI::absorb(&mut circuit, 6, &elts, &mut cs);
+        let output = SpongeAPI::squeeze(&mut circuit, 1, &mut cs);
+
+        assert!(cs.is_satisfied());
+        assert_eq!(Some(expected[0]), output[0].val());
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let patterns = [
+            vec![SpongeOp::Absorb(1), SpongeOp::Squeeze(1)],
+            vec![SpongeOp::Absorb(5), SpongeOp::Squeeze(5)],
+            vec![SpongeOp::Absorb(11), SpongeOp::Squeeze(3)],
+            vec![
+                SpongeOp::Absorb(1),
+                SpongeOp::Absorb(5),
+                SpongeOp::Squeeze(3),
+                SpongeOp::Absorb(7),
+                SpongeOp::Squeeze(12),
+            ],
+            vec![
+                SpongeOp::Absorb(3),
+                SpongeOp::Ratchet,
+                SpongeOp::Ratchet,
+                SpongeOp::Squeeze(2),
+                SpongeOp::Ratchet,
+            ],
+        ];
+
+        for strength in [Strength::Standard, Strength::Strengthened] {
+            let p = Sponge::<Fr, typenum::U5>::api_constants(strength);
+
+            for ops in &patterns {
+                let pattern = IOPattern(ops.clone());
+                let estimate = estimate_cost::<Fr, typenum::U5>(&pattern, strength);
+
+                let mut cs = TestConstraintSystem::<Fr>::new();
+                let mut sponge = SpongeCircuit::new_with_constants(&p, Mode::Simplex);
+                sponge.start(pattern, DomainSeparator::default(), &mut cs);
+                for op in ops {
+                    match op {
+                        SpongeOp::Absorb(n) => {
+                            let elts: Vec<_> = (0..*n)
+                                .map(|i| {
+                                    Elt::num_from_fr::<TestConstraintSystem<Fr>>(Fr::from(i as u64))
+                                })
+                                .collect();
+                            SpongeAPI::absorb(&mut sponge, *n, &elts, &mut cs);
+                        }
+                        SpongeOp::Squeeze(n) => {
+                            SpongeAPI::squeeze(&mut sponge, *n, &mut cs);
+                        }
+                        SpongeOp::Ratchet => SpongeAPI::ratchet(&mut sponge, &mut cs).unwrap(),
+                    }
+                }
+                sponge.finish(&mut cs).unwrap();
+
+                assert_eq!(sponge.permutation_count(), estimate.permutations);
+                assert_eq!(cs.num_constraints(), estimate.constraints);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ratchet_circuit() {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let pattern: IOPattern = "A3 S1 R A2 S2".parse().unwrap();
+        let p = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let elements: Vec<Fr> = (1..=5).map(Fr::from).collect();
+        let acc = &mut ();
+
+        let mut sponge = Sponge::new_with_constants(&p, Mode::Simplex);
+        sponge.start(pattern.clone(), DomainSeparator::default(), acc);
+        SpongeAPI::absorb(&mut sponge, 3, &elements[..3], acc);
+        let mut expected = SpongeAPI::squeeze(&mut sponge, 1, acc);
+        sponge.ratchet(acc).unwrap();
+        SpongeAPI::absorb(&mut sponge, 2, &elements[3..], acc);
+        expected.extend(SpongeAPI::squeeze(&mut sponge, 2, acc));
+        sponge.finish(acc).unwrap();
+
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        let mut circuit = SpongeCircuit::new_with_constants(&p, Mode::Simplex);
+        let elts: Vec<_> = elements
+            .iter()
+            .map(|x| Elt::num_from_fr::<TestConstraintSystem<Fr>>(*x))
+            .collect();
+        let output = {
+            let mut ns = cs.namespace(|| "ns");
+            let acc = &mut ns;
+            circuit.start(pattern, DomainSeparator::default(), acc);
+            SpongeAPI::absorb(&mut circuit, 3, &elts[..3], acc);
+            let mut output = SpongeAPI::squeeze(&mut circuit, 1, acc);
+            circuit.ratchet(acc).unwrap();
+            SpongeAPI::absorb(&mut circuit, 2, &elts[3..], acc);
+            output.extend(SpongeAPI::squeeze(&mut circuit, 2, acc));
+            circuit.finish(acc).unwrap();
+            output
+        };
+
+        let output: Vec<Fr> = output.iter().map(|elt| elt.val().unwrap()).collect();
+        assert_eq!(expected, output);
+        assert!(cs.is_satisfied());
+    }
+
     #[test]
     #[should_panic]
     fn test_sponge_api_circuit_failure() {
-        use crate::sponge::api::SpongeAPI;
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
 
         let parameter = IOPattern(vec![
             SpongeOp::Absorb(1),
@@ -586,7 +1406,7 @@ mod tests {
             let mut ns = cs.namespace(|| "ns");
             let acc = &mut ns;
 
-            sponge.start(parameter, None, acc);
+            sponge.start(parameter, DomainSeparator::default(), acc);
             SpongeAPI::absorb(
                 &mut sponge,
                 1,
@@ -614,6 +1434,7 @@ mod tests {
         for i in 1..3 {
             for j in 1..3 {
                 test_sponge_api_circuit_aux::<Fr, typenum::U2>(i, j);
+                test_sponge_api_circuit_aux::<crate::test_bn254::Fr, typenum::U2>(i, j);
             }
         }
     }
@@ -622,7 +1443,7 @@ mod tests {
         absorb_count: usize,
         squeeze_count: usize,
     ) {
-        use crate::sponge::api::SpongeAPI;
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
 
         let arity = A::to_usize();
         let expected_absorb_permutations = 1 + (absorb_count - 1) / arity;
@@ -646,7 +1467,7 @@ mod tests {
                     .take(absorb_count)
                     .collect();
 
-            sponge.start(parameter.clone(), None, acc);
+            sponge.start(parameter.clone(), DomainSeparator::default(), acc);
 
             SpongeAPI::absorb(&mut sponge, absorb_count as u32, &elts[..], acc);
 
@@ -668,7 +1489,7 @@ mod tests {
                 .take(absorb_count)
                 .collect();
 
-            sponge.start(parameter, None, acc);
+            sponge.start(parameter, DomainSeparator::default(), acc);
             SpongeAPI::absorb(&mut sponge, absorb_count as u32, &elts[..], acc);
 
             let output = SpongeAPI::squeeze(&mut sponge, squeeze_count as u32, acc);
@@ -690,7 +1511,7 @@ mod tests {
 
     impl<F: PrimeField, A: Arity<F>> Circuit<F> for S<F, A> {
         fn synthesize<CS: ConstraintSystem<F>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
-            use crate::sponge::api::SpongeAPI;
+            use crate::sponge::api::{DomainSeparator, SpongeAPI};
 
             let mut sponge = SpongeCircuit::<F, A, _>::new_with_constants(&self.p, Mode::Simplex);
             let mut ns = cs.namespace(|| "ns");
@@ -705,7 +1526,7 @@ mod tests {
 
             let parameter = IOPattern(vec![SpongeOp::Absorb(5), SpongeOp::Squeeze(1)]);
 
-            sponge.start(parameter, None, acc);
+            sponge.start(parameter, DomainSeparator::default(), acc);
 
             SpongeAPI::absorb(
                 &mut sponge,
@@ -744,6 +1565,7 @@ mod tests {
     fn test_sponge_synthesis() {
         test_sponge_synthesis_aux::<Fr, typenum::U2>();
         test_sponge_synthesis_aux::<Fr, typenum::U4>();
+        test_sponge_synthesis_aux::<crate::test_bn254::Fr, typenum::U4>();
     }
 
     // Returns index of first mismatch, along with the mismatched elements if they exist.
@@ -1,3 +1,5 @@
 pub mod api;
+#[cfg(feature = "std")]
 pub mod circuit;
+pub mod padding;
 pub mod vanilla;
@@ -0,0 +1,129 @@
+//! Padding rules for the final, partially absorbed block of a simplex sponge.
+//!
+//! By default, a sponge pads as its [`HashType`](crate::hash_type::HashType) requires: constant-length hashes zero the
+//! unabsorbed rate elements, and sponge hashes leave them as they are. A [`Padding`] set with `Sponge::set_padding` or
+//! `SpongeCircuit::set_padding` replaces this, so that a sponge can match an external specification. Padding is only
+//! applied when the final block is partial, i.e. when a simplex sponge permutes after absorbing fewer than `rate`
+//! elements of its last block.
+use core::fmt;
+use ff::PrimeField;
+
+/// The rate elements of a sponge state which is being padded. This is implemented for mutable slices of field elements
+/// and of circuit elements, so that one [`Padding`] serves both the vanilla and the circuit sponge.
+pub trait PaddingState<F: PrimeField> {
+    /// Returns the number of rate elements.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Set the element at `index` to `value`.
+    fn set(&mut self, index: usize, value: F);
+
+    /// Add `value` to the element at `index`.
+    fn add(&mut self, index: usize, value: F);
+}
+
+impl<F: PrimeField> PaddingState<F> for &mut [F] {
+    fn len(&self) -> usize {
+        <[F]>::len(self)
+    }
+
+    fn set(&mut self, index: usize, value: F) {
+        self[index] = value;
+    }
+
+    fn add(&mut self, index: usize, value: F) {
+        self[index] += value;
+    }
+}
+
+/// A padding rule. It is applied to the rate elements of the state, of which the first `pos` hold the absorbed
+/// elements of the final block, right before the final permutation.
+pub trait Padding<F: PrimeField>: fmt::Debug {
+    fn apply(&self, state: &mut dyn PaddingState<F>, pos: usize);
+}
+
+/// Set every unabsorbed rate element to zero. This is what constant-length hashes do by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ZeroPad;
+
+impl<F: PrimeField> Padding<F> for ZeroPad {
+    fn apply(&self, state: &mut dyn PaddingState<F>, pos: usize) {
+        for index in pos..state.len() {
+            state.set(index, F::ZERO);
+        }
+    }
+}
+
+/// Absorb a single one after the final element, as the `10*` rule of the sponge construction does. Since absorbing
+/// adds to the rate elements, the elements after it are left as they are, which is equivalent to absorbing zeros.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Pad10Star;
+
+impl<F: PrimeField> Padding<F> for Pad10Star {
+    fn apply(&self, state: &mut dyn PaddingState<F>, pos: usize) {
+        state.add(pos, F::ONE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sponge::vanilla::{Mode, Sponge, SpongeTrait};
+    use crate::Strength;
+    use blstrs::Scalar as Fr;
+    use generic_array::typenum::U4;
+
+    fn hash(
+        constants: &crate::poseidon::PoseidonConstants<Fr, U4>,
+        padding: Option<&dyn Padding<Fr>>,
+        elements: &[Fr],
+    ) -> Vec<Fr> {
+        let mut sponge = Sponge::new_with_constants(constants, Mode::Simplex);
+        if let Some(padding) = padding {
+            sponge.set_padding(padding);
+        }
+        sponge.absorb_elements(elements, &mut ()).unwrap();
+        sponge.squeeze_elements(elements.len(), &mut ())
+    }
+
+    #[test]
+    fn test_pad10star_and_zero_pad() {
+        let elements: Vec<Fr> = (1..=6).map(Fr::from).collect();
+        let sponge_constants = Sponge::<Fr, U4>::api_constants(Strength::Standard);
+        let constant_length_constants = Sponge::<Fr, U4>::simplex_constants(elements.len());
+
+        // `ZeroPad` is the default padding of constant-length hashes.
+        assert_eq!(
+            hash(&constant_length_constants, None, &elements),
+            hash(&constant_length_constants, Some(&ZeroPad), &elements)
+        );
+
+        // `Pad10Star` is equivalent to absorbing a one, after which sponge hashes need no padding.
+        let pad10star = hash(&sponge_constants, Some(&Pad10Star), &elements);
+        let mut with_one = elements.clone();
+        with_one.push(Fr::from(1));
+        assert_eq!(
+            pad10star,
+            hash(&sponge_constants, None, &with_one)[..elements.len()]
+        );
+
+        // The padding rules are distinguishable from each other and from no padding.
+        let zero_pad = hash(&sponge_constants, Some(&ZeroPad), &elements);
+        assert_ne!(pad10star, zero_pad);
+        assert_ne!(pad10star, hash(&sponge_constants, None, &elements));
+    }
+
+    #[test]
+    fn test_padding_only_applies_to_partial_blocks() {
+        let elements: Vec<Fr> = (1..=8).map(Fr::from).collect();
+        let constants = Sponge::<Fr, U4>::api_constants(Strength::Standard);
+
+        assert_eq!(
+            hash(&constants, None, &elements),
+            hash(&constants, Some(&Pad10Star), &elements)
+        );
+    }
+}
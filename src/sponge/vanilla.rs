@@ -1,9 +1,14 @@
 use crate::hash_type::HashType;
 use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
-use crate::sponge::api::{Hasher, IOPattern, InnerSpongeAPI, SpongeOp};
+use crate::sponge::api::{label_domain_separator, Hasher, IOPattern, InnerSpongeAPI, SpongeOp};
+use crate::sponge::padding::Padding;
 use crate::{Error, Strength};
-use ff::PrimeField;
-use std::collections::VecDeque;
+use alloc::{collections::VecDeque, format, vec::Vec};
+use core::fmt;
+use ff::{PrimeField, PrimeFieldBits};
+use generic_array::{typenum::Unsigned, ArrayLength, GenericArray};
+use serde::{Deserialize, Serialize};
+use subtle::{Choice, ConstantTimeEq};
 
 // General information on sponge construction: https://keccak.team/files/CSF-0.1.pdf
 
@@ -27,6 +32,14 @@ In duplex mode, absorbing and squeezing can be interleaved. The state is permute
 absorbed. This makes R elements available to be squeezed. If elements remain to be squeezed when the state is permuted,
 remaining unsqueezed elements are queued. Otherwise they would be lost when permuting.
 
+Squeezing an element in duplex mode yields the rate element into which the corresponding absorbed element was added:
+the n-th element squeezed is the n-th rate element absorbed into. So, within the current block, an element may only be
+squeezed from a position which has already been absorbed into, i.e. `squeeze_pos <= absorb_pos` holds whenever an
+element is absorbed. The `SpongeTrait` methods maintain this, but it can be broken by interleaving them with the
+`SpongeAPI` (which leaves `squeeze_pos` at the rate after absorbing) or by setting positions directly. In that case,
+the next absorption would add to a rate element which has already been squeezed, and the element produced there would
+never be output. This is checked with a debug assertion, and `Sponge::set_strict` makes it an error in all builds.
+
 */
 
 pub enum SpongeMode {
@@ -36,28 +49,36 @@ pub enum SpongeMode {
     DuplexSqueeze,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Mode {
     Simplex,
     Duplex,
+    /// A simplex sponge whose absorbed elements overwrite the rate elements, rather than being added to them, whether
+    /// absorbed directly or through the `SpongeAPI`.
+    Overwrite,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Direction {
     Absorbing,
     Squeezing,
 }
 
+#[derive(Clone)]
 pub struct Sponge<'a, F: PrimeField, A: Arity<F>> {
     absorbed: usize,
     squeezed: usize,
     pub state: Poseidon<'a, F, A>,
+    strict: bool,
+    squeeze_before_permute: bool,
     mode: Mode,
     direction: Direction,
     squeeze_pos: usize,
     queue: VecDeque<F>,
     pattern: IOPattern,
     io_count: usize,
+    domain_separator: u128,
+    padding: Option<&'a dyn Padding<F>>,
 }
 
 pub trait SpongeTrait<'a, F: PrimeField, A: Arity<F>>
@@ -70,6 +91,15 @@ where
 
     fn new_with_constants(constants: &'a PoseidonConstants<F, A>, mode: Mode) -> Self;
 
+    /// Switch the sponge to `mode` and restore what it has absorbed and squeezed to that of a freshly-constructed
+    /// sponge, reusing existing allocations: the state elements, the direction, the absorb and squeeze positions, the
+    /// `absorbed` and `squeezed` counts, any permutation count, the duplex queue, and the `IOPattern` with its
+    /// progress are all reset.
+    ///
+    /// The configuration set after construction is preserved, where the implementation has it: strictness,
+    /// `squeeze_before_permute`, the domain separator given by a label, and the padding.
+    fn reset(&mut self, mode: Mode);
+
     fn simplex_constants(size: usize) -> PoseidonConstants<F, A> {
         PoseidonConstants::new_constant_length(size)
     }
@@ -85,12 +115,23 @@ where
     fn mode(&self) -> Mode;
     fn direction(&self) -> Direction;
     fn set_direction(&mut self, direction: Direction);
+
+    // The getters `rate`, `capacity`, `absorb_pos`, `squeeze_pos`, `absorbed` and `squeezed` are stable, and their
+    // meaning is as documented. The corresponding setters exist for implementations of the generic methods of this
+    // trait: calling them directly can break the invariants described at the top of this module.
+
+    /// The number of elements absorbed since construction or the last `reset`, across all permutations.
     fn absorbed(&self) -> usize;
     fn set_absorbed(&mut self, absorbed: usize);
+    /// The number of elements squeezed since construction or the last `reset`, across all permutations.
     fn squeezed(&self) -> usize;
     fn set_squeezed(&mut self, squeezed: usize);
+    /// The offset, within the rate, of the next rate element to be squeezed since the last permutation. It ranges
+    /// from 0 to `rate()`, the latter meaning that the state must be permuted before squeezing again.
     fn squeeze_pos(&self) -> usize;
     fn set_squeeze_pos(&mut self, squeeze_pos: usize);
+    /// The offset, within the rate, of the next rate element to be absorbed into since the last permutation. It
+    /// ranges from 0 to `rate()`, the latter meaning that the state must be permuted before absorbing again.
     fn absorb_pos(&self) -> usize;
     fn set_absorb_pos(&mut self, pos: usize);
 
@@ -102,14 +143,20 @@ where
 
     fn is_simplex(&self) -> bool {
         match self.mode() {
-            Mode::Simplex => true,
+            Mode::Simplex | Mode::Overwrite => true,
             Mode::Duplex => false,
         }
     }
     fn is_duplex(&self) -> bool {
         match self.mode() {
             Mode::Duplex => true,
-            Mode::Simplex => false,
+            Mode::Simplex | Mode::Overwrite => false,
+        }
+    }
+    fn is_overwrite(&self) -> bool {
+        match self.mode() {
+            Mode::Overwrite => true,
+            Mode::Simplex | Mode::Duplex => false,
         }
     }
 
@@ -131,14 +178,43 @@ where
         self.absorbed() - self.squeezed()
     }
 
+    /// Returns how many elements can be absorbed or squeezed, in the current direction, before the state is next
+    /// permuted: `rate - absorb_pos` while absorbing, where absorbing the last of them permutes, and
+    /// `rate - squeeze_pos` while squeezing, where squeezing the one after them permutes. Aligning absorptions to this
+    /// avoids permutations spent on partially filled blocks. A simplex sponge's first squeeze permutes regardless, and a
+    /// duplex sponge never permutes when squeezing.
+    fn elements_until_permute(&self) -> usize {
+        let pos = if self.is_absorbing() {
+            self.absorb_pos()
+        } else {
+            self.squeeze_pos()
+        };
+        self.rate() - pos
+    }
+
     fn is_immediately_squeezable(&self) -> bool {
         self.squeeze_pos() < self.absorb_pos()
     }
 
+    /// Whether absorbing an element now respects the duplex discipline described at the top of this module, i.e.
+    /// would not add to a rate element which has already been squeezed. Always true for simplex sponges.
+    fn can_absorb_without_overwriting(&self) -> bool {
+        !self.is_duplex() || self.squeeze_pos() <= self.absorb_pos()
+    }
+
+    /// Called before absorbing each element, after checking the direction. Implementations may return an error to
+    /// reject absorptions which break the duplex discipline.
+    fn check_absorb(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// The number of state elements which are absorbed into and squeezed from, i.e. the arity.
     fn rate(&self) -> usize;
 
+    /// The number of state elements which are never absorbed into or squeezed from directly. It is always 1.
     fn capacity(&self) -> usize;
 
+    /// The width of the state, i.e. `rate() + capacity()`.
     fn size(&self) -> usize;
 
     fn total_size(&self) -> usize {
@@ -187,7 +263,7 @@ where
                 Mode::Duplex => {
                     panic!("Duplex sponge must permute exactly `rate` absorbed elements.")
                 }
-                Mode::Simplex => {
+                Mode::Simplex | Mode::Overwrite => {
                     let final_permutation = self.squeezed() % self.total_size() <= self.rate();
                     assert!(
                         final_permutation,
@@ -213,7 +289,7 @@ where
             Direction::Squeezing => (),
             Direction::Absorbing => {
                 match self.mode() {
-                    Mode::Simplex => {
+                    Mode::Simplex | Mode::Overwrite => {
                         let done_squeezing_previous = self.squeeze_pos() >= self.rate();
                         let partially_absorbed = self.absorb_pos() > 0;
 
@@ -236,6 +312,11 @@ where
     /// Absorb one field element
     fn absorb(&mut self, elt: &Self::Elt, acc: &mut Self::Acc) -> Result<(), Self::Error> {
         self.ensure_absorbing();
+        self.check_absorb()?;
+        debug_assert!(
+            self.can_absorb_without_overwriting(),
+            "Duplex sponge cannot absorb into an already squeezed rate element."
+        );
 
         // Add input element to state and advance absorption position.
         let tmp = self.absorb_aux(elt);
@@ -299,6 +380,540 @@ where
     fn squeeze_elements(&mut self, count: usize, acc: &mut Self::Acc) -> Vec<Self::Elt>;
 }
 
+/// Pack `xs` into field elements for [`Sponge::absorb_u64s`], as many to an element as fit in `F::CAPACITY` bits.
+///
+/// With `k = F::CAPACITY / 64`, each element is `x_0 + x_1 * 2^64 + ... + x_{k-1} * 2^(64 * (k - 1))` for the next
+/// `k` integers, and the last element holds the remaining ones. Since trailing zeros are not distinguished, the number
+/// of integers must be fixed by the protocol or absorbed separately.
+///
+/// Returns an error if `F::CAPACITY` is less than 64, e.g. for the Goldilocks field, since no integer would fit.
+pub fn pack_u64s<F: PrimeField>(xs: &[u64]) -> Result<Vec<F>, Error> {
+    pack_words(xs.iter().copied(), xs.len(), 64)
+}
+
+/// Like [`pack_u64s`], for 32-bit integers, packing `F::CAPACITY / 32` to an element in steps of `2^32`.
+pub fn pack_u32s<F: PrimeField>(xs: &[u32]) -> Result<Vec<F>, Error> {
+    pack_words(xs.iter().map(|x| u64::from(*x)), xs.len(), 32)
+}
+
+/// The number of `bits`-bit words which fit in an element of `F`, or an error if none does.
+fn words_per_element<F: PrimeField>(bits: u32) -> Result<usize, Error> {
+    match (F::CAPACITY / bits) as usize {
+        0 => Err(Error::Other(format!(
+            "a field with a capacity of {} bits cannot hold {bits}-bit integers",
+            F::CAPACITY
+        ))),
+        per_element => Ok(per_element),
+    }
+}
+
+fn pack_words<F: PrimeField>(
+    words: impl Iterator<Item = u64>,
+    len: usize,
+    bits: u32,
+) -> Result<Vec<F>, Error> {
+    let per_element = words_per_element::<F>(bits)?;
+    let shift = F::from(2).pow_vartime([u64::from(bits)]);
+
+    let mut packed = Vec::with_capacity((len + per_element - 1) / per_element);
+    let mut words = words.peekable();
+    while words.peek().is_some() {
+        let mut scale = F::ONE;
+        let mut element = F::ZERO;
+        for word in words.by_ref().take(per_element) {
+            element += F::from(word) * scale;
+            scale *= shift;
+        }
+        packed.push(element);
+    }
+    Ok(packed)
+}
+
+/// An affine elliptic curve point whose coordinates are elements of the field `F`, so that a sponge over `F` can absorb
+/// it with [`Sponge::absorb_point`]. This is implemented for the points of every `pasta_curves` curve, over its base
+/// field, e.g. for Pallas points over `Fp`.
+///
+/// The identity has no affine coordinates, and is absorbed as `(0, 0)`. Implementations must only be provided for
+/// curves on which `(0, 0)` is not a point, such as short Weierstrass curves `y^2 = x^3 + a x + b` with `b != 0`, so
+/// that this encoding is unambiguous.
+#[cfg(feature = "group")]
+pub trait AffineCoordinates<F>: group::prime::PrimeCurveAffine {
+    /// Returns the coordinates `(x, y)` of the point, or `None` if it is the identity.
+    fn coordinates(&self) -> Option<(F, F)>;
+}
+
+#[cfg(feature = "group")]
+impl<C: pasta_curves::arithmetic::CurveAffine> AffineCoordinates<C::Base> for C {
+    fn coordinates(&self) -> Option<(C::Base, C::Base)> {
+        Option::from(pasta_curves::arithmetic::CurveAffine::coordinates(self)).map(
+            |coordinates: pasta_curves::arithmetic::Coordinates<C>| {
+                (*coordinates.x(), *coordinates.y())
+            },
+        )
+    }
+}
+
+impl<'a, F: PrimeField, A: Arity<F>> Sponge<'a, F, A> {
+    /// Absorb `xs` packed into as few elements as possible, as described by [`pack_u64s`].
+    pub fn absorb_u64s(&mut self, xs: &[u64], acc: &mut ()) -> Result<(), Error> {
+        self.absorb_elements(&pack_u64s::<F>(xs)?, acc)
+    }
+
+    /// Absorb `xs` packed into as few elements as possible, as described by [`pack_u32s`].
+    pub fn absorb_u32s(&mut self, xs: &[u32], acc: &mut ()) -> Result<(), Error> {
+        self.absorb_elements(&pack_u32s::<F>(xs)?, acc)
+    }
+
+    /// Absorb the affine point `point` as its two coordinates, `x` first, or as `(0, 0)` if it is the identity. See
+    /// [`AffineCoordinates`].
+    #[cfg(feature = "group")]
+    pub fn absorb_point<P: AffineCoordinates<F>>(
+        &mut self,
+        point: &P,
+        acc: &mut (),
+    ) -> Result<(), Error> {
+        let (x, y) = point.coordinates().unwrap_or((F::ZERO, F::ZERO));
+        self.absorb_elements(&[x, y], acc)
+    }
+
+    /// Squeeze `count` integers, the inverse of [`Sponge::absorb_u64s`]'s packing: each squeezed element yields its
+    /// low `F::CAPACITY / 64` 64-bit limbs, least significant first. Only bits below `F::CAPACITY` are used, so the
+    /// integers are close to uniform, though not exactly, since the modulus is not a power of two.
+    ///
+    /// Returns an error, without squeezing, if `F::CAPACITY` is less than 64, as for [`pack_u64s`].
+    pub fn squeeze_u64s(&mut self, count: usize, acc: &mut ()) -> Result<Vec<u64>, Error>
+    where
+        F: PrimeFieldBits,
+    {
+        let per_element = words_per_element::<F>(64)?;
+        let mut xs = Vec::with_capacity(count);
+        while xs.len() < count {
+            let element = self
+                .squeeze(acc)?
+                .ok_or_else(|| Error::Other("sponge could not be squeezed".into()))?;
+            let bits = element.to_le_bits();
+            xs.extend(
+                bits.chunks(64)
+                    .take(per_element.min(count - xs.len()))
+                    .map(|limb| {
+                        limb.iter()
+                            .rev()
+                            .fold(0, |acc, bit| (acc << 1) | u64::from(*bit))
+                    }),
+            );
+        }
+        Ok(xs)
+    }
+
+    /// Squeeze an integer uniformly distributed in `[0, bound)`, by rejection sampling, so that it is unbiased unlike
+    /// a squeezed element reduced modulo `bound`. The procedure is:
+    ///
+    /// 1. Let `b` be the bit length of `bound - 1`, i.e. the number of bits needed to represent every candidate.
+    /// 2. Squeeze an element, and let `x` be the integer formed by the `b` least significant bits of its canonical
+    ///    value.
+    /// 3. If `x < bound`, return it. Otherwise, go back to step 2.
+    ///
+    /// Each squeezed element is accepted with probability greater than a half. The low bits of a uniform field element
+    /// are uniform up to a bias of at most `2^b / p`, which is negligible for the fields this crate supports.
+    ///
+    /// Returns an error if `bound` is zero, or if the sponge cannot be squeezed.
+    pub fn squeeze_uniform(&mut self, bound: u64, acc: &mut ()) -> Result<u64, Error>
+    where
+        F: PrimeFieldBits,
+    {
+        if bound == 0 {
+            return Err(Error::Other("cannot sample from an empty range".into()));
+        }
+        let bits = (u64::BITS - (bound - 1).leading_zeros()) as usize;
+        loop {
+            let element = self
+                .squeeze(acc)?
+                .ok_or_else(|| Error::Other("sponge could not be squeezed".into()))?;
+            let x = element
+                .to_le_bits()
+                .iter()
+                .take(bits)
+                .rev()
+                .fold(0, |acc, bit| (acc << 1) | u64::from(*bit));
+            if x < bound {
+                return Ok(x);
+            }
+        }
+    }
+
+    /// Construct a duplex sponge which has already absorbed `length`, the number of elements the caller means to
+    /// squeeze from it, as the single element `F::from(length)`. Duplex constants carry no length in their domain tag,
+    /// so without such a prefix a transcript is indistinguishable from its extensions, which squeeze the same elements
+    /// before producing more. Nothing else is prefixed: the inputs are absorbed as given, and squeezing more or fewer
+    /// than `length` elements is not prevented. The prefix counts towards `absorbed` and, since a duplex sponge squeezes
+    /// what it has absorbed before permuting, is the first element squeezed: transcripts of different lengths are only
+    /// separated in the elements squeezed after the state is next permuted.
+    pub fn new_duplex_with_length_prefix(
+        constants: &'a PoseidonConstants<F, A>,
+        length: usize,
+    ) -> Self {
+        let mut sponge = Self::new_with_constants(constants, Mode::Duplex);
+        // A fresh, non-strict sponge accepts any absorption, and permuting the vanilla state cannot fail.
+        sponge
+            .absorb(&F::from(length as u64), &mut ())
+            .expect("absorbing into a fresh sponge cannot fail");
+        sponge
+    }
+
+    /// Construct a sponge whose `SpongeAPI::start` uses the domain separator derived from `label` by
+    /// [`label_domain_separator`], unless a separator is set, even to 0. Sponges with distinct labels thus produce
+    /// unrelated outputs for the same `IOPattern` and inputs. The label only affects the `SpongeAPI`, and is preserved
+    /// by `reset`.
+    pub fn new_named(constants: &'a PoseidonConstants<F, A>, mode: Mode, label: &str) -> Self {
+        let mut sponge = Self::new_with_constants(constants, mode);
+        sponge.domain_separator = label_domain_separator(label);
+        sponge
+    }
+
+    /// Returns the current value of the capacity element of the state. It starts as the domain tag (or the `IOPattern`
+    /// tag, once started through the `SpongeAPI`) and changes with every permutation, after which it binds everything
+    /// absorbed so far. It is therefore only meaningful at points of the protocol where the number of permutations is
+    /// fixed, e.g. right after absorbing a whole number of rate-sized blocks.
+    pub fn capacity_element(&self) -> F {
+        self.state.elements[0]
+    }
+
+    /// Returns the number of times a sponge of arity `A` permutes while following `pattern` through the `SpongeAPI`,
+    /// from `start` to `finish`. Only the absorb and squeeze positions are tracked, so no field arithmetic is
+    /// performed, and the cost is that of a pass over the ops of `pattern`. This is the count reported by
+    /// [`estimate_cost`](crate::sponge::circuit::estimate_cost) for the circuit sponge.
+    pub fn permutation_count_for(pattern: &IOPattern) -> usize {
+        pattern.permutation_count(A::to_usize())
+    }
+
+    /// In strict mode, absorbing an element which would break the duplex discipline described at the top of this
+    /// module returns an error, rather than only failing a debug assertion. Strict mode is preserved by `reset`.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// By default, a `SpongeAPI` absorb leaves its last block in the rate, and the first squeeze after it permutes
+    /// before reading the rate. With `squeeze_before_permute` set, every absorb permutes before returning instead, as
+    /// reference sponges which permute at the end of each absorb call do, and the next squeeze reads the permuted rate
+    /// without permuting again, until the whole rate has been squeezed.
+    ///
+    /// A single absorb followed by squeezes yields the same elements in both modes. The modes differ when absorbs follow
+    /// each other: here each one starts a new block after a permutation, whereas by default an absorb continues to fill
+    /// the block which the previous one left partially filled. A pattern ending with an absorb also permutes once more
+    /// here. The `SpongeTrait` methods are not affected, and the setting is preserved by `reset`.
+    pub fn set_squeeze_before_permute(&mut self, squeeze_before_permute: bool) {
+        self.squeeze_before_permute = squeeze_before_permute;
+    }
+
+    /// Pad the final block with `padding`, rather than as the hash type of the sponge's constants requires. See
+    /// [`padding`](crate::sponge::padding) for when padding is applied. The padding is preserved by `reset`.
+    pub fn set_padding(&mut self, padding: &'a dyn Padding<F>) {
+        self.padding = Some(padding);
+    }
+
+    /// Lazily squeeze elements through the `SpongeAPI` after every operation declared by the sponge's `IOPattern` has
+    /// been performed, permuting at rate boundaries exactly as `SpongeAPI::squeeze` does. This suits Fiat-Shamir
+    /// challenge generation, where the number of elements needed is not known in advance: declare the pattern without
+    /// the final squeeze and draw elements from the iterator until done. Elements drawn this way are not checked
+    /// against the pattern, and `SpongeAPI::finish` may still be called afterwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `IOPattern` has operations which have not yet been performed.
+    pub fn squeeze_iter<'b>(&'b mut self, acc: &'b mut ()) -> SqueezeIter<'a, 'b, F, A> {
+        assert_eq!(
+            self.io_count,
+            self.pattern.len(),
+            "the IOPattern must be complete before squeezing lazily"
+        );
+
+        SqueezeIter { sponge: self, acc }
+    }
+
+    /// Hash each of `preimages` through the `SpongeAPI` following `pattern`, resetting this sponge in between rather
+    /// than constructing one per preimage. The elements of a preimage are absorbed by the absorb ops of `pattern` in
+    /// order, and its digest is the last element squeezed, which for the usual `[Absorb(n), Squeeze(1)]` is the only
+    /// one. The sponge's mode and label are preserved.
+    ///
+    /// Returns an error if `pattern` is rejected by `IOPattern::try_new`, if it squeezes nothing, or if a preimage does
+    /// not have as many elements as `pattern` absorbs.
+    pub fn hash_many(
+        &mut self,
+        preimages: &[Vec<F>],
+        pattern: &IOPattern,
+        acc: &mut (),
+    ) -> Result<Vec<F>, Error> {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        // The pattern may have been built directly, skipping the checks which `SpongeAPI::absorb` and `squeeze` would
+        // otherwise panic on.
+        IOPattern::try_new(pattern.0.clone()).map_err(|e| Error::Other(format!("{e}")))?;
+        let absorbed: usize = pattern
+            .into_iter()
+            .filter(|op| op.is_absorb())
+            .map(|op| op.count() as usize)
+            .sum();
+        if !pattern.into_iter().any(|op| op.is_squeeze()) {
+            return Err(Error::Other("the IOPattern squeezes nothing".into()));
+        }
+        if let Some(preimage) = preimages.iter().find(|p| p.len() != absorbed) {
+            return Err(Error::ArityMismatch {
+                expected: absorbed,
+                got: preimage.len(),
+            });
+        }
+
+        let mut digests = Vec::with_capacity(preimages.len());
+        let mut squeezed = Vec::new();
+        for preimage in preimages {
+            SpongeTrait::reset(self, self.mode);
+            SpongeAPI::start(self, pattern.clone(), DomainSeparator::default(), acc);
+
+            let mut rest = &preimage[..];
+            for op in pattern {
+                match op {
+                    SpongeOp::Absorb(n) => {
+                        let (elements, tail) = rest.split_at(*n as usize);
+                        SpongeAPI::absorb(self, *n, elements, acc);
+                        rest = tail;
+                    }
+                    SpongeOp::Squeeze(n) => squeezed = SpongeAPI::squeeze(self, *n, acc),
+                    // The op is the pattern's own, so this cannot fail.
+                    SpongeOp::Ratchet => SpongeAPI::ratchet(self, acc).unwrap(),
+                }
+            }
+            // Every op of the pattern was performed, so this cannot fail.
+            SpongeAPI::finish(self, acc).unwrap();
+
+            digests.extend(squeezed.last());
+        }
+        Ok(digests)
+    }
+
+    /// Absorb all of `inputs`, then squeeze `out_len` elements. This is equivalent to calling `absorb` for each input
+    /// followed by `out_len` calls to `squeeze`, including when the inputs cross a rate boundary.
+    ///
+    /// A duplex sponge can only squeeze as many elements as it has absorbed in total. If `out_len` exceeds that, an
+    /// error is returned after absorbing `inputs`, and nothing is squeezed.
+    pub fn duplex(&mut self, inputs: &[F], out_len: usize, acc: &mut ()) -> Result<Vec<F>, Error> {
+        self.absorb_elements(inputs, acc)?;
+
+        if self.is_duplex() && self.available() < out_len {
+            return Err(Error::Other(format!(
+                "cannot squeeze {out_len} elements from a duplex sponge with {} available",
+                self.available()
+            )));
+        }
+
+        let mut output = Vec::with_capacity(out_len);
+        for _ in 0..out_len {
+            output.extend(self.squeeze(acc)?);
+        }
+        Ok(output)
+    }
+
+    /// Hash the variable-length `msg` with this sponge's constants, so that all callers agree on its encoding. The sponge
+    /// is first reset to simplex mode, and is left exhausted. The scheme is:
+    ///
+    /// 1. The message is `msg.len()` as a field element, followed by `msg`, followed by a single one, followed by as
+    ///    many zeros as needed to make its length a multiple of the rate (i.e., the arity).
+    /// 2. Starting from a state whose capacity element is the domain tag and whose rate elements are zero, each
+    ///    rate-sized block of the message is added to the rate elements, and the state is then permuted.
+    /// 3. The first rate element of the final state is returned.
+    pub fn hash_var_length(&mut self, msg: &[F], acc: &mut ()) -> F {
+        self.reset(Mode::Simplex);
+
+        let rate = SpongeTrait::rate(self);
+        let mut padded = Vec::with_capacity(msg.len() + 2 + rate);
+        padded.push(F::from(msg.len() as u64));
+        padded.extend_from_slice(msg);
+        padded.push(F::ONE);
+        while padded.len() % rate != 0 {
+            padded.push(F::ZERO);
+        }
+
+        // Since only whole blocks are absorbed, no implicit padding is applied, and squeezing does not permute again.
+        // Neither absorbing nor squeezing can fail for a simplex sponge.
+        self.absorb_elements(&padded, acc)
+            .expect("simplex sponge absorption failed");
+        self.squeeze(acc)
+            .expect("simplex sponge squeeze failed")
+            .expect("simplex sponge was already exhausted")
+    }
+
+    /// Squeeze `expected.len()` elements and compare them to `expected` in constant time, e.g. to check an
+    /// authentication tag. Every element is squeezed and compared, even after a mismatch. As with [`Sponge::duplex`],
+    /// an error is returned before squeezing if a duplex sponge has fewer elements available.
+    pub fn squeeze_and_verify(&mut self, expected: &[F], acc: &mut ()) -> Result<bool, Error> {
+        if self.is_duplex() && self.available() < expected.len() {
+            return Err(Error::Other(format!(
+                "cannot squeeze {} elements from a duplex sponge with {} available",
+                expected.len(),
+                self.available()
+            )));
+        }
+
+        let mut equal = Choice::from(1);
+        for expected in expected {
+            let squeezed = self
+                .squeeze(acc)?
+                .ok_or_else(|| Error::Other("sponge could not be squeezed".into()))?;
+            equal &= squeezed.ct_eq(expected);
+        }
+        Ok(equal.into())
+    }
+
+    /// Like `SpongeAPI::squeeze`, but squeezes a statically known number of elements, `N`, into a `GenericArray`
+    /// rather than a `Vec`, so that e.g. squeezing a single challenge needs no heap allocation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the next op of the `IOPattern` is not a squeeze of `N` elements.
+    pub fn squeeze_array<N: ArrayLength>(&mut self, acc: &mut ()) -> GenericArray<F, N> {
+        let length = u32::try_from(N::USIZE).expect("squeeze length does not fit in a u32");
+        let mut out = GenericArray::default();
+        crate::sponge::api::SpongeAPI::squeeze_into(self, length, &mut out, acc)
+            .unwrap_or_else(|e| panic!("{e}"));
+        out
+    }
+
+    /// Returns the complete state of the sponge, from which [`Sponge::import_state`] restores an equivalent sponge.
+    pub fn export_state(&self) -> SpongeState<F> {
+        SpongeState {
+            elements: self.state.elements.to_vec(),
+            absorb_pos: SpongeTrait::absorb_pos(self),
+            squeeze_pos: self.squeeze_pos,
+            absorbed: self.absorbed,
+            squeezed: self.squeezed,
+            mode: self.mode,
+            direction: self.direction,
+            queue: self.queue.iter().copied().collect(),
+            pattern: self.pattern.clone(),
+            io_count: self.io_count,
+            strict: self.strict,
+            squeeze_before_permute: self.squeeze_before_permute,
+            domain_separator: self.domain_separator,
+        }
+    }
+
+    /// Restore a sponge from a state returned by [`Sponge::export_state`]. The sponge behaves exactly as the exported
+    /// one would have, provided `constants` are the ones it was constructed with: only their width can be checked, and
+    /// a mismatch is an error. Positions outside the rate, queues longer than the elements absorbed but not yet
+    /// squeezed, and `io_count`s beyond the pattern are rejected as well.
+    pub fn import_state(
+        constants: &'a PoseidonConstants<F, A>,
+        state: SpongeState<F>,
+    ) -> Result<Self, Error> {
+        let mut sponge = Self::new_with_constants(constants, state.mode);
+        let width = sponge.state.elements.len();
+        if state.elements.len() != width {
+            return Err(Error::WidthMismatch {
+                expected: width,
+                got: state.elements.len(),
+            });
+        }
+        let rate = SpongeTrait::rate(&sponge);
+        if state.absorb_pos > rate
+            || state.squeeze_pos > rate
+            || state.queue.len() > state.absorbed.saturating_sub(state.squeezed)
+            || state.io_count > state.pattern.len()
+        {
+            return Err(Error::IndexOutOfBounds);
+        }
+
+        sponge.state.elements.copy_from_slice(&state.elements);
+        SpongeTrait::set_absorb_pos(&mut sponge, state.absorb_pos);
+        sponge.squeeze_pos = state.squeeze_pos;
+        sponge.absorbed = state.absorbed;
+        sponge.squeezed = state.squeezed;
+        sponge.direction = state.direction;
+        sponge.queue.extend(state.queue);
+        sponge.pattern = state.pattern;
+        sponge.io_count = state.io_count;
+        sponge.strict = state.strict;
+        sponge.squeeze_before_permute = state.squeeze_before_permute;
+        sponge.domain_separator = state.domain_separator;
+        Ok(sponge)
+    }
+}
+
+/// The complete state of a [`Sponge`], as returned by [`Sponge::export_state`]. It can be serialized, e.g. to checkpoint
+/// a long-running transcript to disk, and restored with [`Sponge::import_state`], possibly in another process. It does
+/// not include the sponge's constants, which must be supplied again on import, nor its padding, which must be set again.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(serialize = "F: Serialize", deserialize = "F: Deserialize<'de>"))]
+pub struct SpongeState<F: PrimeField> {
+    /// The state vector, capacity element first.
+    pub elements: Vec<F>,
+    pub absorb_pos: usize,
+    pub squeeze_pos: usize,
+    pub absorbed: usize,
+    pub squeezed: usize,
+    pub mode: Mode,
+    pub direction: Direction,
+    /// Elements made available by a duplex sponge which have not yet been squeezed.
+    pub queue: Vec<F>,
+    #[serde(deserialize_with = "IOPattern::deserialize_ops")]
+    pub pattern: IOPattern,
+    pub io_count: usize,
+    pub strict: bool,
+    /// See [`Sponge::set_squeeze_before_permute`]. States exported before this existed import with it unset.
+    #[serde(default)]
+    pub squeeze_before_permute: bool,
+    pub domain_separator: u128,
+}
+
+/// Prints the mode, direction and the stable cursor state described by `SpongeTrait`, but not the state elements.
+impl<F: PrimeField, A: Arity<F>> fmt::Debug for Sponge<'_, F, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sponge")
+            .field("mode", &self.mode)
+            .field("direction", &self.direction)
+            .field("rate", &SpongeTrait::rate(self))
+            .field("capacity", &SpongeTrait::capacity(self))
+            .field("absorb_pos", &SpongeTrait::absorb_pos(self))
+            .field("squeeze_pos", &SpongeTrait::squeeze_pos(self))
+            .field("absorbed", &self.absorbed)
+            .field("squeezed", &self.squeezed)
+            .finish()
+    }
+}
+
+/// Overwrites the state elements and the queued elements with zeros, including those left in the spare capacity of the
+/// queue. Field elements don't implement `Zeroize` in general, so they are overwritten with volatile writes of
+/// `F::ZERO`, which the compiler can't elide. The sponge remains usable, but its state is no longer that of any
+/// sequence of operations: `reset` it before using it again.
+#[cfg(feature = "zeroize")]
+impl<F: PrimeField, A: Arity<F>> zeroize::Zeroize for Sponge<'_, F, A> {
+    fn zeroize(&mut self) {
+        fn wipe<F: PrimeField>(elements: &mut [F]) {
+            for element in elements {
+                // SAFETY: `element` is valid and aligned for writes, and `F: Copy` has no destructor to skip.
+                unsafe { core::ptr::write_volatile(element, F::ZERO) };
+            }
+        }
+
+        wipe(&mut self.state.elements);
+        let capacity = self.queue.capacity();
+        self.queue.resize(capacity, F::ZERO);
+        let (front, back) = self.queue.as_mut_slices();
+        wipe(front);
+        wipe(back);
+        self.queue.clear();
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<F: PrimeField, A: Arity<F>> Drop for Sponge<'_, F, A> {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(self);
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<F: PrimeField, A: Arity<F>> zeroize::ZeroizeOnDrop for Sponge<'_, F, A> {}
+
 impl<'a, F: PrimeField, A: Arity<F>> SpongeTrait<'a, F, A> for Sponge<'a, F, A> {
     type Acc = ();
     type Elt = F;
@@ -317,9 +932,25 @@ impl<'a, F: PrimeField, A: Arity<F>> SpongeTrait<'a, F, A> for Sponge<'a, F, A>
             queue: VecDeque::with_capacity(A::to_usize()),
             pattern: IOPattern(Vec::new()),
             io_count: 0,
+            strict: false,
+            squeeze_before_permute: false,
+            domain_separator: 0,
+            padding: None,
         }
     }
 
+    fn reset(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.direction = Direction::Absorbing;
+        self.state.reset();
+        self.absorbed = 0;
+        self.squeezed = 0;
+        self.squeeze_pos = 0;
+        self.queue.clear();
+        self.pattern.0.clear();
+        self.io_count = 0;
+    }
+
     fn mode(&self) -> Mode {
         self.mode
     }
@@ -382,7 +1013,13 @@ impl<'a, F: PrimeField, A: Arity<F>> SpongeTrait<'a, F, A> for Sponge<'a, F, A>
     }
 
     fn pad(&mut self) {
-        self.state.apply_padding();
+        match self.padding {
+            Some(padding) => {
+                let pos = SpongeTrait::absorb_pos(self);
+                padding.apply(&mut &mut self.state.elements[1..], pos);
+            }
+            None => self.state.apply_padding(),
+        }
     }
 
     fn permute_state(&mut self, _acc: &mut Self::Acc) -> Result<(), Self::Error> {
@@ -405,9 +1042,29 @@ impl<'a, F: PrimeField, A: Arity<F>> SpongeTrait<'a, F, A> for Sponge<'a, F, A>
     }
 
     fn absorb_aux(&mut self, elt: &Self::Elt) -> Self::Elt {
+        if self.is_overwrite() {
+            return *elt;
+        }
         self.element(SpongeTrait::absorb_pos(self) + SpongeTrait::capacity(self)) + elt
     }
 
+    fn check_absorb(&self) -> Result<(), Error> {
+        if self.strict && !self.can_absorb_without_overwriting() {
+            return Err(Error::Other(format!(
+                "duplex sponge cannot absorb at position {} after squeezing up to position {}",
+                SpongeTrait::absorb_pos(self),
+                SpongeTrait::squeeze_pos(self)
+            )));
+        }
+        Ok(())
+    }
+
+    /// There is deliberately no unchecked variant of this for inputs known to be reduced: a value of a `PrimeField`
+    /// is canonical by construction (`from_repr` rejects representations which are not less than the modulus), and
+    /// absorbing one is a single field addition, which the field implementation already performs as cheaply as it
+    /// can. The time spent absorbing is dominated by the permutation every `rate` elements, which no variant could
+    /// skip without changing the result. Trusted bytes still pay for the reduction check once, when decoded with
+    /// [`field_from_bytes_le`](crate::encoding::field_from_bytes_le) or `from_repr`.
     fn absorb_elements(&mut self, elts: &[F], acc: &mut Self::Acc) -> Result<(), Self::Error> {
         for elt in elts {
             self.absorb(elt, acc)?;
@@ -420,6 +1077,31 @@ impl<'a, F: PrimeField, A: Arity<F>> SpongeTrait<'a, F, A> for Sponge<'a, F, A>
     }
 }
 
+/// An iterator lazily squeezing elements from a [`Sponge`] through the `SpongeAPI`, returned by
+/// [`Sponge::squeeze_iter`].
+pub struct SqueezeIter<'a, 'b, F: PrimeField, A: Arity<F>> {
+    sponge: &'b mut Sponge<'a, F, A>,
+    acc: &'b mut (),
+}
+
+impl<F: PrimeField, A: Arity<F>> Iterator for SqueezeIter<'_, '_, F, A> {
+    type Item = F;
+
+    fn next(&mut self) -> Option<F> {
+        let sponge = &mut *self.sponge;
+        let rate = InnerSpongeAPI::rate(sponge);
+        if InnerSpongeAPI::squeeze_pos(sponge) == rate {
+            InnerSpongeAPI::permute(sponge, self.acc);
+            InnerSpongeAPI::set_squeeze_pos(sponge, 0);
+            InnerSpongeAPI::set_absorb_pos(sponge, 0);
+        }
+        let squeezed = sponge.read_rate_element(InnerSpongeAPI::squeeze_pos(sponge));
+        InnerSpongeAPI::set_squeeze_pos(sponge, InnerSpongeAPI::squeeze_pos(sponge) + 1);
+
+        Some(squeezed)
+    }
+}
+
 impl<F: PrimeField, A: Arity<F>> Iterator for Sponge<'_, F, A> {
     type Item = F;
 
@@ -430,7 +1112,7 @@ impl<F: PrimeField, A: Arity<F>> Iterator for Sponge<'_, F, A> {
     fn size_hint(&self) -> (usize, Option<usize>) {
         match self.mode {
             Mode::Duplex => (self.available(), None),
-            Mode::Simplex => (0, None),
+            Mode::Simplex | Mode::Overwrite => (0, None),
         }
     }
 }
@@ -490,11 +1172,27 @@ impl<F: PrimeField, A: Arity<F>> InnerSpongeAPI<F, A> for Sponge<'_, F, A> {
         self.pattern = pattern
     }
 
+    fn io_count(&self) -> usize {
+        self.io_count
+    }
+
     fn increment_io_count(&mut self) -> usize {
         let old_count = self.io_count;
         self.io_count += 1;
         old_count
     }
+
+    fn domain_separator(&self) -> u128 {
+        self.domain_separator
+    }
+
+    fn squeeze_before_permute(&self) -> bool {
+        self.squeeze_before_permute
+    }
+
+    fn absorb_overwrites(&self) -> bool {
+        self.is_overwrite()
+    }
 }
 
 #[cfg(test)]
@@ -548,6 +1246,100 @@ mod tests {
         assert_eq!(n, result.len());
     }
 
+    #[test]
+    fn test_reset() {
+        let c = Sponge::<Fr, typenum::U4>::duplex_constants();
+        let acc = &mut ();
+
+        let elements: Vec<Fr> = (0..7).map(Fr::from).collect();
+
+        let mut fresh = Sponge::new_with_constants(&c, Mode::Duplex);
+        fresh.absorb_elements(&elements[..4], acc).unwrap();
+        let expected = fresh.squeeze_elements(4, acc);
+
+        // Leave the sponge mid-way through absorption, with elements queued.
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        sponge.reset(Mode::Duplex);
+        sponge.absorb_elements(&elements, acc).unwrap();
+
+        sponge.reset(Mode::Duplex);
+        assert_eq!(0, sponge.absorbed());
+        assert_eq!(0, sponge.squeezed());
+        assert!(sponge.is_duplex());
+        assert!(sponge.is_absorbing());
+
+        sponge.absorb_elements(&elements[..4], acc).unwrap();
+        assert_eq!(expected, sponge.squeeze_elements(4, acc));
+    }
+
+    #[test]
+    fn test_overwrite() {
+        let c = Sponge::<Fr, typenum::U4>::simplex_constants(6);
+        let acc = &mut ();
+
+        let elements: Vec<Fr> = (1..7).map(Fr::from).collect();
+
+        let mut simplex = Sponge::new_with_constants(&c, Mode::Simplex);
+        let mut overwrite = Sponge::new_with_constants(&c, Mode::Overwrite);
+        assert!(overwrite.is_simplex());
+
+        // Before the first permutation the rate elements are zero, so adding and overwriting agree.
+        simplex.absorb_elements(&elements[..4], acc).unwrap();
+        overwrite.absorb_elements(&elements[..4], acc).unwrap();
+        assert_eq!(simplex.element(1), overwrite.element(1));
+
+        simplex.absorb_elements(&elements[4..], acc).unwrap();
+        overwrite.absorb_elements(&elements[4..], acc).unwrap();
+        assert_eq!(elements[4], overwrite.element(1));
+        assert_eq!(elements[5], overwrite.element(2));
+        assert_ne!(simplex.element(1), overwrite.element(1));
+        assert_ne!(
+            simplex.squeeze_elements(1, acc),
+            overwrite.squeeze_elements(1, acc)
+        );
+
+        // The `SpongeAPI` overwrites too.
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+        let c = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let pattern = IOPattern(vec![SpongeOp::Absorb(6), SpongeOp::Squeeze(1)]);
+        let mut simplex = Sponge::new_with_constants(&c, Mode::Simplex);
+        let mut overwrite = Sponge::new_with_constants(&c, Mode::Overwrite);
+        for sponge in [&mut simplex, &mut overwrite] {
+            sponge.start(pattern.clone(), DomainSeparator::default(), acc);
+            SpongeAPI::absorb(sponge, 6, &elements, acc);
+        }
+        assert_eq!(elements[4], overwrite.element(1));
+        assert_eq!(elements[5], overwrite.element(2));
+        assert_ne!(
+            SpongeAPI::squeeze(&mut simplex, 1, acc),
+            SpongeAPI::squeeze(&mut overwrite, 1, acc)
+        );
+    }
+
+    #[test]
+    fn test_clone_forks_sponge() {
+        let c = Sponge::<Fr, typenum::U4>::simplex_constants(6);
+        let acc = &mut ();
+
+        let elements: Vec<Fr> = (0..7).map(Fr::from).collect();
+
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        sponge.absorb_elements(&elements[..5], acc).unwrap();
+
+        // Both branches continue from the same (partially queued) state.
+        let mut fork = sponge.clone();
+        sponge.absorb(&elements[5], acc).unwrap();
+        fork.absorb(&elements[6], acc).unwrap();
+        let a = sponge.squeeze_elements(2, acc);
+        let b = fork.squeeze_elements(2, acc);
+        assert_ne!(a, b);
+
+        let mut replay = Sponge::new_with_constants(&c, Mode::Simplex);
+        replay.absorb_elements(&elements[..5], acc).unwrap();
+        replay.absorb(&elements[6], acc).unwrap();
+        assert_eq!(b, replay.squeeze_elements(2, acc));
+    }
+
     #[test]
     fn test_duplex_consistency() {
         let mut rng = XorShiftRng::from_seed(TEST_SEED);
@@ -623,9 +1415,1015 @@ mod tests {
         (output, signature)
     }
 
+    #[test]
+    fn test_new_duplex_with_length_prefix() {
+        let c = Sponge::<Fr, typenum::U4>::duplex_constants();
+        let acc = &mut ();
+        let inputs: Vec<Fr> = (1..=3).map(Fr::from).collect();
+
+        let mut manual = Sponge::new_with_constants(&c, Mode::Duplex);
+        manual.absorb(&Fr::from(3), acc).unwrap();
+        let mut prefixed = Sponge::new_duplex_with_length_prefix(&c, 3);
+        assert_eq!(1, prefixed.absorbed);
+        assert_eq!(Mode::Duplex, prefixed.mode);
+
+        let squeeze = |sponge: &mut Sponge<Fr, typenum::U4>| {
+            sponge.absorb_elements(&inputs, &mut ()).unwrap();
+            sponge.squeeze_elements(3, &mut ())
+        };
+        let expected = squeeze(&mut manual);
+        assert_eq!(expected, squeeze(&mut prefixed));
+
+        // The prefix itself is squeezed first.
+        assert_eq!(Fr::from(3), expected[0]);
+
+        // The prefix separates transcripts of different lengths once the state is permuted. Together with the inputs,
+        // it fills the rate, so the elements absorbed next are added to the permuted state.
+        let permuted = |length| {
+            let mut sponge = Sponge::new_duplex_with_length_prefix(&c, length);
+            sponge.absorb_elements(&inputs, &mut ()).unwrap();
+            sponge.squeeze_elements(4, &mut ());
+            sponge.absorb_elements(&inputs[..2], &mut ()).unwrap();
+            sponge.squeeze_elements(2, &mut ())
+        };
+        assert_ne!(permuted(3), permuted(4));
+        assert_ne!(inputs[..2], permuted(3)[..]);
+    }
+
+    #[test]
+    fn test_duplex_call() {
+        let c = Sponge::<Fr, typenum::U4>::duplex_constants();
+        let acc = &mut ();
+        let inputs: Vec<Fr> = (0..12).map(Fr::from).collect();
+
+        // Cover squeezing less than, exactly and more than a rate's worth, from both sides of a rate boundary.
+        for (absorb, squeeze) in [(3, 2), (4, 4), (5, 5), (11, 9), (2, 2)] {
+            let mut manual = Sponge::new_with_constants(&c, Mode::Duplex);
+            let mut duplexed = manual.clone();
+
+            for round in 0..2 {
+                let inputs = &inputs[round..round + absorb];
+                manual.absorb_elements(inputs, acc).unwrap();
+                let expected: Vec<Fr> = (0..squeeze)
+                    .map(|_| manual.squeeze(acc).unwrap().unwrap())
+                    .collect();
+
+                assert_eq!(expected, duplexed.duplex(inputs, squeeze, acc).unwrap());
+            }
+        }
+
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Duplex);
+        assert!(sponge.duplex(&inputs[..3], 4, acc).is_err());
+        assert_eq!(3, sponge.duplex(&[], 3, acc).unwrap().len());
+    }
+
+    #[test]
+    fn test_hash_var_length() {
+        let c = Sponge::<Fr, typenum::U4>::duplex_constants();
+        let acc = &mut ();
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Duplex);
+
+        for len in 0..10 {
+            let msg: Vec<Fr> = (0..len).map(|i| Fr::from(i + 1)).collect();
+
+            let mut state = vec![Fr::from(0); c.width()];
+            state[0] = c.domain_tag;
+            let mut padded = vec![Fr::from(len)];
+            padded.extend(&msg);
+            padded.push(Fr::from(1));
+            padded.resize((padded.len() + 3) / 4 * 4, Fr::from(0));
+            for block in padded.chunks(4) {
+                for (elt, x) in state[1..].iter_mut().zip(block) {
+                    *elt += x;
+                }
+                c.permute(&mut state);
+            }
+
+            let digest = sponge.hash_var_length(&msg, acc);
+            assert_eq!(state[1], digest);
+            assert_eq!(digest, sponge.hash_var_length(&msg, acc));
+        }
+
+        // Trailing zeros are not ambiguous.
+        let one = [Fr::from(1)];
+        assert_ne!(
+            sponge.hash_var_length(&one, acc),
+            sponge.hash_var_length(&[one[0], Fr::from(0)], acc)
+        );
+    }
+
+    #[test]
+    fn test_squeeze_and_verify() {
+        let c = Sponge::<Fr, typenum::U4>::duplex_constants();
+        let acc = &mut ();
+        let inputs: Vec<Fr> = (0..6).map(Fr::from).collect();
+
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Duplex);
+        sponge.absorb_elements(&inputs, acc).unwrap();
+        let mut verifier = sponge.clone();
+        let mut tampered = sponge.clone();
+        let mut short = sponge.clone();
+
+        let tag = sponge.squeeze_elements(5, acc);
+        assert!(verifier.squeeze_and_verify(&tag, acc).unwrap());
+
+        let mut wrong_tag = tag.clone();
+        wrong_tag[2] += Fr::from(1);
+        assert!(!tampered.squeeze_and_verify(&wrong_tag, acc).unwrap());
+
+        assert!(short.squeeze_and_verify(&[Fr::from(0); 7], acc).is_err());
+    }
+
+    #[test]
+    fn test_duplex_discipline() {
+        let c = Sponge::<Fr, typenum::U4>::duplex_constants();
+        let acc = &mut ();
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Duplex);
+        sponge.set_strict(true);
+
+        // Squeezing everything absorbed, up to and across the rate boundary, keeps to the discipline.
+        for n in 1..=9 {
+            sponge.absorb(&Fr::from(n), acc).unwrap();
+            assert!(sponge.can_absorb_without_overwriting());
+            if n % 3 == 0 {
+                while sponge.squeeze(acc).unwrap().is_some() {}
+                assert!(sponge.can_absorb_without_overwriting());
+            }
+        }
+
+        // Squeezing past what has been absorbed into the current block breaks it.
+        let absorb_pos = SpongeTrait::absorb_pos(&sponge);
+        SpongeTrait::set_squeeze_pos(&mut sponge, absorb_pos + 1);
+        assert!(!sponge.can_absorb_without_overwriting());
+        assert!(sponge.absorb(&Fr::from(0), acc).is_err());
+
+        SpongeTrait::reset(&mut sponge, Mode::Duplex);
+        SpongeTrait::set_squeeze_pos(&mut sponge, 1);
+        assert!(sponge.absorb(&Fr::from(0), acc).is_err());
+
+        // Simplex sponges are never affected.
+        let c = Sponge::<Fr, typenum::U4>::simplex_constants(2);
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        sponge.set_strict(true);
+        SpongeTrait::set_squeeze_pos(&mut sponge, 1);
+        assert!(sponge.absorb(&Fr::from(0), acc).is_ok());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "already squeezed")]
+    fn test_duplex_discipline_debug_assert() {
+        let c = Sponge::<Fr, typenum::U4>::duplex_constants();
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Duplex);
+        SpongeTrait::set_squeeze_pos(&mut sponge, 1);
+        sponge.absorb(&Fr::from(0), &mut ()).unwrap();
+    }
+
+    #[test]
+    fn test_squeeze_iter() {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let c = Sponge::<Fr, typenum::U2>::api_constants(Strength::Standard);
+        let acc = &mut ();
+        let pattern = IOPattern(vec![SpongeOp::Absorb(3), SpongeOp::Squeeze(1)]);
+
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        SpongeAPI::start(&mut sponge, pattern, DomainSeparator::default(), acc);
+        SpongeAPI::absorb(
+            &mut sponge,
+            3,
+            &[Fr::from(1), Fr::from(2), Fr::from(3)],
+            acc,
+        );
+        let mut split = sponge.clone();
+
+        let first = SpongeAPI::squeeze(&mut sponge, 1, acc);
+        let all: Vec<Fr> = sponge.squeeze_iter(acc).take(7).collect();
+
+        // Continues the output stream of the declared squeeze, however it is drawn, across rate boundaries.
+        assert_eq!(first, SpongeAPI::squeeze(&mut split, 1, acc));
+        let mut drawn: Vec<Fr> = split.squeeze_iter(acc).take(2).collect();
+        drawn.extend(split.squeeze_iter(acc).take(5));
+        assert_eq!(all, drawn);
+        assert_ne!(first[0], all[0]);
+
+        assert!(SpongeAPI::finish(&mut sponge, acc).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "must be complete")]
+    fn test_squeeze_iter_incomplete_pattern() {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let c = Sponge::<Fr, typenum::U2>::api_constants(Strength::Standard);
+        let acc = &mut ();
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        SpongeAPI::start(
+            &mut sponge,
+            IOPattern(vec![SpongeOp::Absorb(1)]),
+            DomainSeparator::default(),
+            acc,
+        );
+        sponge.squeeze_iter(acc).next();
+    }
+
+    #[test]
+    fn test_absorb_u64s() {
+        use ff::Field;
+
+        let c = Sponge::<Fr, typenum::U2>::simplex_constants(4);
+        let xs: Vec<u64> = (0..10).map(|i| u64::MAX - i).collect();
+
+        // BLS12-381's scalar field has a capacity of 254 bits, so three integers fit in each element.
+        let packed = pack_u64s::<Fr>(&xs).unwrap();
+        assert_eq!(4, packed.len());
+        let shift = Fr::from(2).pow_vartime([64]);
+        assert_eq!(
+            Fr::from(xs[3]) + Fr::from(xs[4]) * shift + Fr::from(xs[5]) * shift.square(),
+            packed[1]
+        );
+        assert_eq!(Fr::from(xs[9]), packed[3]);
+        // And seven 32-bit integers.
+        let packed_u32s = pack_u32s::<Fr>(&[1; 10]).unwrap();
+        assert_eq!(2, packed_u32s.len());
+        assert_eq!(
+            Fr::from(1 + (1 << 32)),
+            pack_u32s::<Fr>(&[1, 1]).unwrap()[0]
+        );
+        assert_eq!(pack_u32s::<Fr>(&[1; 3]).unwrap()[0], packed_u32s[1]);
+
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        sponge.absorb_u64s(&xs, &mut ()).unwrap();
+        let mut reference = Sponge::new_with_constants(&c, Mode::Simplex);
+        reference.absorb_elements(&packed, &mut ()).unwrap();
+
+        let squeezed = sponge.squeeze_u64s(4, &mut ()).unwrap();
+        let elements = reference.squeeze_elements(2, &mut ());
+        let limbs = |x: Fr| {
+            let bits = x.to_le_bits();
+            (0..3)
+                .map(|i| (0..64).fold(0u64, |acc, j| acc | (u64::from(bits[64 * i + j]) << j)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(limbs(elements[0]), squeezed[..3]);
+        assert_eq!(limbs(elements[1])[..1], squeezed[3..]);
+    }
+
+    #[test]
+    fn test_absorb_u64s_small_field() {
+        use crate::test_goldilocks::Fp;
+
+        // Goldilocks has a capacity of 63 bits, too few for a 64-bit integer but enough for one 32-bit integer.
+        assert!(pack_u64s::<Fp>(&[1, 2]).is_err());
+        assert!(pack_u64s::<Fp>(&[]).is_err());
+        assert_eq!(
+            vec![Fp::from(1), Fp::from(2)],
+            pack_u32s::<Fp>(&[1, 2]).unwrap()
+        );
+
+        // Round constants cannot be generated for fields smaller than 32 bytes, so import arbitrary ones.
+        let strength = Strength::custom(8, 22).unwrap();
+        let c = PoseidonConstants::<Fp, typenum::U2>::from_parts(
+            (1..=90).map(Fp::from).collect(),
+            crate::mds::cauchy_mds(
+                &[Fp::from(0), Fp::from(1), Fp::from(2)],
+                &[Fp::from(3), Fp::from(4), Fp::from(5)],
+            )
+            .unwrap(),
+            strength,
+        )
+        .unwrap();
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        assert!(sponge.absorb_u64s(&[1], &mut ()).is_err());
+        sponge.absorb_u32s(&[1, 2], &mut ()).unwrap();
+        assert!(sponge.squeeze_u64s(1, &mut ()).is_err());
+        // Nothing was squeezed by the failed call.
+        let mut reference = Sponge::new_with_constants(&c, Mode::Simplex);
+        reference.absorb_u32s(&[1, 2], &mut ()).unwrap();
+        assert_eq!(
+            reference.squeeze_elements(2, &mut ()),
+            sponge.squeeze_elements(2, &mut ())
+        );
+    }
+
+    #[test]
+    fn test_capacity_element() {
+        let c = Sponge::<Fr, typenum::U2>::duplex_constants();
+        let absorb = |inputs: &[Fr]| {
+            let mut sponge = Sponge::new_with_constants(&c, Mode::Duplex);
+            sponge.absorb_elements(inputs, &mut ()).unwrap();
+            sponge.capacity_element()
+        };
+
+        // Nothing is bound before the first permutation.
+        assert_eq!(c.domain_tag, absorb(&[Fr::from(1)]));
+        assert_eq!(
+            absorb(&[Fr::from(1), Fr::from(2)]),
+            absorb(&[Fr::from(1), Fr::from(2)])
+        );
+        assert_ne!(c.domain_tag, absorb(&[Fr::from(1), Fr::from(2)]));
+        assert_ne!(
+            absorb(&[Fr::from(1), Fr::from(2)]),
+            absorb(&[Fr::from(1), Fr::from(3)])
+        );
+    }
+
+    #[test]
+    fn test_permutation_count_for() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let count = |ops: Vec<SpongeOp>| {
+            Sponge::<Fr, typenum::U4>::permutation_count_for(&IOPattern::try_new(ops).unwrap())
+        };
+
+        assert_eq!(0, count(vec![]));
+        assert_eq!(0, count(vec![SpongeOp::Absorb(4)]));
+        // Squeezing after absorbing always permutes first.
+        assert_eq!(1, count(vec![SpongeOp::Absorb(4), SpongeOp::Squeeze(1)]));
+        assert_eq!(2, count(vec![SpongeOp::Absorb(5), SpongeOp::Squeeze(1)]));
+        assert_eq!(2, count(vec![SpongeOp::Absorb(8), SpongeOp::Squeeze(1)]));
+        assert_eq!(2, count(vec![SpongeOp::Absorb(4), SpongeOp::Squeeze(5)]));
+        // Long ops are counted without visiting each element.
+        assert_eq!((1 << 28) - 1, count(vec![SpongeOp::Absorb(1 << 30)]));
+
+        // Agree with a replay of the `SpongeAPI` bookkeeping one element at a time.
+        for _ in 0..100 {
+            let ops: Vec<SpongeOp> = (0..rng.gen_range(0..8))
+                .map(|_| {
+                    let n = rng.gen_range(1..12);
+                    if rng.gen() {
+                        SpongeOp::Absorb(n)
+                    } else {
+                        SpongeOp::Squeeze(n)
+                    }
+                })
+                .collect();
+
+            let mut expected = 0;
+            let (mut absorb_pos, mut squeeze_pos) = (0, 0);
+            for op in &ops {
+                for _ in 0..op.count() {
+                    let pos = if op.is_absorb() {
+                        &mut absorb_pos
+                    } else {
+                        &mut squeeze_pos
+                    };
+                    if *pos == 4 {
+                        expected += 1;
+                        *pos = 0;
+                        if !op.is_absorb() {
+                            absorb_pos = 0;
+                        }
+                    }
+                    if op.is_absorb() {
+                        absorb_pos += 1;
+                    } else {
+                        squeeze_pos += 1;
+                    }
+                }
+                if op.is_absorb() {
+                    squeeze_pos = 4;
+                }
+            }
+            assert_eq!(expected, count(ops));
+        }
+    }
+
+    #[test]
+    fn test_export_import_state() {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let c = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let elements: Vec<Fr> = (0..10).map(Fr::from).collect();
+
+        // A duplex sponge with queued elements.
+        let mut duplex = Sponge::new_with_constants(&c, Mode::Duplex);
+        duplex.absorb_elements(&elements[..6], &mut ()).unwrap();
+        duplex.squeeze_elements(1, &mut ());
+        let json = serde_json::to_vec(&duplex.export_state()).unwrap();
+        let mut restored =
+            Sponge::import_state(&c, serde_json::from_slice(&json).unwrap()).unwrap();
+        assert_eq!(duplex.export_state(), restored.export_state());
+        for sponge in [&mut duplex, &mut restored] {
+            sponge.absorb_elements(&elements[6..], &mut ()).unwrap();
+        }
+        assert_eq!(
+            duplex.squeeze_elements(5, &mut ()),
+            restored.squeeze_elements(5, &mut ())
+        );
+
+        // A duplex sponge which has queued more than a rate's worth of elements.
+        let mut duplex = Sponge::new_with_constants(&c, Mode::Duplex);
+        duplex.absorb_elements(&elements, &mut ()).unwrap();
+        assert!(duplex.export_state().queue.len() > 4);
+        let mut restored = Sponge::import_state(&c, duplex.export_state()).unwrap();
+        assert_eq!(
+            duplex.squeeze_elements(10, &mut ()),
+            restored.squeeze_elements(10, &mut ())
+        );
+
+        // A sponge in the middle of an `IOPattern`.
+        let pattern = IOPattern(vec![SpongeOp::Absorb(5), SpongeOp::Squeeze(2)]);
+        let acc = &mut ();
+        let mut sponge = Sponge::new_named(&c, Mode::Simplex, "checkpoint");
+        sponge.start(pattern, DomainSeparator::default(), acc);
+        SpongeAPI::absorb(&mut sponge, 5, &elements[..5], acc);
+        let mut restored = Sponge::import_state(&c, sponge.export_state()).unwrap();
+        assert_eq!(
+            SpongeAPI::squeeze(&mut sponge, 2, acc),
+            SpongeAPI::squeeze(&mut restored, 2, acc)
+        );
+        restored.finish(acc).unwrap();
+
+        // The pattern of a state is not coalesced, since `io_count` indexes into its ops.
+        let pattern = IOPattern(vec![SpongeOp::Absorb(2), SpongeOp::Absorb(3)]);
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        sponge.start(pattern, DomainSeparator::default(), acc);
+        SpongeAPI::absorb(&mut sponge, 2, &elements[..2], acc);
+        let json = serde_json::to_vec(&sponge.export_state()).unwrap();
+        let mut restored =
+            Sponge::import_state(&c, serde_json::from_slice(&json).unwrap()).unwrap();
+        SpongeAPI::absorb(&mut restored, 3, &elements[2..5], acc);
+        restored.finish(acc).unwrap();
+
+        let wide = Sponge::<Fr, typenum::U8>::api_constants(Strength::Standard);
+        assert!(matches!(
+            Sponge::import_state(&wide, sponge.export_state()),
+            Err(Error::WidthMismatch {
+                expected: 9,
+                got: 5
+            })
+        ));
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_zeroize() {
+        use zeroize::Zeroize;
+
+        let c = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let elements: Vec<Fr> = (1..=6).map(Fr::from).collect();
+
+        // A duplex sponge with queued elements.
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Duplex);
+        sponge.absorb_elements(&elements, &mut ()).unwrap();
+        sponge.squeeze_elements(1, &mut ());
+        assert!(!sponge.export_state().queue.is_empty());
+
+        sponge.zeroize();
+        let state = sponge.export_state();
+        assert!(state.elements.iter().all(|x| *x == Fr::from(0)));
+        assert!(state.queue.is_empty());
+
+        // The sponge can be used again once it is reset.
+        sponge.reset(Mode::Duplex);
+        sponge.absorb_elements(&elements, &mut ()).unwrap();
+        let mut fresh = Sponge::new_with_constants(&c, Mode::Duplex);
+        fresh.absorb_elements(&elements, &mut ()).unwrap();
+        assert_eq!(
+            fresh.squeeze_elements(3, &mut ()),
+            sponge.squeeze_elements(3, &mut ())
+        );
+    }
+
+    #[test]
+    fn test_squeeze_before_permute() {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let c = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let pattern = IOPattern(vec![
+            SpongeOp::Absorb(2),
+            SpongeOp::Absorb(1),
+            SpongeOp::Squeeze(5),
+        ]);
+
+        let squeeze = |pattern: &IOPattern, squeeze_before_permute| {
+            let acc = &mut ();
+            let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+            sponge.set_squeeze_before_permute(squeeze_before_permute);
+            sponge.start(pattern.clone(), DomainSeparator::default(), acc);
+            for op in pattern {
+                match op {
+                    SpongeOp::Absorb(n) => {
+                        let elements: Vec<Fr> = (1..=*n as u64).map(Fr::from).collect();
+                        SpongeAPI::absorb(&mut sponge, *n, &elements, acc);
+                    }
+                    SpongeOp::Squeeze(n) => return SpongeAPI::squeeze(&mut sponge, *n, acc),
+                    SpongeOp::Ratchet => unreachable!(),
+                }
+            }
+            unreachable!()
+        };
+
+        // The reference procedure permutes at the end of every absorb call: the capacity holds the tag, `[1, 2]` is
+        // added to the rate and the state permuted, `[1]` is added and the state permuted again, then five elements
+        // are squeezed with a permutation after the first four. These values were computed by an implementation of
+        // that procedure and of the Poseidon permutation independent of neptune, with the same parameters.
+        let reference = [
+            "1704989086155937104317991330574321344816080383805285936677797219771336428333",
+            "4580598651660483295943590258202649622733322983898382055126685930223254072921",
+            "43871284288179562647596749770653467501961169364222731358842703497119761549043",
+            "12535643545993741016925112278000933322766764164327511906445085054053019485429",
+            "38913656979488428400861319518970778026130130979096447824896790855282031547129",
+        ]
+        .map(|x| Fr::from_str_vartime(x).unwrap());
+        assert_eq!(reference.to_vec(), squeeze(&pattern, true));
+        assert_ne!(reference.to_vec(), squeeze(&pattern, false));
+
+        // A single absorb followed by squeezes yields the same elements in both modes.
+        let pattern = IOPattern(vec![SpongeOp::Absorb(3), SpongeOp::Squeeze(6)]);
+        assert_eq!(squeeze(&pattern, true), squeeze(&pattern, false));
+    }
+
+    #[test]
+    fn test_squeeze_uniform() {
+        let c = Sponge::<Fr, typenum::U4>::simplex_constants(1);
+        let acc = &mut ();
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        sponge.absorb(&Fr::from(7), acc).unwrap();
+
+        // Replaying the documented procedure on squeezed elements yields the same samples.
+        let mut replay = sponge.clone();
+        for bound in [1, 2, 3, 5, 6, 100, 1 << 40, u64::MAX] {
+            let x = sponge.squeeze_uniform(bound, acc).unwrap();
+            assert!(x < bound);
+
+            let bits = (64 - (bound - 1).leading_zeros()) as usize;
+            let expected = loop {
+                let element = replay.squeeze(acc).unwrap().unwrap();
+                let candidate = element
+                    .to_le_bits()
+                    .iter()
+                    .take(bits)
+                    .enumerate()
+                    .fold(0u64, |acc, (i, bit)| acc | u64::from(*bit) << i);
+                if candidate < bound {
+                    break candidate;
+                }
+            };
+            assert_eq!(expected, x);
+        }
+
+        // Every value in a small range is sampled.
+        let mut seen = [false; 6];
+        for _ in 0..100 {
+            seen[sponge.squeeze_uniform(6, acc).unwrap() as usize] = true;
+        }
+        assert!(seen.iter().all(|seen| *seen));
+
+        assert!(sponge.squeeze_uniform(0, acc).is_err());
+    }
+
+    #[cfg(feature = "group")]
+    #[test]
+    fn test_absorb_point() {
+        use group::prime::PrimeCurveAffine;
+        use pasta_curves::arithmetic::CurveAffine;
+        use pasta_curves::{pallas, Fp};
+
+        let c = Sponge::<Fp, typenum::U2>::api_constants(Strength::Standard);
+        let hash = |elements: &[Fp]| {
+            let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+            sponge.absorb_elements(elements, &mut ()).unwrap();
+            sponge.squeeze(&mut ()).unwrap().unwrap()
+        };
+        let hash_point = |point: &pallas::Affine| {
+            let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+            sponge.absorb_point(point, &mut ()).unwrap();
+            sponge.squeeze(&mut ()).unwrap().unwrap()
+        };
+
+        let generator = pallas::Affine::generator();
+        let coordinates = CurveAffine::coordinates(&generator).unwrap();
+        assert_eq!(
+            hash(&[*coordinates.x(), *coordinates.y()]),
+            hash_point(&generator)
+        );
+        assert_eq!(
+            hash(&[Fp::from(0), Fp::from(0)]),
+            hash_point(&pallas::Affine::identity())
+        );
+    }
+
+    #[test]
+    fn test_elements_until_permute() {
+        let c = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        let acc = &mut ();
+
+        for i in 0..10 {
+            let remaining = sponge.elements_until_permute();
+            assert_eq!(4 - i % 4, remaining);
+            let capacity = sponge.capacity_element();
+            sponge.absorb(&Fr::from(i as u64), acc).unwrap();
+            // Only the last of the remaining elements permutes.
+            assert_eq!(remaining == 1, capacity != sponge.capacity_element());
+        }
+
+        sponge.squeeze(acc).unwrap();
+        assert_eq!(3, sponge.elements_until_permute());
+        sponge.squeeze_elements(3, acc);
+        assert_eq!(0, sponge.elements_until_permute());
+        let capacity = sponge.capacity_element();
+        sponge.squeeze(acc).unwrap();
+        assert_ne!(capacity, sponge.capacity_element());
+        assert_eq!(3, sponge.elements_until_permute());
+    }
+
+    #[test]
+    fn test_squeeze_array() {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let c = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let acc = &mut ();
+        let pattern = IOPattern(vec![
+            SpongeOp::Absorb(2),
+            SpongeOp::Squeeze(1),
+            SpongeOp::Squeeze(5),
+        ]);
+        let run = || {
+            let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+            sponge.start(pattern.clone(), DomainSeparator::default(), &mut ());
+            SpongeAPI::absorb(&mut sponge, 2, &[Fr::from(1), Fr::from(2)], &mut ());
+            sponge
+        };
+
+        let mut sponge = run();
+        let challenge = sponge.squeeze_array::<typenum::U1>(acc);
+        let rest = sponge.squeeze_array::<typenum::U5>(acc);
+        sponge.finish(acc).unwrap();
+
+        let mut expected = run();
+        assert_eq!(
+            SpongeAPI::squeeze(&mut expected, 1, acc),
+            challenge.as_slice()
+        );
+        assert_eq!(SpongeAPI::squeeze(&mut expected, 5, acc), rest.as_slice());
+        expected.finish(acc).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Operation 1 of the IOPattern is Squeeze(1), got Squeeze(2).")]
+    fn test_squeeze_array_mismatch() {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let c = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let acc = &mut ();
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        let pattern = IOPattern(vec![SpongeOp::Absorb(1), SpongeOp::Squeeze(1)]);
+        sponge.start(pattern, DomainSeparator::default(), acc);
+        SpongeAPI::absorb(&mut sponge, 1, &[Fr::from(1)], acc);
+        sponge.squeeze_array::<typenum::U2>(acc);
+    }
+
+    #[test]
+    fn test_ratchet() {
+        use crate::sponge::api::{self, DomainSeparator, SpongeAPI};
+
+        let c = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let acc = &mut ();
+        let run = |pattern: &str| {
+            let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+            sponge.start(
+                pattern.parse().unwrap(),
+                DomainSeparator::default(),
+                &mut (),
+            );
+            SpongeAPI::absorb(&mut sponge, 2, &[Fr::from(1), Fr::from(2)], &mut ());
+            sponge
+        };
+
+        // A ratchet permutes and keeps only the capacity, and the next squeeze permutes again.
+        let mut sponge = run("A2 R S1");
+        let mut permuted = run("A2 S1");
+        let squeezed = SpongeAPI::squeeze(&mut permuted, 1, acc);
+        let capacity = sponge.capacity_element();
+        sponge.ratchet(acc).unwrap();
+        assert_ne!(capacity, sponge.capacity_element());
+        assert!(sponge.state.elements[1..].iter().all(|x| *x == Fr::from(0)));
+        let after = SpongeAPI::squeeze(&mut sponge, 1, acc);
+        sponge.finish(acc).unwrap();
+        assert_ne!(squeezed, after);
+
+        // A ratchet is an op of the pattern like any other.
+        let mut sponge = run("A2 S1");
+        let state = sponge.export_state();
+        assert!(matches!(
+            sponge.ratchet(acc),
+            Err(api::Error::PatternMismatch {
+                expected: Some(SpongeOp::Squeeze(1)),
+                got: SpongeOp::Ratchet,
+                index: 1
+            })
+        ));
+        assert_eq!(state, sponge.export_state());
+    }
+
+    #[test]
+    fn test_zero_length_ops() {
+        use crate::sponge::api::{self, DomainSeparator, SpongeAPI};
+
+        let c = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let acc = &mut ();
+        let pattern = IOPattern(vec![
+            SpongeOp::Absorb(1),
+            SpongeOp::Absorb(0),
+            SpongeOp::Squeeze(0),
+            SpongeOp::Squeeze(1),
+        ]);
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        sponge.start(pattern, DomainSeparator::default(), acc);
+        SpongeAPI::absorb(&mut sponge, 1, &[Fr::from(1)], acc);
+
+        // Zero-length ops are rejected even where the pattern declares them, and leave the sponge untouched.
+        let state = sponge.export_state();
+        assert!(matches!(
+            sponge.try_absorb(0, &[], acc),
+            Err(api::Error::ZeroCount { index: 1 })
+        ));
+        assert!(matches!(
+            sponge.try_squeeze(0, acc),
+            Err(api::Error::ZeroCount { index: 1 })
+        ));
+        assert!(matches!(
+            sponge.squeeze_into(0, &mut [], acc),
+            Err(api::Error::ZeroCount { index: 1 })
+        ));
+        assert_eq!(state, sponge.export_state());
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot squeeze zero elements")]
+    fn test_zero_length_squeeze_panics() {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let c = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let acc = &mut ();
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        sponge.start(
+            IOPattern(vec![SpongeOp::Squeeze(0)]),
+            DomainSeparator::default(),
+            acc,
+        );
+        SpongeAPI::squeeze(&mut sponge, 0, acc);
+    }
+
+    #[test]
+    fn test_finish_partial() {
+        use crate::sponge::api::{self, DomainSeparator, SpongeAPI};
+
+        let c = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let pattern = IOPattern(vec![
+            SpongeOp::Absorb(2),
+            SpongeOp::Squeeze(1),
+            SpongeOp::Absorb(1),
+            SpongeOp::Squeeze(1),
+        ]);
+        let elements = [Fr::from(1), Fr::from(2)];
+        let acc = &mut ();
+        let run = |acc: &mut ()| {
+            let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+            sponge.start(pattern.clone(), DomainSeparator::default(), acc);
+            SpongeAPI::absorb(&mut sponge, 2, &elements, acc);
+            let squeezed = SpongeAPI::squeeze(&mut sponge, 1, acc);
+            (sponge, squeezed)
+        };
+
+        // Finishing after a prefix of the pattern succeeds and clears the state.
+        let (mut sponge, partial) = run(acc);
+        assert_eq!(2, sponge.finish_partial(acc));
+        assert!(sponge.state.elements.iter().all(|x| *x == Fr::from(0)));
+
+        // `finish` rejects the same prefix, and the prefix is indistinguishable from the start of a complete run.
+        let (mut sponge, squeezed) = run(acc);
+        assert_eq!(partial, squeezed);
+        assert!(matches!(
+            sponge.finish(acc),
+            Err(api::Error::ParameterUsageMismatch)
+        ));
+        let (mut sponge, _) = run(acc);
+        SpongeAPI::absorb(&mut sponge, 1, &elements[..1], acc);
+        SpongeAPI::squeeze(&mut sponge, 1, acc);
+        assert_eq!(4, sponge.finish_partial(acc));
+    }
+
+    #[test]
+    fn test_squeeze_into() {
+        use crate::sponge::api::{self, DomainSeparator, SpongeAPI};
+
+        let c = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let pattern = IOPattern(vec![
+            SpongeOp::Absorb(2),
+            SpongeOp::Squeeze(6),
+            SpongeOp::Squeeze(1),
+        ]);
+        let elements = [Fr::from(1), Fr::from(2)];
+        let acc = &mut ();
+
+        let mut expected = Sponge::new_with_constants(&c, Mode::Simplex);
+        expected.start(pattern.clone(), DomainSeparator::default(), acc);
+        SpongeAPI::absorb(&mut expected, 2, &elements, acc);
+
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        sponge.start(pattern, DomainSeparator::default(), acc);
+        SpongeAPI::absorb(&mut sponge, 2, &elements, acc);
+
+        let mut out = [Fr::from(0); 6];
+        assert!(matches!(
+            sponge.squeeze_into(6, &mut out[..5], acc),
+            Err(api::Error::ParameterUsageMismatch)
+        ));
+        assert!(matches!(
+            sponge.squeeze_into(5, &mut out[..5], acc),
+            Err(api::Error::PatternMismatch { .. })
+        ));
+
+        // Squeezes across a permutation, like `squeeze`.
+        sponge.squeeze_into(6, &mut out, acc).unwrap();
+        assert_eq!(SpongeAPI::squeeze(&mut expected, 6, acc), out);
+        let mut challenge = [Fr::from(0)];
+        sponge.squeeze_into(1, &mut challenge, acc).unwrap();
+        assert_eq!(SpongeAPI::squeeze(&mut expected, 1, acc), challenge);
+        sponge.finish(acc).unwrap();
+    }
+
+    #[test]
+    fn test_hash_many() {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let c = Sponge::<Fr, typenum::U4>::api_constants(Strength::Standard);
+        let pattern = IOPattern(vec![
+            SpongeOp::Absorb(3),
+            SpongeOp::Squeeze(1),
+            SpongeOp::Absorb(4),
+            SpongeOp::Squeeze(2),
+        ]);
+        let preimages: Vec<Vec<Fr>> = (0..5)
+            .map(|i| (0..7).map(|j| Fr::from(10 * i + j)).collect())
+            .collect();
+
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        let digests = sponge.hash_many(&preimages, &pattern, &mut ()).unwrap();
+        assert_eq!(preimages.len(), digests.len());
+
+        for (preimage, digest) in preimages.iter().zip(&digests) {
+            let acc = &mut ();
+            let mut fresh = Sponge::new_with_constants(&c, Mode::Simplex);
+            fresh.start(pattern.clone(), DomainSeparator::default(), acc);
+            SpongeAPI::absorb(&mut fresh, 3, &preimage[..3], acc);
+            SpongeAPI::squeeze(&mut fresh, 1, acc);
+            SpongeAPI::absorb(&mut fresh, 4, &preimage[3..], acc);
+            assert_eq!(*digest, SpongeAPI::squeeze(&mut fresh, 2, acc)[1]);
+            fresh.finish(acc).unwrap();
+        }
+
+        // The sponge's label is used as the domain separator.
+        let simple = IOPattern(vec![SpongeOp::Absorb(7), SpongeOp::Squeeze(1)]);
+        let unlabeled = sponge.hash_many(&preimages[..1], &simple, &mut ()).unwrap();
+        let mut labeled = Sponge::new_named(&c, Mode::Simplex, "label");
+        assert_ne!(
+            unlabeled,
+            labeled
+                .hash_many(&preimages[..1], &simple, &mut ())
+                .unwrap()
+        );
+
+        assert!(matches!(
+            sponge.hash_many(&[vec![Fr::from(1); 6]], &pattern, &mut ()),
+            Err(Error::ArityMismatch {
+                expected: 7,
+                got: 6
+            })
+        ));
+        assert!(sponge
+            .hash_many(
+                &[vec![Fr::from(1)]],
+                &IOPattern(vec![SpongeOp::Absorb(1)]),
+                &mut ()
+            )
+            .is_err());
+        assert!(matches!(
+            sponge.hash_many(
+                &[vec![Fr::from(1)]],
+                &IOPattern(vec![SpongeOp::Absorb(1), SpongeOp::Squeeze(0)]),
+                &mut ()
+            ),
+            Err(Error::Other(e)) if e == "Operation 1 of the IOPattern has a count of zero."
+        ));
+    }
+
+    #[test]
+    fn test_debug() {
+        let c = Sponge::<Fr, typenum::U4>::duplex_constants();
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Duplex);
+        sponge.absorb_elements(&[Fr::from(1); 5], &mut ()).unwrap();
+        sponge.squeeze_elements(2, &mut ());
+
+        assert_eq!(
+            "Sponge { mode: Duplex, direction: Squeezing, rate: 4, capacity: 1, absorb_pos: 1, squeeze_pos: 0, \
+             absorbed: 5, squeezed: 2 }",
+            format!("{sponge:?}")
+        );
+    }
+
+    #[test]
+    fn test_new_named() {
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
+
+        let c = Sponge::<Fr, typenum::U2>::api_constants(Strength::Standard);
+        let pattern = IOPattern(vec![SpongeOp::Absorb(2), SpongeOp::Squeeze(1)]);
+        let run = |mut sponge: Sponge<'_, Fr, typenum::U2>, domain_separator: DomainSeparator| {
+            let acc = &mut ();
+            SpongeAPI::start(&mut sponge, pattern.clone(), domain_separator, acc);
+            SpongeAPI::absorb(&mut sponge, 2, &[Fr::from(1), Fr::from(2)], acc);
+            let output = SpongeAPI::squeeze(&mut sponge, 1, acc);
+            SpongeAPI::finish(&mut sponge, acc).unwrap();
+            output
+        };
+
+        let transcript = run(
+            Sponge::new_named(&c, Mode::Simplex, "transcript"),
+            DomainSeparator::default(),
+        );
+        assert_eq!(
+            transcript,
+            run(
+                Sponge::new_named(&c, Mode::Simplex, "transcript"),
+                DomainSeparator::default()
+            )
+        );
+        assert_ne!(
+            transcript,
+            run(
+                Sponge::new_named(&c, Mode::Simplex, "commitment"),
+                DomainSeparator::default()
+            )
+        );
+        assert_ne!(
+            transcript,
+            run(
+                Sponge::new_with_constants(&c, Mode::Simplex),
+                DomainSeparator::default()
+            )
+        );
+
+        // The label is equivalent to starting with its derived separator, and an explicit separator overrides it.
+        let acc = &mut ();
+        let mut sponge = Sponge::new_with_constants(&c, Mode::Simplex);
+        SpongeAPI::start_with_domain(
+            &mut sponge,
+            pattern.clone(),
+            label_domain_separator("transcript"),
+            acc,
+        );
+        SpongeAPI::absorb(&mut sponge, 2, &[Fr::from(1), Fr::from(2)], acc);
+        assert_eq!(transcript, SpongeAPI::squeeze(&mut sponge, 1, acc));
+        assert_eq!(
+            transcript,
+            run(
+                Sponge::new_with_constants(&c, Mode::Simplex),
+                DomainSeparator::from_label("transcript")
+            )
+        );
+        assert_eq!(
+            run(
+                Sponge::new_with_constants(&c, Mode::Simplex),
+                DomainSeparator::from_u32(7)
+            ),
+            run(
+                Sponge::new_named(&c, Mode::Simplex, "transcript"),
+                DomainSeparator::from_u32(7)
+            )
+        );
+
+        // An explicit 0 overrides the label too, unlike the default separator.
+        let unnamed = run(
+            Sponge::new_with_constants(&c, Mode::Simplex),
+            DomainSeparator::default(),
+        );
+        assert_eq!(
+            unnamed,
+            run(
+                Sponge::new_named(&c, Mode::Simplex, "transcript"),
+                DomainSeparator::from_u32(0)
+            )
+        );
+
+        // The deprecated `Option<u32>` shim maps `None` to the default separator and `Some` to an explicit one.
+        #[allow(deprecated)]
+        let run_optional = |domain_separator: Option<u32>| {
+            let acc = &mut ();
+            let mut sponge = Sponge::new_named(&c, Mode::Simplex, "transcript");
+            sponge.start_optional(pattern.clone(), domain_separator, acc);
+            SpongeAPI::absorb(&mut sponge, 2, &[Fr::from(1), Fr::from(2)], acc);
+            SpongeAPI::squeeze(&mut sponge, 1, acc)
+        };
+        assert_eq!(transcript, run_optional(None));
+        assert_eq!(unnamed, run_optional(Some(0)));
+    }
+
     #[test]
     fn test_sponge_api_simple() {
-        use crate::sponge::api::SpongeAPI;
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
 
         let parameter = IOPattern(vec![
             SpongeOp::Absorb(1),
@@ -638,7 +2436,7 @@ mod tests {
             let mut sponge = Sponge::new_with_constants(&p, Mode::Simplex);
             let acc = &mut ();
 
-            sponge.start(parameter, None, acc);
+            sponge.start(parameter, DomainSeparator::default(), acc);
             SpongeAPI::absorb(&mut sponge, 1, &[Fr::from(123)], acc);
             SpongeAPI::absorb(
                 &mut sponge,
@@ -682,10 +2480,68 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sponge_api_checked() {
+        use crate::sponge::api::{DomainSeparator, Error as ApiError, SpongeAPI};
+
+        let parameter = IOPattern(vec![SpongeOp::Absorb(2), SpongeOp::Squeeze(1)]);
+
+        let p = Sponge::<Fr, typenum::U2>::api_constants(Strength::Standard);
+        let acc = &mut ();
+
+        let expected = {
+            let mut sponge = Sponge::new_with_constants(&p, Mode::Simplex);
+            sponge.start(parameter.clone(), DomainSeparator::default(), acc);
+            SpongeAPI::absorb(&mut sponge, 2, &[Fr::from(1), Fr::from(2)], acc);
+            let output = SpongeAPI::squeeze(&mut sponge, 1, acc);
+            sponge.finish(acc).unwrap();
+            output
+        };
+
+        let mut sponge = Sponge::new_with_constants(&p, Mode::Simplex);
+        sponge.start(parameter, DomainSeparator::default(), acc);
+
+        // Squeezing before absorbing does not match the pattern.
+        match sponge.try_squeeze(1, acc) {
+            Err(ApiError::PatternMismatch {
+                expected,
+                got,
+                index,
+            }) => {
+                assert_eq!(Some(SpongeOp::Absorb(2)), expected);
+                assert_eq!(SpongeOp::Squeeze(1), got);
+                assert_eq!(0, index);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Absorbing the wrong number of elements does not match the pattern either.
+        assert!(sponge.try_absorb(1, &[Fr::from(1)], acc).is_err());
+
+        // Failed operations leave the sponge untouched, so the caller can recover.
+        sponge
+            .try_absorb(2, &[Fr::from(1), Fr::from(2)], acc)
+            .unwrap();
+        let output = sponge.try_squeeze(1, acc).unwrap();
+
+        match sponge.try_squeeze(1, acc) {
+            Err(ApiError::PatternMismatch {
+                expected, index, ..
+            }) => {
+                assert_eq!(None, expected);
+                assert_eq!(2, index);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        sponge.finish(acc).unwrap();
+        assert_eq!(expected, output);
+    }
+
     #[test]
     #[should_panic]
     fn test_sponge_api_failure() {
-        use crate::sponge::api::SpongeAPI;
+        use crate::sponge::api::{DomainSeparator, SpongeAPI};
 
         let parameter = IOPattern(vec![
             SpongeOp::Absorb(1),
@@ -698,7 +2554,7 @@ mod tests {
             let mut sponge = Sponge::new_with_constants(&p, Mode::Simplex);
             let acc = &mut ();
 
-            sponge.start(parameter, None, acc);
+            sponge.start(parameter, DomainSeparator::default(), acc);
             SpongeAPI::absorb(&mut sponge, 1, &[Fr::from(123)], acc);
             SpongeAPI::absorb(
                 &mut sponge,
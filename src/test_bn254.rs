@@ -0,0 +1,366 @@
+//! The BN254 scalar field, which is also the base field of Grumpkin, for tests showing that neptune works over it.
+//!
+//! The modulus `r` is 254 bits, one fewer than the BLS12-381 and Pasta scalar fields, and `r - 1` is divisible by 3, so
+//! that the default S-box is `x^5` as for those fields. No BN254 crate is a dependency, so the field is implemented
+//! here. Elements are stored as canonical little-endian limbs, and multiplied with two Montgomery multiplications.
+use core::fmt;
+use core::iter::{Product, Sum};
+use core::ops::{Add, AddAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+use ff::{Field, PrimeField};
+use rand::RngCore;
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq, CtOption};
+
+const MODULUS: [u64; 4] = [
+    0x43e1_f593_f000_0001,
+    0x2833_e848_79b9_7091,
+    0xb850_45b6_8181_585d,
+    0x3064_4e72_e131_a029,
+];
+
+/// `-r^-1 mod 2^64`.
+const INV: u64 = 0xc2e1_f593_efff_ffff;
+
+/// `2^512 mod r`, which converts the result of a Montgomery multiplication back to canonical form.
+const R2: [u64; 4] = [
+    0x1bb8_e645_ae21_6da7,
+    0x53fe_3ab1_e35c_59e3,
+    0x8c49_833d_53bb_8085,
+    0x0216_d0b1_7f4e_44a5,
+];
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Fr([u64; 4]);
+
+impl fmt::Debug for Fr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x")?;
+        self.0
+            .iter()
+            .rev()
+            .try_for_each(|limb| write!(f, "{limb:016x}"))
+    }
+}
+
+/// Returns `a - b` and whether it borrowed.
+fn sub_limbs(a: &[u64; 4], b: &[u64; 4]) -> ([u64; 4], bool) {
+    let mut result = [0; 4];
+    let mut borrow = false;
+    for i in 0..4 {
+        let (d, b1) = a[i].overflowing_sub(b[i]);
+        let (d, b2) = d.overflowing_sub(u64::from(borrow));
+        result[i] = d;
+        borrow = b1 || b2;
+    }
+    (result, borrow)
+}
+
+/// Reduces `limbs`, which must be less than `2r`, modulo `r`.
+fn reduce_once(limbs: [u64; 4]) -> Fr {
+    let (reduced, borrow) = sub_limbs(&limbs, &MODULUS);
+    if borrow {
+        Fr(limbs)
+    } else {
+        Fr(reduced)
+    }
+}
+
+/// Returns `a * b * 2^-256 mod r`, for `a` and `b` less than `r`.
+fn mont_mul(a: &[u64; 4], b: &[u64; 4]) -> Fr {
+    let mut t = [0u64; 6];
+    for b_i in b {
+        let mut carry = 0u128;
+        for j in 0..4 {
+            let sum = u128::from(t[j]) + u128::from(a[j]) * u128::from(*b_i) + carry;
+            t[j] = sum as u64;
+            carry = sum >> 64;
+        }
+        let sum = u128::from(t[4]) + carry;
+        t[4] = sum as u64;
+        t[5] = (sum >> 64) as u64;
+
+        // Add the multiple of `r` which clears the lowest limb, and shift it out.
+        let m = t[0].wrapping_mul(INV);
+        let mut carry = (u128::from(t[0]) + u128::from(m) * u128::from(MODULUS[0])) >> 64;
+        for j in 1..4 {
+            let sum = u128::from(t[j]) + u128::from(m) * u128::from(MODULUS[j]) + carry;
+            t[j - 1] = sum as u64;
+            carry = sum >> 64;
+        }
+        let sum = u128::from(t[4]) + carry;
+        t[3] = sum as u64;
+        t[4] = t[5] + (sum >> 64) as u64;
+    }
+    // `r < 2^254`, so the result is less than `2r` and fits in four limbs.
+    reduce_once([t[0], t[1], t[2], t[3]])
+}
+
+impl Fr {
+    fn add_impl(&self, other: &Self) -> Self {
+        let mut result = [0; 4];
+        let mut carry = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (s, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (s, c2) = s.overflowing_add(u64::from(carry));
+            *limb = s;
+            carry = c1 || c2;
+        }
+        reduce_once(result)
+    }
+
+    fn sub_impl(&self, other: &Self) -> Self {
+        let (diff, borrow) = sub_limbs(&self.0, &other.0);
+        if borrow {
+            // Adding `r` wraps around past `2^256`, giving the canonical result.
+            let mut result = [0; 4];
+            let mut carry = false;
+            for (i, limb) in result.iter_mut().enumerate() {
+                let (s, c1) = diff[i].overflowing_add(MODULUS[i]);
+                let (s, c2) = s.overflowing_add(u64::from(carry));
+                *limb = s;
+                carry = c1 || c2;
+            }
+            Fr(result)
+        } else {
+            Fr(diff)
+        }
+    }
+
+    fn mul_impl(&self, other: &Self) -> Self {
+        mont_mul(&mont_mul(&self.0, &other.0).0, &R2)
+    }
+}
+
+impl ConditionallySelectable for Fr {
+    fn conditional_select(a: &Self, b: &Self, choice: Choice) -> Self {
+        Fr(core::array::from_fn(|i| {
+            u64::conditional_select(&a.0[i], &b.0[i], choice)
+        }))
+    }
+}
+
+impl ConstantTimeEq for Fr {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0
+            .iter()
+            .zip(&other.0)
+            .fold(Choice::from(1), |acc, (a, b)| acc & a.ct_eq(b))
+    }
+}
+
+impl Neg for Fr {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::ZERO.sub_impl(&self)
+    }
+}
+
+macro_rules! impl_binop {
+    ($trait:ident, $method:ident, $assign_trait:ident, $assign_method:ident, $impl:ident) => {
+        impl $trait for Fr {
+            type Output = Self;
+
+            fn $method(self, other: Self) -> Self {
+                self.$impl(&other)
+            }
+        }
+
+        impl<'a> $trait<&'a Fr> for Fr {
+            type Output = Self;
+
+            fn $method(self, other: &'a Self) -> Self {
+                self.$impl(other)
+            }
+        }
+
+        impl $assign_trait for Fr {
+            fn $assign_method(&mut self, other: Self) {
+                *self = self.$impl(&other);
+            }
+        }
+
+        impl<'a> $assign_trait<&'a Fr> for Fr {
+            fn $assign_method(&mut self, other: &'a Self) {
+                *self = self.$impl(other);
+            }
+        }
+    };
+}
+
+impl_binop!(Add, add, AddAssign, add_assign, add_impl);
+impl_binop!(Sub, sub, SubAssign, sub_assign, sub_impl);
+impl_binop!(Mul, mul, MulAssign, mul_assign, mul_impl);
+
+impl Sum for Fr {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Fr> for Fr {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, Add::add)
+    }
+}
+
+impl Product for Fr {
+    fn product<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ONE, Mul::mul)
+    }
+}
+
+impl<'a> Product<&'a Fr> for Fr {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::ONE, Mul::mul)
+    }
+}
+
+impl From<u64> for Fr {
+    fn from(value: u64) -> Self {
+        Fr([value, 0, 0, 0])
+    }
+}
+
+impl Field for Fr {
+    const ZERO: Self = Fr([0; 4]);
+    const ONE: Self = Fr([1, 0, 0, 0]);
+
+    fn random(mut rng: impl RngCore) -> Self {
+        loop {
+            let mut limbs = [0; 4];
+            limbs.iter_mut().for_each(|limb| *limb = rng.next_u64());
+            limbs[3] &= u64::MAX >> 2;
+            if sub_limbs(&limbs, &MODULUS).1 {
+                return Fr(limbs);
+            }
+        }
+    }
+
+    fn square(&self) -> Self {
+        self.mul_impl(self)
+    }
+
+    fn double(&self) -> Self {
+        self.add_impl(self)
+    }
+
+    fn invert(&self) -> CtOption<Self> {
+        let (r_minus_two, _) = sub_limbs(&MODULUS, &[2, 0, 0, 0]);
+        CtOption::new(self.pow_vartime(r_minus_two), !self.is_zero())
+    }
+
+    fn sqrt(&self) -> CtOption<Self> {
+        // `(t - 1) / 2`, where `r - 1 = 2^28 * t` with `t` odd.
+        let t_minus_one_over_two = [
+            0xcdcb_848a_1f0f_ac9f,
+            0x0c0a_c2e9_419f_4243,
+            0x098d_014d_c282_2db4,
+            0x0000_0001_8322_7397,
+        ];
+        ff::helpers::sqrt_tonelli_shanks(self, t_minus_one_over_two)
+    }
+
+    fn sqrt_ratio(num: &Self, div: &Self) -> (Choice, Self) {
+        ff::helpers::sqrt_ratio_generic(num, div)
+    }
+}
+
+impl PrimeField for Fr {
+    type Repr = [u8; 32];
+
+    fn from_repr(repr: Self::Repr) -> CtOption<Self> {
+        let limbs: [u64; 4] = core::array::from_fn(|i| {
+            u64::from_le_bytes(repr[8 * i..8 * (i + 1)].try_into().unwrap())
+        });
+        let (_, borrow) = sub_limbs(&limbs, &MODULUS);
+        CtOption::new(Fr(limbs), Choice::from(u8::from(borrow)))
+    }
+
+    fn to_repr(&self) -> Self::Repr {
+        let mut repr = [0; 32];
+        for (chunk, limb) in repr.chunks_exact_mut(8).zip(&self.0) {
+            chunk.copy_from_slice(&limb.to_le_bytes());
+        }
+        repr
+    }
+
+    fn is_odd(&self) -> Choice {
+        Choice::from((self.0[0] & 1) as u8)
+    }
+
+    const MODULUS: &'static str =
+        "0x30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000001";
+    const NUM_BITS: u32 = 254;
+    const CAPACITY: u32 = 253;
+    const TWO_INV: Self = Fr([
+        0xa1f0_fac9_f800_0001,
+        0x9419_f424_3cdc_b848,
+        0xdc28_22db_40c0_ac2e,
+        0x1832_2739_7098_d014,
+    ]);
+    const MULTIPLICATIVE_GENERATOR: Self = Fr([5, 0, 0, 0]);
+    const S: u32 = 28;
+    // `5^t`, which has order `2^28`.
+    const ROOT_OF_UNITY: Self = Fr([
+        0x9bd6_1b6e_725b_19f0,
+        0x402d_111e_4111_2ed4,
+        0x00e0_a7eb_8ef6_2abc,
+        0x2a3c_09f0_a58a_7e85,
+    ]);
+    const ROOT_OF_UNITY_INV: Self = Fr([
+        0x7239_4277_9d18_157e,
+        0xec9d_51f8_fd39_9d5d,
+        0x6117_635d_49d5_387f,
+        0x01b7_7519_9c22_9cd5,
+    ]);
+    // `5^(2^28)`.
+    const DELTA: Self = Fr([
+        0xa145_8af6_83d2_8e01,
+        0x3f54_4c14_cc40_ac6b,
+        0x21f1_ec72_be9f_9a26,
+        0x0ba4_9465_f79c_e3ca,
+    ]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn test_arithmetic() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let minus_one = -Fr::ONE;
+        assert_eq!(Fr::ONE, minus_one.square());
+        assert_eq!(Fr::ONE, Fr::TWO_INV.double());
+        assert_eq!(Fr::ONE, Fr::ROOT_OF_UNITY * Fr::ROOT_OF_UNITY_INV);
+        assert_eq!(Fr::ONE, Fr::ROOT_OF_UNITY.pow_vartime([1 << 28]));
+        assert_eq!(minus_one, Fr::ROOT_OF_UNITY.pow_vartime([1 << 27]));
+        assert_eq!(
+            Fr::DELTA,
+            Fr::MULTIPLICATIVE_GENERATOR.pow_vartime([1 << 28])
+        );
+
+        for _ in 0..100 {
+            let (a, b) = (Fr::random(&mut rng), Fr::random(&mut rng));
+            assert_eq!(a, a + b - b);
+            assert_eq!(a * b, b * a);
+            assert_eq!(a * (b + Fr::ONE), a * b + a);
+            if a != Fr::ZERO {
+                assert_eq!(Fr::ONE, a * a.invert().unwrap());
+            }
+            assert_eq!(a.square(), a.square().sqrt().unwrap().square());
+            assert_eq!(a, Fr::from_repr(a.to_repr()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_round_constants() {
+        // The first round constant of the reference Poseidon instance `poseidonperm_x5_254_3` over BN254, which
+        // circomlib also uses, generated with the Grain LFSR for `t = 3`, `R_F = 8` and `R_P = 57`.
+        let constants = crate::round_constants::generate_constants::<Fr>(1, 0, 254, 3, 8, 57);
+        let expected = "0x0ee9a592ba9a9518d05986d656f40c2114c4993c11bb29938d21d47304cd8e6e";
+        assert_eq!(expected, format!("{:?}", constants[0]));
+    }
+}
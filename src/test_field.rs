@@ -0,0 +1,341 @@
+//! A prime field for tests of field-dependent behavior which the BLS12-381 and Pasta fields cannot exercise.
+//!
+//! The modulus `p = 2^255 - 46545` is a safe prime (`(p - 1) / 2` is prime), so that `gcd(3, p - 1) = 1` and `x^3` is
+//! the default S-box, whereas the BLS12-381 and Pasta fields use `x^5`. Elements are stored as canonical little-endian
+//! limbs and arithmetic favors simplicity over speed.
+use core::fmt;
+use core::iter::{Product, Sum};
+use core::ops::{Add, AddAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+use ff::{Field, PrimeField};
+use rand::RngCore;
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq, CtOption};
+
+/// `2^255 - p`, so that `2^255 = C (mod p)`.
+const C: u64 = 46545;
+
+const MODULUS: [u64; 4] = [
+    0xffff_ffff_ffff_4a2f,
+    0xffff_ffff_ffff_ffff,
+    0xffff_ffff_ffff_ffff,
+    0x7fff_ffff_ffff_ffff,
+];
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Fp([u64; 4]);
+
+impl fmt::Debug for Fp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x")?;
+        self.0
+            .iter()
+            .rev()
+            .try_for_each(|limb| write!(f, "{limb:016x}"))
+    }
+}
+
+/// Returns `a - b` and whether it borrowed.
+fn sub_limbs(a: &[u64; 4], b: &[u64; 4]) -> ([u64; 4], bool) {
+    let mut result = [0; 4];
+    let mut borrow = false;
+    for i in 0..4 {
+        let (d, b1) = a[i].overflowing_sub(b[i]);
+        let (d, b2) = d.overflowing_sub(u64::from(borrow));
+        result[i] = d;
+        borrow = b1 || b2;
+    }
+    (result, borrow)
+}
+
+/// Reduces `limbs`, which must be less than `2^256 + p`, modulo `p`.
+fn reduce_once(limbs: [u64; 4], carry: bool) -> Fp {
+    let (reduced, borrow) = sub_limbs(&limbs, &MODULUS);
+    if carry || !borrow {
+        Fp(reduced)
+    } else {
+        Fp(limbs)
+    }
+}
+
+/// Reduces a 512-bit little-endian integer modulo `p`, by folding the bits above 255 back in as multiples of `C`.
+fn reduce_wide(mut x: [u64; 8]) -> Fp {
+    loop {
+        let hi: [u64; 5] =
+            core::array::from_fn(|i| (x[3 + i] >> 63) | (x.get(4 + i).unwrap_or(&0) << 1));
+        if hi == [0; 5] {
+            break;
+        }
+
+        let mut folded = [0; 8];
+        folded[..4].copy_from_slice(&x[..4]);
+        folded[3] &= u64::MAX >> 1;
+        let mut carry = 0u128;
+        for (i, limb) in folded.iter_mut().enumerate() {
+            let product = hi.get(i).map_or(0, |h| u128::from(*h) * u128::from(C));
+            let sum = u128::from(*limb) + product + carry;
+            *limb = sum as u64;
+            carry = sum >> 64;
+        }
+        x = folded;
+    }
+    reduce_once([x[0], x[1], x[2], x[3]], false)
+}
+
+impl Fp {
+    fn add_impl(&self, other: &Self) -> Self {
+        let mut result = [0; 4];
+        let mut carry = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (s, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (s, c2) = s.overflowing_add(u64::from(carry));
+            *limb = s;
+            carry = c1 || c2;
+        }
+        reduce_once(result, carry)
+    }
+
+    fn sub_impl(&self, other: &Self) -> Self {
+        let (diff, borrow) = sub_limbs(&self.0, &other.0);
+        if borrow {
+            // Adding `p` wraps around past `2^256`, giving the canonical result.
+            let mut result = [0; 4];
+            let mut carry = false;
+            for (i, limb) in result.iter_mut().enumerate() {
+                let (s, c1) = diff[i].overflowing_add(MODULUS[i]);
+                let (s, c2) = s.overflowing_add(u64::from(carry));
+                *limb = s;
+                carry = c1 || c2;
+            }
+            Fp(result)
+        } else {
+            Fp(diff)
+        }
+    }
+
+    fn mul_impl(&self, other: &Self) -> Self {
+        let mut wide = [0u64; 8];
+        for i in 0..4 {
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let t = u128::from(wide[i + j])
+                    + u128::from(self.0[i]) * u128::from(other.0[j])
+                    + carry;
+                wide[i + j] = t as u64;
+                carry = t >> 64;
+            }
+            wide[i + 4] = carry as u64;
+        }
+        reduce_wide(wide)
+    }
+}
+
+impl ConditionallySelectable for Fp {
+    fn conditional_select(a: &Self, b: &Self, choice: Choice) -> Self {
+        Fp(core::array::from_fn(|i| {
+            u64::conditional_select(&a.0[i], &b.0[i], choice)
+        }))
+    }
+}
+
+impl ConstantTimeEq for Fp {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0
+            .iter()
+            .zip(&other.0)
+            .fold(Choice::from(1), |acc, (a, b)| acc & a.ct_eq(b))
+    }
+}
+
+impl Neg for Fp {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::ZERO.sub_impl(&self)
+    }
+}
+
+macro_rules! impl_binop {
+    ($trait:ident, $method:ident, $assign_trait:ident, $assign_method:ident, $impl:ident) => {
+        impl $trait for Fp {
+            type Output = Self;
+
+            fn $method(self, other: Self) -> Self {
+                self.$impl(&other)
+            }
+        }
+
+        impl<'a> $trait<&'a Fp> for Fp {
+            type Output = Self;
+
+            fn $method(self, other: &'a Self) -> Self {
+                self.$impl(other)
+            }
+        }
+
+        impl $assign_trait for Fp {
+            fn $assign_method(&mut self, other: Self) {
+                *self = self.$impl(&other);
+            }
+        }
+
+        impl<'a> $assign_trait<&'a Fp> for Fp {
+            fn $assign_method(&mut self, other: &'a Self) {
+                *self = self.$impl(other);
+            }
+        }
+    };
+}
+
+impl_binop!(Add, add, AddAssign, add_assign, add_impl);
+impl_binop!(Sub, sub, SubAssign, sub_assign, sub_impl);
+impl_binop!(Mul, mul, MulAssign, mul_assign, mul_impl);
+
+impl Sum for Fp {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Fp> for Fp {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, Add::add)
+    }
+}
+
+impl Product for Fp {
+    fn product<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ONE, Mul::mul)
+    }
+}
+
+impl<'a> Product<&'a Fp> for Fp {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::ONE, Mul::mul)
+    }
+}
+
+impl From<u64> for Fp {
+    fn from(value: u64) -> Self {
+        Fp([value, 0, 0, 0])
+    }
+}
+
+impl Field for Fp {
+    const ZERO: Self = Fp([0; 4]);
+    const ONE: Self = Fp([1, 0, 0, 0]);
+
+    fn random(mut rng: impl RngCore) -> Self {
+        loop {
+            let mut limbs = [0; 4];
+            limbs.iter_mut().for_each(|limb| *limb = rng.next_u64());
+            limbs[3] &= u64::MAX >> 1;
+            if sub_limbs(&limbs, &MODULUS).1 {
+                return Fp(limbs);
+            }
+        }
+    }
+
+    fn square(&self) -> Self {
+        self.mul_impl(self)
+    }
+
+    fn double(&self) -> Self {
+        self.add_impl(self)
+    }
+
+    fn invert(&self) -> CtOption<Self> {
+        let (p_minus_two, _) = sub_limbs(&MODULUS, &[2, 0, 0, 0]);
+        CtOption::new(self.pow_vartime(p_minus_two), !self.is_zero())
+    }
+
+    fn sqrt(&self) -> CtOption<Self> {
+        // p = 3 (mod 4), so a square root of a square `x` is `x^((p + 1) / 4)`.
+        let p_plus_one_over_four = [
+            0xffff_ffff_ffff_d28c,
+            0xffff_ffff_ffff_ffff,
+            0xffff_ffff_ffff_ffff,
+            0x1fff_ffff_ffff_ffff,
+        ];
+        let root = self.pow_vartime(p_plus_one_over_four);
+        CtOption::new(root, root.square().ct_eq(self))
+    }
+
+    fn sqrt_ratio(num: &Self, div: &Self) -> (Choice, Self) {
+        ff::helpers::sqrt_ratio_generic(num, div)
+    }
+}
+
+impl PrimeField for Fp {
+    type Repr = [u8; 32];
+
+    fn from_repr(repr: Self::Repr) -> CtOption<Self> {
+        let limbs: [u64; 4] = core::array::from_fn(|i| {
+            u64::from_le_bytes(repr[8 * i..8 * (i + 1)].try_into().unwrap())
+        });
+        let (_, borrow) = sub_limbs(&limbs, &MODULUS);
+        CtOption::new(Fp(limbs), Choice::from(u8::from(borrow)))
+    }
+
+    fn to_repr(&self) -> Self::Repr {
+        let mut repr = [0; 32];
+        for (chunk, limb) in repr.chunks_exact_mut(8).zip(&self.0) {
+            chunk.copy_from_slice(&limb.to_le_bytes());
+        }
+        repr
+    }
+
+    fn is_odd(&self) -> Choice {
+        Choice::from((self.0[0] & 1) as u8)
+    }
+
+    const MODULUS: &'static str =
+        "0x7fffffffffffffffffffffffffffffffffffffffffffffffffffffffffff4a2f";
+    const NUM_BITS: u32 = 255;
+    const CAPACITY: u32 = 254;
+    const TWO_INV: Self = Fp([
+        0xffff_ffff_ffff_a518,
+        0xffff_ffff_ffff_ffff,
+        0xffff_ffff_ffff_ffff,
+        0x3fff_ffff_ffff_ffff,
+    ]);
+    // 5 generates the multiplicative group, since it is neither a square nor of order 2.
+    const MULTIPLICATIVE_GENERATOR: Self = Fp([5, 0, 0, 0]);
+    const S: u32 = 1;
+    // The square roots of unity are 1 and -1 = p - 1.
+    const ROOT_OF_UNITY: Self = Fp([
+        0xffff_ffff_ffff_4a2e,
+        0xffff_ffff_ffff_ffff,
+        0xffff_ffff_ffff_ffff,
+        0x7fff_ffff_ffff_ffff,
+    ]);
+    const ROOT_OF_UNITY_INV: Self = Self::ROOT_OF_UNITY;
+    const DELTA: Self = Fp([25, 0, 0, 0]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn test_arithmetic() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let minus_one = -Fp::ONE;
+        assert_eq!(Fp::ROOT_OF_UNITY, minus_one);
+        assert_eq!(Fp::ONE, minus_one.square());
+        assert_eq!(Fp::ONE, Fp::TWO_INV.double());
+        assert_eq!(Fp::from(C), Fp::from(2).pow_vartime([255]));
+
+        for _ in 0..100 {
+            let (a, b) = (Fp::random(&mut rng), Fp::random(&mut rng));
+            assert_eq!(a, a + b - b);
+            assert_eq!(a * b, b * a);
+            assert_eq!(a * (b + Fp::ONE), a * b + a);
+            if a != Fp::ZERO {
+                assert_eq!(Fp::ONE, a * a.invert().unwrap());
+            }
+            assert_eq!(a.square(), a.square().sqrt().unwrap().square());
+            assert_eq!(a, Fp::from_repr(a.to_repr()).unwrap());
+        }
+    }
+}
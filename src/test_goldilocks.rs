@@ -0,0 +1,233 @@
+//! The Goldilocks field, for tests of behavior which depends on a field too small to hold a 64-bit integer.
+//!
+//! The modulus is `p = 2^64 - 2^32 + 1`, so `F::CAPACITY` is 63. Elements are stored as their canonical value and
+//! arithmetic is done on `u128`s, favoring simplicity over speed.
+use core::fmt;
+use core::iter::{Product, Sum};
+use core::ops::{Add, AddAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+use ff::{Field, FieldBits, PrimeField, PrimeFieldBits};
+use rand::RngCore;
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq, CtOption};
+
+const MODULUS: u64 = 0xffff_ffff_0000_0001;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Fp(u64);
+
+impl fmt::Debug for Fp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:016x}", self.0)
+    }
+}
+
+impl Fp {
+    fn reduce(x: u128) -> Self {
+        Fp((x % u128::from(MODULUS)) as u64)
+    }
+
+    fn add_impl(&self, other: &Self) -> Self {
+        Self::reduce(u128::from(self.0) + u128::from(other.0))
+    }
+
+    fn sub_impl(&self, other: &Self) -> Self {
+        Self::reduce(u128::from(self.0) + u128::from(MODULUS) - u128::from(other.0))
+    }
+
+    fn mul_impl(&self, other: &Self) -> Self {
+        Self::reduce(u128::from(self.0) * u128::from(other.0))
+    }
+}
+
+impl ConditionallySelectable for Fp {
+    fn conditional_select(a: &Self, b: &Self, choice: Choice) -> Self {
+        Fp(u64::conditional_select(&a.0, &b.0, choice))
+    }
+}
+
+impl ConstantTimeEq for Fp {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+impl Neg for Fp {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::ZERO.sub_impl(&self)
+    }
+}
+
+macro_rules! impl_binop {
+    ($trait:ident, $method:ident, $assign_trait:ident, $assign_method:ident, $impl:ident) => {
+        impl $trait for Fp {
+            type Output = Self;
+
+            fn $method(self, other: Self) -> Self {
+                self.$impl(&other)
+            }
+        }
+
+        impl<'a> $trait<&'a Fp> for Fp {
+            type Output = Self;
+
+            fn $method(self, other: &'a Self) -> Self {
+                self.$impl(other)
+            }
+        }
+
+        impl $assign_trait for Fp {
+            fn $assign_method(&mut self, other: Self) {
+                *self = self.$impl(&other);
+            }
+        }
+
+        impl<'a> $assign_trait<&'a Fp> for Fp {
+            fn $assign_method(&mut self, other: &'a Self) {
+                *self = self.$impl(other);
+            }
+        }
+    };
+}
+
+impl_binop!(Add, add, AddAssign, add_assign, add_impl);
+impl_binop!(Sub, sub, SubAssign, sub_assign, sub_impl);
+impl_binop!(Mul, mul, MulAssign, mul_assign, mul_impl);
+
+impl Sum for Fp {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Fp> for Fp {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, Add::add)
+    }
+}
+
+impl Product for Fp {
+    fn product<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ONE, Mul::mul)
+    }
+}
+
+impl<'a> Product<&'a Fp> for Fp {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::ONE, Mul::mul)
+    }
+}
+
+impl From<u64> for Fp {
+    fn from(value: u64) -> Self {
+        Self::reduce(u128::from(value))
+    }
+}
+
+impl Field for Fp {
+    const ZERO: Self = Fp(0);
+    const ONE: Self = Fp(1);
+
+    fn random(mut rng: impl RngCore) -> Self {
+        loop {
+            let x = rng.next_u64();
+            if x < MODULUS {
+                return Fp(x);
+            }
+        }
+    }
+
+    fn square(&self) -> Self {
+        self.mul_impl(self)
+    }
+
+    fn double(&self) -> Self {
+        self.add_impl(self)
+    }
+
+    fn invert(&self) -> CtOption<Self> {
+        CtOption::new(self.pow_vartime([MODULUS - 2]), !self.is_zero())
+    }
+
+    fn sqrt(&self) -> CtOption<Self> {
+        // `(t - 1) / 2`, where `p - 1 = 2^32 * t` with `t = 2^32 - 1`.
+        ff::helpers::sqrt_tonelli_shanks(self, [0x7fff_ffff])
+    }
+
+    fn sqrt_ratio(num: &Self, div: &Self) -> (Choice, Self) {
+        ff::helpers::sqrt_ratio_generic(num, div)
+    }
+}
+
+impl PrimeField for Fp {
+    type Repr = [u8; 8];
+
+    fn from_repr(repr: Self::Repr) -> CtOption<Self> {
+        let x = u64::from_le_bytes(repr);
+        CtOption::new(Fp(x), Choice::from(u8::from(x < MODULUS)))
+    }
+
+    fn to_repr(&self) -> Self::Repr {
+        self.0.to_le_bytes()
+    }
+
+    fn is_odd(&self) -> Choice {
+        Choice::from((self.0 & 1) as u8)
+    }
+
+    const MODULUS: &'static str = "0xffffffff00000001";
+    const NUM_BITS: u32 = 64;
+    const CAPACITY: u32 = 63;
+    const TWO_INV: Self = Fp(0x7fff_ffff_8000_0001);
+    const MULTIPLICATIVE_GENERATOR: Self = Fp(7);
+    const S: u32 = 32;
+    // `7^t`, which has order `2^32`.
+    const ROOT_OF_UNITY: Self = Fp(0x1856_29dc_da58_878c);
+    const ROOT_OF_UNITY_INV: Self = Fp(0x76b6_b635_b6fc_8719);
+    // `7^(2^32)`.
+    const DELTA: Self = Fp(0xaa5b_2509_f86b_b4d4);
+}
+
+impl PrimeFieldBits for Fp {
+    type ReprBits = [u64; 1];
+
+    fn to_le_bits(&self) -> FieldBits<Self::ReprBits> {
+        FieldBits::new([self.0])
+    }
+
+    fn char_le_bits() -> FieldBits<Self::ReprBits> {
+        FieldBits::new([MODULUS])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn test_arithmetic() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        assert_eq!(Fp::ONE, Fp::TWO_INV.double());
+        assert_eq!(Fp::ONE, Fp::ROOT_OF_UNITY * Fp::ROOT_OF_UNITY_INV);
+        assert_eq!(Fp::ONE, Fp::ROOT_OF_UNITY.pow_vartime([1 << 32]));
+        assert_eq!(-Fp::ONE, Fp::ROOT_OF_UNITY.pow_vartime([1 << 31]));
+        assert_eq!(
+            Fp::DELTA,
+            Fp::MULTIPLICATIVE_GENERATOR.pow_vartime([1 << 32])
+        );
+
+        for _ in 0..100 {
+            let (a, b) = (Fp::random(&mut rng), Fp::random(&mut rng));
+            assert_eq!(a, a + b - b);
+            assert_eq!(a * b, b * a);
+            assert_eq!(a * (b + Fp::ONE), a * b + a);
+            if a != Fp::ZERO {
+                assert_eq!(Fp::ONE, a * a.invert().unwrap());
+            }
+            assert_eq!(a.square(), a.square().sqrt().unwrap().square());
+            assert_eq!(a, Fp::from_repr(a.to_repr()).unwrap());
+        }
+    }
+}
@@ -0,0 +1,96 @@
+#[cfg(any(feature = "cuda", feature = "opencl"))]
+use crate::batch_hasher::Batcher;
+use crate::error::Error;
+use crate::poseidon::{Arity, Poseidon, PoseidonConstants, SimplePoseidonBatchHasher};
+use crate::{BatchHasher, BatcherBackend, NeptuneField};
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(any(feature = "cuda", feature = "opencl"))]
+use ec_gpu_gen::rust_gpu_tools::Device;
+use generic_array::GenericArray;
+
+/// The first disagreement found by [`check_backend_consistency`].
+#[derive(Debug)]
+pub enum BackendMismatch {
+    /// `backend` hashed the preimage at `index` differently from [`Poseidon::hash`].
+    Digest {
+        index: usize,
+        backend: BatcherBackend,
+    },
+    /// A batcher could not be constructed, or failed to hash.
+    Failed(Error),
+}
+
+impl fmt::Display for BackendMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendMismatch::Digest { index, backend } => {
+                write!(f, "{backend:?} hashed preimage {index} incorrectly.")
+            }
+            BackendMismatch::Failed(e) => write!(f, "Backend failed: {e}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BackendMismatch {}
+
+/// Hashes `preimages` with the standard-strength Merkle tree constants on every backend available: the CPU batch
+/// hasher and, when the `cuda` or `opencl` feature is enabled, a GPU [`Batcher`](crate::batch_hasher::Batcher) for
+/// each device found, using the framework `ec-gpu-gen` selects for it. Each backend's digests are compared with those
+/// of [`Poseidon::hash`], and so, transitively, with each other's. Returns the first mismatching preimage and the
+/// backend which hashed it, or the first error, e.g. if the GPU kernels were not compiled for the arity `A`.
+///
+/// This is meant for downstream CI, to check that the backends of the machine at hand agree on a corpus of preimages
+/// before relying on them.
+pub fn check_backend_consistency<F, A>(
+    preimages: &[GenericArray<F, A>],
+) -> Result<(), BackendMismatch>
+where
+    F: NeptuneField,
+    A: Arity<F>,
+{
+    let constants = PoseidonConstants::<F, A>::new();
+    let expected: Vec<F> = preimages
+        .iter()
+        .map(|preimage| Poseidon::new_with_preimage(preimage, &constants).hash())
+        .collect();
+    let check = |backend: BatcherBackend, hashes: Vec<F>| match (0..expected.len())
+        .find(|i| hashes.get(*i) != Some(&expected[*i]))
+    {
+        Some(index) => Err(BackendMismatch::Digest { index, backend }),
+        None => Ok(()),
+    };
+
+    let max_batch_size = preimages.len().max(1);
+    let mut cpu = SimplePoseidonBatchHasher::<F, A>::new(max_batch_size);
+    check(
+        BatcherBackend::Cpu,
+        cpu.hash(preimages).map_err(BackendMismatch::Failed)?,
+    )?;
+
+    #[cfg(any(feature = "cuda", feature = "opencl"))]
+    for device in Device::all() {
+        let mut batcher =
+            Batcher::<F, A>::new(device, max_batch_size).map_err(BackendMismatch::Failed)?;
+        let hashes = batcher.hash(preimages).map_err(BackendMismatch::Failed)?;
+        check(batcher.backend(), hashes)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blstrs::Scalar as Fr;
+    use generic_array::{sequence::GenericSequence, typenum::U2};
+
+    #[test]
+    fn test_check_backend_consistency() {
+        let preimages = (0..10u64)
+            .map(|i| GenericArray::<Fr, U2>::generate(|j| Fr::from(i * 2 + j as u64)))
+            .collect::<Vec<_>>();
+        check_backend_consistency(&preimages).unwrap();
+        check_backend_consistency::<Fr, U2>(&[]).unwrap();
+    }
+}